@@ -1,16 +1,32 @@
 use codespan_reporting::term::termcolor::ColorChoice;
 use failure::{Error, ResultExt, SyncFailure};
 use mdbook::{renderer::RenderContext, MDBook};
+use mdbook_linkcheck::ExitCode;
 use std::{io, path::PathBuf};
 use structopt::StructOpt;
 
-fn main() -> Result<(), Error> {
+fn main() {
     env_logger::init();
     let args = Args::from_args();
 
+    let code = match run(&args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            for cause in e.iter_causes() {
+                eprintln!("\tCaused by: {}", cause);
+            }
+            ExitCode::for_error(&e)
+        },
+    };
+
+    std::process::exit(code.code());
+}
+
+fn run(args: &Args) -> Result<ExitCode, Error> {
     // get a `RenderContext`, either from stdin (because we're used as a plugin)
     // or by instrumenting MDBook directly (in standalone mode).
-    let ctx: RenderContext = if args.standalone {
+    let mut ctx: RenderContext = if args.standalone {
         let md = MDBook::load(dunce::canonicalize(&args.root)?)
             .map_err(SyncFailure::new)?;
         let destination = md.build_dir_for("linkcheck");
@@ -20,6 +36,61 @@ fn main() -> Result<(), Error> {
             .context("Unable to parse RenderContext")?
     };
 
+    if let Some(ref json_output) = args.json_output {
+        // command-line arguments take precedence over the book's config
+        let json_output = std::env::current_dir()?.join(json_output);
+        ctx.config
+            .set("output.linkcheck.json-report", json_output)
+            .map_err(SyncFailure::new)?;
+    }
+
+    if let Some(ref sarif) = args.sarif {
+        let sarif = std::env::current_dir()?.join(sarif);
+        ctx.config
+            .set("output.linkcheck.sarif-report", sarif)
+            .map_err(SyncFailure::new)?;
+    }
+
+    if let Some(ref junit) = args.junit {
+        let junit = std::env::current_dir()?.join(junit);
+        ctx.config
+            .set("output.linkcheck.junit-report", junit)
+            .map_err(SyncFailure::new)?;
+    }
+
+    if args.no_cache {
+        ctx.config
+            .set("output.linkcheck.use-cache", false)
+            .map_err(SyncFailure::new)?;
+    }
+
+    if args.offline {
+        ctx.config
+            .set("output.linkcheck.offline", true)
+            .map_err(SyncFailure::new)?;
+    }
+
+    if args.quiet {
+        ctx.config
+            .set("output.linkcheck.show-progress", false)
+            .map_err(SyncFailure::new)?;
+        ctx.config
+            .set("output.linkcheck.print-summary", false)
+            .map_err(SyncFailure::new)?;
+    }
+
+    if args.list_links {
+        ctx.config
+            .set("output.linkcheck.list-links", true)
+            .map_err(SyncFailure::new)?;
+    }
+
+    if let Some(ref git_ref) = args.changed_since {
+        ctx.config
+            .set("output.linkcheck.changed-since", git_ref)
+            .map_err(SyncFailure::new)?;
+    }
+
     let cache_file = ctx.destination.join("cache.json");
     mdbook_linkcheck::run(&cache_file, args.colour, &ctx)
 }
@@ -47,6 +118,52 @@ struct Args {
         possible_values = &["always", "auto", "never"]
     )]
     colour: ColorChoice,
+    #[structopt(
+        long = "json-output",
+        help = "Write a JSON report of every link to this file",
+        parse(from_os_str)
+    )]
+    json_output: Option<PathBuf>,
+    #[structopt(
+        long = "sarif",
+        help = "Write a SARIF log of broken links to this file",
+        parse(from_os_str)
+    )]
+    sarif: Option<PathBuf>,
+    #[structopt(
+        long = "junit",
+        help = "Write a JUnit XML report to this file",
+        parse(from_os_str)
+    )]
+    junit: Option<PathBuf>,
+    #[structopt(
+        long = "no-cache",
+        help = "Check every link from scratch, ignoring any cached results"
+    )]
+    no_cache: bool,
+    #[structopt(
+        long = "offline",
+        help = "Only check local links, skipping every web link"
+    )]
+    offline: bool,
+    #[structopt(
+        long = "list-links",
+        alias = "dry-run",
+        help = "Print every link and how it would be checked, without checking \
+                (--dry-run does the same)"
+    )]
+    list_links: bool,
+    #[structopt(
+        long = "changed-since",
+        help = "Only check chapters which changed since this git ref"
+    )]
+    changed_since: Option<String>,
+    #[structopt(
+        short = "q",
+        long = "quiet",
+        help = "Don't show a progress indicator or the summary"
+    )]
+    quiet: bool,
 }
 
 fn parse_colour(raw: &str) -> Result<ColorChoice, Error> {
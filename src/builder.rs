@@ -0,0 +1,417 @@
+//! Constructing a [`Config`] in code (see [`Config::builder()`]).
+
+use crate::{
+    config::{BasicAuth, BearerToken, HttpHeader, RawBasicAuth},
+    BrokenLinkCount, Category, Config, DnsResolution, HashedGlob, HashedRegex,
+    HttpMethod, WarningPolicy, WebScheme,
+};
+use failure::Error;
+use std::{convert::TryFrom, path::PathBuf};
+
+/// A fluent way to construct a [`Config`], created by [`Config::builder()`].
+///
+/// Anything which isn't set keeps its [`Default`] value. Patterns, headers,
+/// and credentials are parsed as they're added, and the first problem is
+/// returned by [`ConfigBuilder::build()`].
+///
+/// ```rust
+/// use mdbook_linkcheck::{Config, WarningPolicy};
+///
+/// let cfg = Config::builder()
+///     .follow_web_links(true)
+///     .exclude(r"localhost:\d+")
+///     .add_http_header(r"api\.example\.com", "Accept: application/json")
+///     .warning_policy(WarningPolicy::Error)
+///     .build()
+///     .unwrap();
+///
+/// assert!(cfg.follow_web_links);
+/// assert!(cfg.should_skip("http://localhost:3000/"));
+/// ```
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    cfg: Config,
+    error: Option<Error>,
+}
+
+/// Generate a setter for a [`Config`] field. `Into<T>` accepts anything
+/// convertible to the field's type, and `Option<...>` sets the field to
+/// `Some(value)`.
+macro_rules! setter {
+    ($name:ident: Option<Into<$ty:ty>>) => {
+        #[doc = concat!("Set [`Config::", stringify!($name), "`].")]
+        pub fn $name(mut self, value: impl Into<$ty>) -> Self {
+            self.cfg.$name = Some(value.into());
+            self
+        }
+    };
+    ($name:ident: Option<$ty:ty>) => {
+        #[doc = concat!("Set [`Config::", stringify!($name), "`].")]
+        pub fn $name(mut self, value: $ty) -> Self {
+            self.cfg.$name = Some(value);
+            self
+        }
+    };
+    ($name:ident: Into<$ty:ty>) => {
+        #[doc = concat!("Set [`Config::", stringify!($name), "`].")]
+        pub fn $name(mut self, value: impl Into<$ty>) -> Self {
+            self.cfg.$name = value.into();
+            self
+        }
+    };
+    ($name:ident: $ty:ty) => {
+        #[doc = concat!("Set [`Config::", stringify!($name), "`].")]
+        pub fn $name(mut self, value: $ty) -> Self {
+            self.cfg.$name = value;
+            self
+        }
+    };
+}
+
+impl ConfigBuilder {
+    setter!(follow_web_links: bool);
+    setter!(offline: bool);
+    setter!(check_url_syntax: bool);
+    setter!(scheme_relative_default: WebScheme);
+    setter!(list_links: bool);
+    setter!(changed_since: Option<Into<String>>);
+    setter!(traverse_parent_directories: bool);
+    setter!(local_link_extensions: Vec<String>);
+    setter!(check_anchors: bool);
+    setter!(case_insensitive_fragments: bool);
+    setter!(detect_orphans: bool);
+    setter!(detect_unused_references: bool);
+    setter!(use_ignore_file: bool);
+    setter!(exclude_case_insensitive: bool);
+    setter!(exclude_interpolate_env: bool);
+    setter!(skip_loopback: bool);
+    setter!(user_agent: Into<String>);
+    setter!(cache_timeout: u64);
+    setter!(negative_cache_timeout: u64);
+    setter!(cache_file: Option<Into<PathBuf>>);
+    setter!(use_cache: bool);
+    setter!(request_timeout: u64);
+    setter!(dns_resolution: DnsResolution);
+    setter!(max_redirects: usize);
+    setter!(accepted_status_codes: Vec<u16>);
+    setter!(fallback_to_get: bool);
+    setter!(prefer_head: bool);
+    setter!(respect_robots_txt: bool);
+    setter!(max_retries: u32);
+    setter!(retry_backoff_ms: u64);
+    setter!(max_retry_after: u64);
+    setter!(max_concurrency: usize);
+    setter!(max_concurrency_per_host: usize);
+    setter!(requests_per_second: Option<u32>);
+    setter!(requests_per_host_per_second: Option<u32>);
+    setter!(proxy: Option<Into<String>>);
+    setter!(http_proxy: Option<Into<String>>);
+    setter!(https_proxy: Option<Into<String>>);
+    setter!(no_proxy: Vec<String>);
+    setter!(allow_insecure_certificates: bool);
+    setter!(client_cert_path: Option<Into<PathBuf>>);
+    setter!(client_key_path: Option<Into<PathBuf>>);
+    setter!(tls_ca_cert: Option<Into<PathBuf>>);
+    setter!(check_remote_fragments: bool);
+    setter!(max_body_size: u64);
+    setter!(warn_on_http: bool);
+    setter!(warn_on_insecure_http: bool);
+    setter!(warn_on_redirect: bool);
+    setter!(warn_on_temporary_redirect: bool);
+    setter!(check_mailto: bool);
+    setter!(check_mailto_dns: bool);
+    setter!(check_tel: bool);
+    setter!(warning_policy: WarningPolicy);
+    setter!(web_warning_policy: Option<WarningPolicy>);
+    setter!(local_warning_policy: Option<WarningPolicy>);
+    setter!(broken_link_policy: Option<WarningPolicy>);
+    setter!(fail_on_warning_count: Option<usize>);
+    setter!(group_by_url: bool);
+    setter!(broken_link_count: BrokenLinkCount);
+    setter!(print_summary: bool);
+    setter!(print_cache_stats: bool);
+    setter!(show_progress: Option<bool>);
+    setter!(json_report: Option<Into<PathBuf>>);
+    setter!(sarif_report: Option<Into<PathBuf>>);
+    setter!(junit_report: Option<Into<PathBuf>>);
+    setter!(link_graph: Option<Into<PathBuf>>);
+
+    /// Add a pattern to [`Config::exclude`].
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        if let Some(re) = self.regex("exclude", pattern) {
+            self.cfg.exclude.push(re);
+        }
+        self
+    }
+
+    /// Add a glob to [`Config::exclude_globs`].
+    pub fn exclude_glob(mut self, glob: &str) -> Self {
+        match HashedGlob::new(glob) {
+            Ok(glob) => self.cfg.exclude_globs.push(glob),
+            Err(e) => self.fail(failure::format_err!(
+                "Invalid `exclude-globs` pattern \"{}\": {}",
+                glob,
+                e
+            )),
+        }
+        self
+    }
+
+    /// Add a pattern to [`Config::include`].
+    pub fn include(mut self, pattern: &str) -> Self {
+        if let Some(re) = self.regex("include", pattern) {
+            self.cfg.include.push(re);
+        }
+        self
+    }
+
+    /// Add a pattern to [`Config::exclude_exceptions`].
+    pub fn exclude_exception(mut self, pattern: &str) -> Self {
+        if let Some(re) = self.regex("exclude-exceptions", pattern) {
+            self.cfg.exclude_exceptions.push(re);
+        }
+        self
+    }
+
+    /// Add a pattern to [`Config::insecure_hosts`].
+    pub fn insecure_host(mut self, pattern: &str) -> Self {
+        if let Some(re) = self.regex("insecure-hosts", pattern) {
+            self.cfg.insecure_hosts.push(re);
+        }
+        self
+    }
+
+    /// Skip links matching `link_pattern`, but only in files matching
+    /// `file_pattern` (see [`Config::exclude_in_files`]).
+    pub fn exclude_in_files(
+        mut self,
+        file_pattern: &str,
+        link_pattern: &str,
+    ) -> Self {
+        let file = self.regex("exclude-in-files", file_pattern);
+        let link = self.regex("exclude-in-files", link_pattern);
+        if let (Some(file), Some(link)) = (file, link) {
+            self.cfg.exclude_in_files.entry(file).or_default().push(link);
+        }
+        self
+    }
+
+    /// Use a particular [`HttpMethod`] for URLs matching a pattern (see
+    /// [`Config::http_method`]).
+    pub fn http_method_for(
+        mut self,
+        pattern: &str,
+        method: HttpMethod,
+    ) -> Self {
+        if let Some(re) = self.regex("http-method", pattern) {
+            self.cfg.http_method.insert(re, method);
+        }
+        self
+    }
+
+    /// Override the [`Config::cache_timeout`] for URLs matching a pattern
+    /// (see [`Config::cache_timeouts`]).
+    pub fn cache_timeout_for(mut self, pattern: &str, seconds: u64) -> Self {
+        if let Some(re) = self.regex("cache-timeouts", pattern) {
+            self.cfg.cache_timeouts.insert(re, seconds);
+        }
+        self
+    }
+
+    /// Accept extra status codes from URLs matching a pattern (see
+    /// [`Config::accepted_status_codes_by_url`]).
+    pub fn accepted_status_codes_for<I>(
+        mut self,
+        pattern: &str,
+        codes: I,
+    ) -> Self
+    where
+        I: IntoIterator<Item = u16>,
+    {
+        if let Some(re) = self.regex("accepted-status-codes-by-url", pattern) {
+            self.cfg
+                .accepted_status_codes_by_url
+                .entry(re)
+                .or_default()
+                .extend(codes);
+        }
+        self
+    }
+
+    /// Send an extra header (e.g. `"Accept: text/html"`) to URLs matching a
+    /// pattern (see [`Config::http_headers`]). Environment variables are
+    /// interpolated straight away, like they are when deserializing.
+    pub fn add_http_header(mut self, pattern: &str, header: &str) -> Self {
+        let re = self.regex("http-headers", pattern);
+        match (re, HttpHeader::try_from(header)) {
+            (Some(re), Ok(header)) => {
+                self.cfg.http_headers.entry(re).or_default().push(header)
+            },
+            (_, Err(e)) => self.fail(failure::err_msg(e)),
+            (None, _) => {},
+        }
+        self
+    }
+
+    /// Log into URLs matching a pattern (see [`Config::basic_auth`]).
+    pub fn basic_auth(
+        mut self,
+        pattern: &str,
+        username: &str,
+        password: &str,
+    ) -> Self {
+        let re = self.regex("basic-auth", pattern);
+        let auth = BasicAuth::try_from(RawBasicAuth::Fields {
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+        match (re, auth) {
+            (Some(re), Ok(auth)) => {
+                self.cfg.basic_auth.insert(re, auth);
+            },
+            (_, Err(e)) => self.fail(failure::err_msg(e)),
+            (None, _) => {},
+        }
+        self
+    }
+
+    /// Send a bearer token to URLs matching a pattern (see
+    /// [`Config::bearer_token`]).
+    pub fn bearer_token(mut self, pattern: &str, token: &str) -> Self {
+        let re = self.regex("bearer-token", pattern);
+        match (re, BearerToken::try_from(token.to_string())) {
+            (Some(re), Ok(token)) => {
+                self.cfg.bearer_token.insert(re, token);
+            },
+            (_, Err(e)) => self.fail(failure::err_msg(e)),
+            (None, _) => {},
+        }
+        self
+    }
+
+    /// Require the body of pages matching `url_pattern` to match
+    /// `body_pattern` (see [`Config::body_must_contain`]).
+    pub fn body_must_contain(
+        mut self,
+        url_pattern: &str,
+        body_pattern: &str,
+    ) -> Self {
+        let url = self.regex("body-must-contain", url_pattern);
+        let body = self.regex("body-must-contain", body_pattern);
+        if let (Some(url), Some(body)) = (url, body) {
+            self.cfg.body_must_contain.insert(url, body);
+        }
+        self
+    }
+
+    /// Require the body of pages matching `url_pattern` to *not* match
+    /// `body_pattern` (see [`Config::body_must_not_contain`]).
+    pub fn body_must_not_contain(
+        mut self,
+        url_pattern: &str,
+        body_pattern: &str,
+    ) -> Self {
+        let url = self.regex("body-must-not-contain", url_pattern);
+        let body = self.regex("body-must-not-contain", body_pattern);
+        if let (Some(url), Some(body)) = (url, body) {
+            self.cfg.body_must_not_contain.insert(url, body);
+        }
+        self
+    }
+
+    /// Override the [`WarningPolicy`] for a [`Category`] of problem (see
+    /// [`Config::warning_policies`]).
+    pub fn warning_policy_for(
+        mut self,
+        category: Category,
+        policy: WarningPolicy,
+    ) -> Self {
+        self.cfg.warning_policies.insert(category, policy);
+        self
+    }
+
+    /// Finish building the [`Config`], going through the same steps as
+    /// [`crate::get_config()`] (e.g. interpolating environment variables).
+    pub fn build(self) -> Result<Config, Error> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        let mut cfg = self.cfg;
+        cfg.finalize()?;
+
+        Ok(cfg)
+    }
+
+    fn regex(&mut self, field: &str, pattern: &str) -> Option<HashedRegex> {
+        match HashedRegex::new(pattern) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                self.fail(failure::format_err!(
+                    "Invalid `{}` pattern \"{}\": {}",
+                    field,
+                    pattern,
+                    e
+                ));
+                None
+            },
+        }
+    }
+
+    /// Remember the first problem, so it can be returned by
+    /// [`ConfigBuilder::build()`].
+    fn fail(&mut self, error: Error) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_first_problem_is_reported() {
+        let err = Config::builder()
+            .exclude("example\\.com")
+            .include("(unclosed")
+            .add_http_header("https", "no colon")
+            .build()
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.starts_with("Invalid `include` pattern \"(unclosed\""));
+    }
+
+    #[test]
+    fn headers_are_validated() {
+        let err = Config::builder()
+            .add_http_header("https", "no colon")
+            .build()
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("HTTP header must contain"), "{}", err);
+    }
+
+    #[test]
+    fn credentials_are_validated() {
+        let err = Config::builder()
+            .basic_auth("staging", "user:name", "password")
+            .build()
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("can't contain `:`"), "{}", err);
+    }
+
+    #[test]
+    fn the_exclude_patterns_are_compiled() {
+        let cfg = Config::builder().exclude("google\\.com").build().unwrap();
+
+        let compiled = cfg.compiled_exclude.is_match("https://google.com/");
+        assert_eq!(compiled, Some(true));
+        assert!(cfg.should_skip("https://google.com/"));
+    }
+}
@@ -2,6 +2,7 @@ use failure::Error;
 use serde_derive::{Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
+    fmt::{self, Display, Formatter},
     io::{Read, Write},
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -11,17 +12,34 @@ use std::{
 };
 
 /// A cache used to avoid unnecessary web requests.
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Cache {
+    /// The version of the cache's format, so we can tell how to read caches
+    /// written by older versions of `mdbook-linkcheck`.
+    #[serde(default = "first_version")]
+    version: u32,
     links: RwLock<BTreeMap<String, CacheEntry>>,
     #[serde(skip)]
     cache_hits: AtomicUsize,
     #[serde(skip)]
     cache_misses: AtomicUsize,
+    #[serde(skip)]
+    cache_expired: AtomicUsize,
 }
 
 impl Cache {
+    /// The current version of the cache's format. This must be bumped
+    /// whenever the meaning of a cache entry changes.
+    ///
+    /// - Version 1 only stored when a link was checked and whether it was
+    ///   successful
+    /// - Version 2 added the `ETag` and `Last-Modified` headers so stale
+    ///   entries can be revalidated using conditional requests
+    /// - Version 3 added the reason a link failed, so failures can be reused
+    ///   (see [`crate::Config::negative_cache_timeout`])
+    pub const VERSION: u32 = 3;
+
     /// Save the [`Cache`] as JSON.
     pub fn save<W: Write>(&self, writer: W) -> Result<(), Error> {
         serde_json::to_writer(writer, self)?;
@@ -29,22 +47,54 @@ impl Cache {
     }
 
     /// Load a [`Cache`] from some JSON.
+    ///
+    /// A cache written in a different format (see [`Cache::VERSION`]) is
+    /// discarded, so it gets rebuilt from scratch instead of producing
+    /// misleading results. The exception is version 2, whose entries are
+    /// still meaningful but never say why a link failed, so those failures
+    /// just get checked again.
     pub fn load<R: Read>(reader: R) -> Result<Cache, Error> {
-        serde_json::from_reader(reader).map_err(Error::from)
-    }
-
-    pub(crate) fn lookup(&self, url: &str) -> Option<CacheEntry> {
-        let links = self.links.read().expect("Lock was poisoned");
+        let mut cache: Cache = serde_json::from_reader(reader)?;
 
-        let entry = links.get(url).copied();
+        if cache.version == 2 {
+            log::debug!("Upgrading a version 2 cache");
+            cache.version = Cache::VERSION;
+        }
 
-        if entry.is_some() {
-            self.cache_hits.fetch_add(1, Ordering::SeqCst);
-        } else {
-            self.cache_misses.fetch_add(1, Ordering::SeqCst);
+        if cache.version != Cache::VERSION {
+            log::info!(
+                "Discarding a cache with version {} (expected version {})",
+                cache.version,
+                Cache::VERSION
+            );
+            return Ok(Cache::default());
         }
 
-        entry
+        Ok(cache)
+    }
+
+    /// Look up the entry for a URL, using `is_fresh` to decide whether it
+    /// can be used as-is. This updates the [`Cache::stats()`].
+    pub(crate) fn lookup<F>(&self, url: &str, is_fresh: F) -> Lookup
+    where
+        F: FnOnce(&CacheEntry) -> bool,
+    {
+        let (counter, lookup) = match self.get(url) {
+            Some(entry) if is_fresh(&entry) => {
+                (&self.cache_hits, Lookup::Hit(entry))
+            },
+            Some(entry) => (&self.cache_expired, Lookup::Expired(entry)),
+            None => (&self.cache_misses, Lookup::Miss),
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+
+        lookup
+    }
+
+    /// Get the entry for a URL without touching the counters.
+    pub(crate) fn get(&self, url: &str) -> Option<CacheEntry> {
+        let links = self.links.read().expect("Lock was poisoned");
+        links.get(url).cloned()
     }
 
     pub(crate) fn insert<S: Into<String>>(&self, url: S, entry: CacheEntry) {
@@ -54,27 +104,109 @@ impl Cache {
             .insert(url.into(), entry);
     }
 
-    /// Reset the [`Cache::cache_hits()`] and [`Cache::cache_misses()`]
-    /// counters.
+    /// Reset the [`Cache::cache_hits()`], [`Cache::cache_misses()`], and
+    /// [`Cache::cache_expired()`] counters.
     pub fn reset_counters(&self) {
         self.cache_hits.store(0, Ordering::SeqCst);
         self.cache_misses.store(0, Ordering::SeqCst);
+        self.cache_expired.store(0, Ordering::SeqCst);
     }
 
-    /// The number of times a lookup was successful.
+    /// The number of times a lookup found a fresh entry, so no request was
+    /// needed.
     pub fn cache_hits(&self) -> usize { self.cache_hits.load(Ordering::SeqCst) }
 
-    /// The number of times a lookup was unsuccessful.
+    /// The number of times a lookup found nothing.
     pub fn cache_misses(&self) -> usize {
         self.cache_misses.load(Ordering::SeqCst)
     }
+
+    /// The number of times a lookup found an entry which was too old to use
+    /// (see [`crate::Config::cache_timeout`]).
+    pub fn cache_expired(&self) -> usize {
+        self.cache_expired.load(Ordering::SeqCst)
+    }
+
+    /// A snapshot of the counters (see [`Config::print_cache_stats`]).
+    ///
+    /// [`Config::print_cache_stats`]: crate::Config::print_cache_stats
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.cache_hits(),
+            misses: self.cache_misses(),
+            expired: self.cache_expired(),
+        }
+    }
 }
 
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache {
+            version: Cache::VERSION,
+            links: RwLock::default(),
+            cache_hits: AtomicUsize::default(),
+            cache_misses: AtomicUsize::default(),
+            cache_expired: AtomicUsize::default(),
+        }
+    }
+}
+
+/// How often the [`Cache`] was useful during a run.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups which found a fresh entry.
+    pub hits: usize,
+    /// Lookups which found nothing.
+    pub misses: usize,
+    /// Lookups which found an entry that was too old to use.
+    pub expired: usize,
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cache: {} hits, {} misses, {} expired",
+            self.hits, self.misses, self.expired
+        )
+    }
+}
+
+/// What [`Cache::lookup()`] found.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Lookup {
+    /// A fresh entry, which can be used instead of sending a request.
+    Hit(CacheEntry),
+    /// An entry which is too old to use on its own, although its validators
+    /// can still be used for a conditional request.
+    Expired(CacheEntry),
+    /// Nothing was cached for the URL.
+    Miss,
+}
+
+fn first_version() -> u32 { 1 }
+
 /// An entry in the cache.
-#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct CacheEntry {
     pub unix_timestamp: u64,
     pub successful: bool,
+    /// The `ETag` header from the last successful response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// The `Last-Modified` header from the last successful response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// Why the link was broken, if it was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+    /// Where the link redirected to, if we were keeping track (see
+    /// [`crate::Config::warn_on_redirect`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirected_to: Option<String>,
+    /// Were all of those redirects permanent?
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub permanent_redirect: bool,
 }
 
 impl CacheEntry {
@@ -90,9 +222,16 @@ impl CacheEntry {
         CacheEntry {
             unix_timestamp: unix_timestamp.as_secs(),
             successful,
+            ..Default::default()
         }
     }
 
+    /// Does this entry have anything we can use to make a conditional
+    /// request?
+    pub fn can_revalidate(&self) -> bool {
+        self.successful && (self.etag.is_some() || self.last_modified.is_some())
+    }
+
     pub fn elapsed(&self) -> Duration {
         let ts =
             SystemTime::UNIX_EPOCH + Duration::from_secs(self.unix_timestamp);
@@ -100,3 +239,177 @@ impl CacheEntry {
         ts.elapsed().expect("Entry timestamp was in the future")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn caches_from_before_versioning_are_discarded() {
+        let src = r#"{"links":{"https://example.com/":{"unix_timestamp":1234,"successful":true}}}"#;
+
+        let cache = Cache::load(src.as_bytes()).unwrap();
+
+        assert_eq!(cache.version, Cache::VERSION);
+        assert!(cache.get("https://example.com/").is_none());
+    }
+
+    #[test]
+    fn caches_with_a_different_version_are_discarded() {
+        for version in &[1, Cache::VERSION + 1] {
+            let src = format!(
+                r#"{{"version":{},"links":{{"https://example.com/":{{"unix_timestamp":1234,"successful":true}}}}}}"#,
+                version
+            );
+
+            let cache = Cache::load(src.as_bytes()).unwrap();
+
+            assert!(cache.get("https://example.com/").is_none());
+        }
+    }
+
+    #[test]
+    fn entries_without_validators_can_be_loaded() {
+        let src = format!(
+            r#"{{"version":{},"links":{{"https://example.com/":{{"unix_timestamp":1234,"successful":true}}}}}}"#,
+            Cache::VERSION
+        );
+
+        let cache = Cache::load(src.as_bytes()).unwrap();
+
+        let entry = cache.get("https://example.com/").unwrap();
+        assert_eq!(
+            entry,
+            CacheEntry {
+                unix_timestamp: 1234,
+                successful: true,
+                etag: None,
+                last_modified: None,
+                failure: None,
+                redirected_to: None,
+                permanent_redirect: false,
+            }
+        );
+    }
+
+    #[test]
+    fn version_2_caches_are_upgraded() {
+        let src = r#"{"version":2,"links":{"https://example.com/":{"unix_timestamp":1234,"successful":true,"etag":"\"abc\""},"https://example.com/broken":{"unix_timestamp":1234,"successful":false}}}"#;
+
+        let cache = Cache::load(src.as_bytes()).unwrap();
+
+        assert_eq!(cache.version, Cache::VERSION);
+        let entry = cache.get("https://example.com/").unwrap();
+        assert!(entry.successful);
+        assert_eq!(entry.etag.as_deref(), Some("\"abc\""));
+        // old failures don't say what went wrong
+        let entry = cache.get("https://example.com/broken").unwrap();
+        assert!(!entry.successful);
+        assert_eq!(entry.failure, None);
+    }
+
+    #[test]
+    fn round_trip_a_failure() {
+        let cache = Cache::default();
+        let entry = CacheEntry {
+            failure: Some(String::from("Server responded with 404 Not Found")),
+            ..CacheEntry::new(SystemTime::now(), false)
+        };
+        cache.insert("https://example.com/", entry.clone());
+        let mut buffer = Vec::new();
+
+        cache.save(&mut buffer).unwrap();
+        let got = Cache::load(buffer.as_slice()).unwrap();
+
+        assert_eq!(got.get("https://example.com/"), Some(entry));
+    }
+
+    #[test]
+    fn round_trip_the_validators() {
+        let cache = Cache::default();
+        let entry = CacheEntry {
+            etag: Some(String::from(r#""abc123""#)),
+            last_modified: Some(String::from("Wed, 21 Oct 2015 07:28:00 GMT")),
+            ..CacheEntry::new(SystemTime::now(), true)
+        };
+        cache.insert("https://example.com/", entry.clone());
+        let mut buffer = Vec::new();
+
+        cache.save(&mut buffer).unwrap();
+        let got = Cache::load(buffer.as_slice()).unwrap();
+
+        assert_eq!(got.get("https://example.com/"), Some(entry));
+    }
+
+    fn prepopulated() -> Cache {
+        let cache = Cache::default();
+        let now = SystemTime::now();
+        let old = now - Duration::from_secs(60 * 60);
+        cache.insert("https://example.com/fresh", CacheEntry::new(now, true));
+        cache.insert("https://example.com/old", CacheEntry::new(old, true));
+        cache
+    }
+
+    fn is_fresh(entry: &CacheEntry) -> bool {
+        entry.elapsed() < Duration::from_secs(60)
+    }
+
+    #[test]
+    fn count_hits_misses_and_expired_entries() {
+        let cache = prepopulated();
+        let inputs = vec![
+            "https://example.com/fresh",
+            "https://example.com/old",
+            "https://example.com/missing",
+            "https://example.com/fresh",
+            "https://example.com/fresh",
+            "https://example.com/other",
+        ];
+
+        let got: Vec<_> = inputs
+            .into_iter()
+            .map(|url| match cache.lookup(url, is_fresh) {
+                Lookup::Hit(_) => "hit",
+                Lookup::Expired(_) => "expired",
+                Lookup::Miss => "miss",
+            })
+            .collect();
+
+        assert_eq!(got, ["hit", "expired", "miss", "hit", "hit", "miss"]);
+        let should_be = CacheStats {
+            hits: 3,
+            misses: 2,
+            expired: 1,
+        };
+        assert_eq!(cache.stats(), should_be);
+        assert_eq!(should_be.to_string(), "cache: 3 hits, 2 misses, 1 expired");
+
+        // peeking at entries doesn't count
+        let _ = cache.get("https://example.com/fresh");
+        assert_eq!(cache.stats(), should_be);
+
+        cache.reset_counters();
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn counters_are_accurate_across_threads() {
+        let cache = prepopulated();
+        let lookups = 2000;
+
+        // the same thread pool validate() uses for checking web links
+        (0..lookups).into_par_iter().for_each(|_| {
+            cache.lookup("https://example.com/fresh", is_fresh);
+            cache.lookup("https://example.com/old", is_fresh);
+            cache.lookup("https://example.com/missing", is_fresh);
+        });
+
+        let should_be = CacheStats {
+            hits: lookups,
+            misses: lookups,
+            expired: lookups,
+        };
+        assert_eq!(cache.stats(), should_be);
+    }
+}
@@ -0,0 +1,116 @@
+//! Only checking the chapters which changed since a git ref (see
+//! [`Config::changed_since`]).
+//!
+//! [`Config::changed_since`]: crate::Config::changed_since
+
+use codespan::{FileId, Files};
+use failure::{Error, ResultExt};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Ask `git` which files under `src_dir` are different from `git_ref`,
+/// including uncommitted changes and new files which haven't been added yet.
+///
+/// Paths are relative to `src_dir`. They're NUL-separated so names with
+/// spaces or non-ASCII characters aren't quoted by `git`.
+pub(crate) fn changed_files(
+    src_dir: &Path,
+    git_ref: &str,
+) -> Result<HashSet<PathBuf>, Error> {
+    // "--end-of-options" stops a ref like "--output=foo" being read as a flag
+    let diff_args = [
+        "diff",
+        "-z",
+        "--name-only",
+        "--relative",
+        "--end-of-options",
+        git_ref,
+        "--",
+    ];
+    let diff = git(src_dir, &diff_args).with_context(|_| {
+        format!("Unable to find the files changed since \"{}\"", git_ref)
+    })?;
+    let untracked_args = ["ls-files", "-z", "--others", "--exclude-standard"];
+    let untracked = git(src_dir, &untracked_args)
+        .context("Unable to find the untracked files")?;
+
+    Ok(diff
+        .split('\0')
+        .chain(untracked.split('\0'))
+        .filter(|name| !name.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, Error> {
+    log::debug!("Running `git {}` in \"{}\"", args.join(" "), dir.display());
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Unable to run git")?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(failure::err_msg(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+/// The chapters whose source file is one of the `changed` files.
+pub(crate) fn select_chapters(
+    chapters: &[FileId],
+    files: &Files,
+    changed: &HashSet<PathBuf>,
+) -> Vec<FileId> {
+    chapters
+        .iter()
+        .copied()
+        .filter(|&id| changed.contains(Path::new(files.name(id))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn find_changed_files_with_unusual_names() {
+        let root = std::env::temp_dir()
+            .join(concat!(env!("CARGO_PKG_NAME"), "-changed-files"));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let git = |args: &[&str]| {
+            let mut all_args =
+                vec!["-c", "user.name=test", "-c", "user.email=test@test"];
+            all_args.extend(args);
+            super::git(&root, &all_args).unwrap()
+        };
+        git(&["init", "-q"]);
+        fs::write(root.join("unchanged.md"), "").unwrap();
+        fs::write(root.join("chapter ü.md"), "").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "Initial commit"]);
+        fs::write(root.join("chapter ü.md"), "edited").unwrap();
+        fs::write(root.join("new\tchapter.md"), "").unwrap();
+
+        let got = changed_files(&root, "HEAD").unwrap();
+
+        let should_be: HashSet<_> = vec!["chapter ü.md", "new\tchapter.md"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(got, should_be);
+        // refs can't be mistaken for options
+        assert!(changed_files(&root, "--output=oops").is_err());
+        assert!(!root.join("oops").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+//! Syntax checks for `mailto:` and `tel:` links.
+//!
+//! There's no way to check an email address or phone number actually exists
+//! without contacting someone, so these only make sure the link is
+//! well-formed. The most we'll do is look up an email address's domain.
+
+use crate::{validate::Reason, Link};
+use percent_encoding::percent_decode_str;
+use std::{net::ToSocketAddrs, sync::mpsc, thread, time::Duration};
+
+/// The kinds of contact link we know how to check.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ContactScheme {
+    Mailto,
+    Tel,
+}
+
+impl ContactScheme {
+    /// Figure out whether a link is a `mailto:` or `tel:` link.
+    pub(crate) fn of(link: &Link) -> Option<ContactScheme> {
+        let uri = link.uri.to_string();
+        let (scheme, _) = split_scheme(&uri)?;
+
+        if scheme.eq_ignore_ascii_case("mailto") {
+            Some(ContactScheme::Mailto)
+        } else if scheme.eq_ignore_ascii_case("tel") {
+            Some(ContactScheme::Tel)
+        } else {
+            None
+        }
+    }
+
+    /// Make sure the link is syntactically valid.
+    pub(crate) fn check(self, link: &Link) -> Result<(), Reason> {
+        let uri = link.uri.to_string();
+        let target = split_scheme(&uri).map(|(_, rest)| rest).unwrap_or("");
+
+        match self {
+            ContactScheme::Mailto if !is_valid_mailto(target) => {
+                Err(Reason::MalformedEmailAddress(target.to_string()))
+            },
+            ContactScheme::Tel if !is_valid_tel(target) => {
+                Err(Reason::MalformedPhoneNumber(target.to_string()))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The domains of every recipient in a `mailto:` link.
+pub(crate) fn email_domains(link: &Link) -> Vec<String> {
+    let uri = link.uri.to_string();
+    let target = split_scheme(&uri).map(|(_, rest)| rest).unwrap_or("");
+
+    recipients(target)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|address| address.rfind('@').map(|ix| &address[ix + 1..]))
+        .map(|domain| domain.to_lowercase())
+        .collect()
+}
+
+/// Does this domain have an address? We can't do MX lookups with the
+/// standard library, but a domain which accepts email almost always has an
+/// `A` or `AAAA` record too.
+///
+/// The system resolver can't be cancelled, so the lookup happens on its own
+/// thread and we stop waiting for it (treating the domain as unresolvable)
+/// after `timeout`.
+pub(crate) fn domain_resolves(domain: &str, timeout: Duration) -> bool {
+    let (tx, rx) = mpsc::channel();
+    let host = domain.to_string();
+
+    thread::spawn(move || {
+        let resolves = match (host.as_str(), 25).to_socket_addrs() {
+            Ok(mut addresses) => addresses.next().is_some(),
+            Err(e) => {
+                log::debug!("Unable to resolve \"{}\": {}", host, e);
+                false
+            },
+        };
+        let _ = tx.send(resolves);
+    });
+
+    rx.recv_timeout(timeout).unwrap_or_else(|_| {
+        log::debug!("Timed out while resolving \"{}\"", domain);
+        false
+    })
+}
+
+/// `http::Uri` doesn't know about `mailto:` or `tel:`, so we need to pull the
+/// scheme out ourselves.
+fn split_scheme(uri: &str) -> Option<(&str, &str)> {
+    let colon = uri.find(':')?;
+    let scheme = &uri[..colon];
+
+    if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    Some((scheme, &uri[colon + 1..]))
+}
+
+/// Check a `mailto:` target (RFC 6068), e.g.
+/// `alice@example.com,bob@example.com?subject=Hello`.
+fn is_valid_mailto(target: &str) -> bool {
+    match recipients(target) {
+        Some(recipients) => {
+            !recipients.is_empty()
+                && recipients.iter().all(|address| is_plausible_address(address))
+        },
+        None => false,
+    }
+}
+
+/// Get every recipient from a `mailto:` target, including any in `to=` query
+/// parameters, or `None` if the query string is malformed.
+fn recipients(target: &str) -> Option<Vec<String>> {
+    let (to, query) = match target.find('?') {
+        Some(ix) => (&target[..ix], Some(&target[ix + 1..])),
+        None => (target, None),
+    };
+
+    let mut recipients: Vec<String> = Vec::new();
+    recipients.extend(split_addresses(to));
+
+    for pair in query.into_iter().flat_map(|q| q.split('&')) {
+        let mut parts = pair.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+
+        match parts.next() {
+            Some(value) if name.eq_ignore_ascii_case("to") => {
+                recipients.extend(split_addresses(value))
+            },
+            Some(_) if !name.is_empty() => {},
+            _ => return None,
+        }
+    }
+
+    Some(recipients)
+}
+
+fn split_addresses(raw: &str) -> impl Iterator<Item = String> + '_ {
+    raw.split(',')
+        .map(|address| percent_decode_str(address).decode_utf8_lossy())
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty())
+}
+
+/// Does this look like `local@domain`? Anything more precise tends to reject
+/// real addresses.
+fn is_plausible_address(address: &str) -> bool {
+    let at = match address.rfind('@') {
+        Some(ix) => ix,
+        None => return false,
+    };
+    let (local, domain) = (&address[..at], &address[at + 1..]);
+
+    !local.is_empty()
+        && !local.chars().any(|c| c.is_whitespace() || c == '@')
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+}
+
+/// Check a `tel:` target (RFC 3966), e.g. `+1-201-555-0123;ext=42`.
+///
+/// Global numbers start with a `+`, while local numbers must say where they
+/// are valid using a `phone-context` parameter.
+fn is_valid_tel(target: &str) -> bool {
+    let target = percent_decode_str(target).decode_utf8_lossy();
+    let mut parts = target.split(';');
+    let number = parts.next().unwrap_or("");
+    let mut has_context = false;
+
+    for param in parts {
+        let mut bits = param.splitn(2, '=');
+        let name = bits.next().unwrap_or("");
+        let value = bits.next();
+
+        if name.is_empty()
+            || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            || value == Some("")
+        {
+            return false;
+        }
+
+        if name.eq_ignore_ascii_case("phone-context") {
+            has_context = true;
+        }
+    }
+
+    if let Some(global) = number.strip_prefix('+') {
+        is_phone_number(global, |c| c.is_ascii_digit())
+    } else {
+        has_context
+            && is_phone_number(number, |c| {
+                c.is_ascii_hexdigit() || c == '*' || c == '#'
+            })
+    }
+}
+
+fn is_phone_number<F>(number: &str, is_digit: F) -> bool
+where
+    F: Fn(char) -> bool,
+{
+    let is_visual_separator = |c| ['-', '.', '(', ')'].contains(&c);
+
+    number.chars().any(&is_digit)
+        && number.chars().all(|c| is_digit(c) || is_visual_separator(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan::Files;
+
+    fn check(raw: &str) -> Option<Result<(), Reason>> {
+        let mut files = Files::new();
+        let id = files.add("index.md", "");
+        let link = Link::parse(raw, 0..raw.len(), id).unwrap();
+
+        ContactScheme::of(&link).map(|scheme| scheme.check(&link))
+    }
+
+    #[test]
+    fn only_contact_links_are_recognised() {
+        assert!(check("mailto:alice@example.com").is_some());
+        assert!(check("TEL:+1-201-555-0123").is_some());
+        assert!(check("./chapter_1.md").is_none());
+        assert!(check("https://example.com/").is_none());
+    }
+
+    #[test]
+    fn a_valid_email_address() {
+        assert!(check("mailto:alice@example.com").unwrap().is_ok());
+        assert!(check("mailto:alice@example.com,bob@example.org")
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn malformed_email_addresses() {
+        let inputs = vec![
+            "mailto:",
+            "mailto:alice",
+            "mailto:alice@",
+            "mailto:@example.com",
+            "mailto:alice@localhost",
+            "mailto:alice@example..com",
+            "mailto:alice@example.com,bob",
+        ];
+
+        for input in inputs {
+            match check(input) {
+                Some(Err(Reason::MalformedEmailAddress(_))) => {},
+                other => panic!("{} gave {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn email_addresses_with_query_parameters() {
+        let inputs = vec![
+            "mailto:alice@example.com?subject=Hello%20World",
+            "mailto:alice@example.com?subject=Hi&body=Nice%20book",
+            "mailto:?to=alice@example.com&subject=Hi",
+            "mailto:alice@example.com?cc=bob@example.com",
+        ];
+
+        for input in inputs {
+            assert!(check(input).unwrap().is_ok(), "{}", input);
+        }
+
+        assert!(check("mailto:?subject=Hi").unwrap().is_err());
+        assert!(check("mailto:alice@example.com?subject").unwrap().is_err());
+    }
+
+    #[test]
+    fn more_email_address_forms() {
+        let valid = vec![
+            "mailto:alice+books@mail.example.co.uk",
+            "mailto:first.last@example.com",
+            "mailto:alice@xn--bcher-kva.example",
+        ];
+        for input in valid {
+            assert!(check(input).unwrap().is_ok(), "{}", input);
+        }
+
+        let invalid = vec![
+            "mailto:alice@-example.com",
+            "mailto:alice@example-.com",
+            "mailto:alice@exa_mple.com",
+            "mailto:alice@.example.com",
+            "mailto:alice@example.com.",
+            "mailto:alice%20smith@example.com",
+        ];
+        for input in invalid {
+            assert!(check(input).unwrap().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn get_the_domains_to_look_up() {
+        let mut files = Files::new();
+        let id = files.add("index.md", "");
+        let raw = "mailto:a@Example.com,b@example.org?to=c@example.net";
+        let link = Link::parse(raw, 0..raw.len(), id).unwrap();
+
+        let got = email_domains(&link);
+
+        assert_eq!(got, vec!["example.com", "example.org", "example.net"]);
+    }
+
+    #[test]
+    fn look_up_a_domain() {
+        // IP addresses "resolve" without needing the network
+        assert!(domain_resolves("127.0.0.1", Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn phone_numbers() {
+        let valid = vec![
+            "tel:+1-201-555-0123",
+            "tel:+44(0)20.7946.0018",
+            "tel:+1-201-555-0123;ext=42",
+            "tel:7042;phone-context=example.com",
+        ];
+        for input in valid {
+            assert!(check(input).unwrap().is_ok(), "{}", input);
+        }
+
+        let invalid = vec![
+            "tel:",
+            "tel:+",
+            "tel:+1-CALL-ME",
+            "tel:555-0123",
+            "tel:+1-201-555-0123;ext=",
+        ];
+        for input in invalid {
+            match check(input) {
+                Some(Err(Reason::MalformedPhoneNumber(_))) => {},
+                other => panic!("{} gave {:?}", input, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! The graph of links between a book's chapters (see
+//! [`crate::Config::link_graph`]).
+
+use crate::Link;
+use codespan::{FileId, Files};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::{Component, Path},
+};
+
+/// Which chapters link to which, as written to the link graph.
+///
+/// Like the JSON report, fields are only ever added to this format so it can
+/// be safely deserialized by other tools. Paths are relative to the book's
+/// `src/` directory and always use `/` as a separator.
+///
+/// ```json
+/// {
+///   "chapters": ["chapter_1.md", "nested/index.md"],
+///   "links": [
+///     {
+///       "source": "chapter_1.md",
+///       "line": 3,
+///       "start": 42,
+///       "end": 71,
+///       "target": "nested/index.md",
+///       "anchor": "installation"
+///     }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LinkGraph {
+    /// Every chapter in the book, including ones nothing links to.
+    pub chapters: Vec<String>,
+    /// The links between chapters, in the order they were found.
+    pub links: Vec<LinkEdge>,
+}
+
+/// A link from one chapter to another (or to somewhere in the same chapter).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LinkEdge {
+    /// The file the link was found in.
+    pub source: String,
+    /// The (1-based) line the link starts on.
+    pub line: usize,
+    /// The byte offset the link starts at in the source file.
+    pub start: usize,
+    /// The byte offset the link ends at in the source file.
+    pub end: usize,
+    /// The file the link points to.
+    pub target: String,
+    /// The anchor the link points to, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<String>,
+}
+
+impl LinkGraph {
+    /// Work out which chapters link to which.
+    ///
+    /// Only links which resolve to a file inside `src_dir` are included, so
+    /// web links and broken links are skipped.
+    pub fn new(
+        links: &[Link],
+        chapters: &[FileId],
+        files: &Files,
+        src_dir: &Path,
+    ) -> LinkGraph {
+        let chapters = chapters
+            .iter()
+            .map(|&id| slash_path(Path::new(files.name(id))))
+            .collect();
+        let links = links
+            .iter()
+            .filter_map(|link| edge(link, files, src_dir))
+            .collect();
+
+        LinkGraph { chapters, links }
+    }
+}
+
+/// Find the chapters which no other chapter links to.
+///
+/// The first chapter is the book's landing page, so it's never an orphan.
+/// Links from a chapter to itself don't count.
+pub fn find_orphan_pages(
+    links: &[Link],
+    chapters: &[FileId],
+    files: &Files,
+    src_dir: &Path,
+) -> Vec<FileId> {
+    let graph = LinkGraph::new(links, chapters, files, src_dir);
+    let linked: HashSet<&str> = graph
+        .links
+        .iter()
+        .filter(|edge| edge.source != edge.target)
+        .map(|edge| edge.target.as_str())
+        .collect();
+
+    chapters
+        .iter()
+        .zip(&graph.chapters)
+        .skip(1)
+        .filter(|(_, name)| !linked.contains(name.as_str()))
+        .map(|(&id, _)| id)
+        .collect()
+}
+
+fn edge(link: &Link, files: &Files, src_dir: &Path) -> Option<LinkEdge> {
+    let source = Path::new(files.name(link.file));
+
+    if link.is_scheme_relative() {
+        return None;
+    }
+
+    let target = match link.uri.scheme_str() {
+        None if link.uri.path().is_empty() => slash_path(source),
+        None | Some("file") => {
+            let path = link.as_filesystem_path(src_dir, files);
+            let resolved =
+                crate::validate::validate_local_link(src_dir, &path, false)
+                    .ok()?;
+            slash_path(resolved.strip_prefix(src_dir).ok()?)
+        },
+        _ => return None,
+    };
+
+    let start = link.span.start();
+    let line = files
+        .location(link.file, start)
+        .map(|location| location.line.to_usize() + 1)
+        .unwrap_or_default();
+
+    Some(LinkEdge {
+        source: slash_path(source),
+        line,
+        start: start.to_usize(),
+        end: link.span.end().to_usize(),
+        target,
+        anchor: link.fragment.clone().filter(|anchor| !anchor.is_empty()),
+    })
+}
+
+/// A path relative to the `src/` directory, using `/` on every platform.
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graph_of_a_two_chapter_book() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let src_dir = dunce::canonicalize(src_dir).unwrap();
+        let mut files = Files::new();
+        let first = files.add(
+            "chapter_1.md",
+            "# Chapter 1\n\n[nested](./nested/index.md#heading)\n",
+        );
+        let second = files.add(
+            "nested/index.md",
+            "[back](../chapter_1.md) [top](#top)\n\n[web](https://example.com/) [missing](./missing.md)\n",
+        );
+        let chapters = vec![first, second];
+        let (links, _) = crate::links::extract(chapters.clone(), &files);
+
+        let got = LinkGraph::new(&links, &chapters, &files, &src_dir);
+
+        let should_be = LinkGraph {
+            chapters: vec![
+                String::from("chapter_1.md"),
+                String::from("nested/index.md"),
+            ],
+            links: vec![
+                LinkEdge {
+                    source: String::from("chapter_1.md"),
+                    line: 3,
+                    start: links[0].span.start().to_usize(),
+                    end: links[0].span.end().to_usize(),
+                    target: String::from("nested/index.md"),
+                    anchor: Some(String::from("heading")),
+                },
+                LinkEdge {
+                    source: String::from("nested/index.md"),
+                    line: 1,
+                    start: links[1].span.start().to_usize(),
+                    end: links[1].span.end().to_usize(),
+                    target: String::from("chapter_1.md"),
+                    anchor: None,
+                },
+                LinkEdge {
+                    source: String::from("nested/index.md"),
+                    line: 1,
+                    start: links[2].span.start().to_usize(),
+                    end: links[2].span.end().to_usize(),
+                    target: String::from("nested/index.md"),
+                    anchor: Some(String::from("top")),
+                },
+            ],
+        };
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn find_pages_nothing_links_to() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let src_dir = dunce::canonicalize(src_dir).unwrap();
+        let mut files = Files::new();
+        let first = files.add("chapter_1.md", "[nested](./nested/index.md)");
+        let nested = files.add("nested/index.md", "[self](./index.md#top)");
+        let sibling =
+            files.add("nested/sibling.md", "[first](../chapter_1.md)");
+        let chapters = vec![first, nested, sibling];
+        let (links, _) = crate::links::extract(chapters.clone(), &files);
+
+        let got = find_orphan_pages(&links, &chapters, &files, &src_dir);
+
+        // the first chapter is never an orphan, and linking to yourself
+        // doesn't count
+        assert_eq!(got, vec![sibling]);
+    }
+
+    #[test]
+    fn the_json_format_is_stable() {
+        let edge = LinkEdge {
+            source: String::from("chapter_1.md"),
+            line: 3,
+            start: 13,
+            end: 48,
+            target: String::from("nested/index.md"),
+            anchor: None,
+        };
+
+        let got = serde_json::to_value(&edge).unwrap();
+
+        let should_be = serde_json::json!({
+            "source": "chapter_1.md",
+            "line": 3,
+            "start": 13,
+            "end": 48,
+            "target": "nested/index.md",
+        });
+        assert_eq!(got, should_be);
+    }
+}
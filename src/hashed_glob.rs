@@ -0,0 +1,78 @@
+use globset::{Glob, GlobMatcher};
+use serde::{Serialize, Deserialize, Deserializer, de::Error};
+use std::{
+    hash::{Hash, Hasher},
+    str::FromStr
+};
+
+/// A wrapper around [`globset::Glob`] which implements **string repr based**
+/// [`Serialize`], [`Deserialize`], [`PartialEq`], [`Eq`], [`Hash`].
+///
+/// Globs are matched against the whole link, so `*.pdf` matches
+/// `https://example.com/manual.pdf` and `**/drafts/**` matches
+/// `https://example.com/blog/drafts/post.html`.
+///
+/// ```
+/// use mdbook_linkcheck::HashedGlob;
+///
+/// let glob = HashedGlob::new("*.pdf").unwrap();
+/// assert!(glob.is_match("https://example.com/manual.pdf"));
+/// assert!(!glob.is_match("https://example.com/manual.html"));
+/// ```
+#[derive(Serialize, Debug, Clone)]
+#[serde(transparent)]
+pub struct HashedGlob {
+    /// String representation.
+    pub string: String,
+
+    /// Compiled glob.
+    #[serde(skip_serializing)]
+    pub matcher: GlobMatcher
+}
+
+impl HashedGlob {
+    /// Create new [`HashedGlob`] instance.
+    pub fn new(s: &str) -> Result<Self, globset::Error> {
+        let string = s.to_string();
+        let matcher = Glob::new(s)?.compile_matcher();
+
+        Ok(HashedGlob { string, matcher })
+    }
+
+    /// Does the link match this glob?
+    pub fn is_match(&self, link: &str) -> bool {
+        self.matcher.is_match(link)
+    }
+}
+
+impl<'de> Deserialize<'de> for HashedGlob {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        let string = String::deserialize(deserializer)?;
+        HashedGlob::new(&string).map_err(D::Error::custom)
+    }
+}
+
+impl Hash for HashedGlob {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.string.hash(state);
+    }
+}
+
+impl PartialEq for HashedGlob {
+    fn eq(&self, other: &Self) -> bool {
+        self.string == other.string
+    }
+}
+
+impl Eq for HashedGlob {}
+
+impl FromStr for HashedGlob {
+    type Err = globset::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HashedGlob::new(s)
+    }
+}
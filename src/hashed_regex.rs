@@ -1,9 +1,10 @@
-use regex::Regex;
+use regex::{Regex, RegexSet, RegexSetBuilder};
 use serde::{Serialize, Deserialize, Deserializer, de::Error};
 use std::{
     hash::{Hash, Hasher},
     ops::Deref,
-    str::FromStr
+    str::FromStr,
+    sync::Arc
 };
 
 /// A wrapper around [`regex::Regex`] which implements **string repr based**
@@ -88,3 +89,45 @@ impl Deref for HashedRegex {
         &self.re
     }
 }
+
+/// Several [`HashedRegex`]es compiled into a single [`regex::RegexSet`], so
+/// checking whether any of them match is one pass over the text instead of
+/// one per pattern.
+///
+/// It starts out empty and is only filled in by [`CompiledRegexSet::new()`].
+/// It's a cache of the patterns it came from, so every [`CompiledRegexSet`]
+/// compares as equal and clones share the compiled set.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CompiledRegexSet(Option<Arc<RegexSet>>);
+
+impl CompiledRegexSet {
+    /// Compile some [`HashedRegex`]es into a set, optionally ignoring case
+    /// (as if every pattern started with `(?i)`).
+    pub fn new(
+        patterns: &[HashedRegex],
+        ignore_case: bool
+    ) -> Result<Self, regex::Error> {
+        let strings = patterns.iter().map(|pat| &pat.string);
+        let set = RegexSetBuilder::new(strings)
+            .case_insensitive(ignore_case)
+            // a set of hundreds of patterns easily outgrows the default DFA
+            // cache, falling back to a matcher which is slower than trying
+            // each pattern on its own
+            .dfa_size_limit(64 << 20)
+            .build()?;
+
+        Ok(CompiledRegexSet(Some(Arc::new(set))))
+    }
+
+    /// Does any of the patterns match somewhere in `text`, or `None` if the
+    /// set hasn't been compiled?
+    pub fn is_match(&self, text: &str) -> Option<bool> {
+        self.0.as_ref().map(|set| set.is_match(text))
+    }
+}
+
+impl PartialEq for CompiledRegexSet {
+    fn eq(&self, _other: &Self) -> bool { true }
+}
+
+impl Eq for CompiledRegexSet {}
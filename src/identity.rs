@@ -0,0 +1,185 @@
+//! Client certificates for web servers which require mutual TLS, and extra
+//! root certificates for servers using a private certificate authority.
+
+use crate::Config;
+use failure::{Error, ResultExt};
+use reqwest::{Certificate, Identity};
+use std::{fs, path::Path};
+
+/// Load the client certificate from [`Config::client_cert_path`] and
+/// [`Config::client_key_path`], if there is one.
+pub(crate) fn client_identity(cfg: &Config) -> Result<Option<Identity>, Error> {
+    match (&cfg.client_cert_path, &cfg.client_key_path) {
+        (Some(cert), Some(key)) => load_identity(cert, key).map(Some),
+        (None, None) => Ok(None),
+        _ => Err(failure::err_msg(
+            "Both `client-cert-path` and `client-key-path` must be set to use \
+             a client certificate",
+        )),
+    }
+}
+
+/// Load the extra root certificate from [`Config::tls_ca_cert`], if there is
+/// one.
+pub(crate) fn root_certificate(
+    cfg: &Config,
+) -> Result<Option<Certificate>, Error> {
+    let path = match cfg.tls_ca_cert {
+        Some(ref path) => path,
+        None => return Ok(None),
+    };
+
+    let pem = fs::read(path).with_context(|_| {
+        format!(
+            "Unable to read the CA certificate from \"{}\"",
+            path.display()
+        )
+    })?;
+    let cert = Certificate::from_pem(&pem).with_context(|_| {
+        format!(
+            "\"{}\" doesn't contain a valid PEM-encoded certificate",
+            path.display()
+        )
+    })?;
+
+    Ok(Some(cert))
+}
+
+fn load_identity(
+    cert_path: &Path,
+    key_path: &Path,
+) -> Result<Identity, Error> {
+    let cert = fs::read(cert_path).with_context(|_| {
+        format!(
+            "Unable to read the client certificate from \"{}\"",
+            cert_path.display()
+        )
+    })?;
+    let key = fs::read(key_path).with_context(|_| {
+        format!("Unable to read the client key from \"{}\"", key_path.display())
+    })?;
+
+    let der = pem_to_pkcs12(&cert, &key)
+        .context("Unable to load the client certificate")?;
+    let identity = Identity::from_pkcs12_der(&der, "")?;
+
+    Ok(identity)
+}
+
+/// `native-tls` can only load a client certificate from a PKCS #12 archive,
+/// so we need to convert the PEM-encoded certificate and key ourselves.
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "ios")))]
+fn pem_to_pkcs12(cert: &[u8], key: &[u8]) -> Result<Vec<u8>, Error> {
+    use openssl::{pkcs12::Pkcs12, pkey::PKey, stack::Stack, x509::X509};
+
+    let mut certs = X509::stack_from_pem(cert)?.into_iter();
+    let leaf = certs
+        .next()
+        .ok_or_else(|| failure::err_msg("No certificates were found"))?;
+    let key = PKey::private_key_from_pem(key)?;
+
+    let mut chain = Stack::new()?;
+    for cert in certs {
+        chain.push(cert)?;
+    }
+
+    let pkcs12 = Pkcs12::builder()
+        .name("mdbook-linkcheck")
+        .pkey(&key)
+        .cert(&leaf)
+        .ca(chain)
+        .build2("")?;
+
+    Ok(pkcs12.to_der()?)
+}
+
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "ios"))]
+fn pem_to_pkcs12(_cert: &[u8], _key: &[u8]) -> Result<Vec<u8>, Error> {
+    Err(failure::err_msg(
+        "PEM client certificates are only supported on platforms which use \
+         OpenSSL",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(name)
+    }
+
+    #[test]
+    fn no_client_certificate_by_default() {
+        assert!(client_identity(&Config::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn load_a_pem_client_certificate() {
+        let cfg = Config {
+            client_cert_path: Some(fixture("client-cert.pem")),
+            client_key_path: Some(fixture("client-key.pem")),
+            ..Default::default()
+        };
+
+        let got = client_identity(&cfg).unwrap();
+
+        assert!(got.is_some());
+        // and the HTTP client is happy to use it
+        crate::validate::create_client(&cfg, false, None).unwrap();
+    }
+
+    #[test]
+    fn the_certificate_and_key_must_both_be_provided() {
+        let cfg = Config {
+            client_cert_path: Some(fixture("client-cert.pem")),
+            ..Default::default()
+        };
+
+        let err = client_identity(&cfg).unwrap_err();
+
+        assert!(err.to_string().contains("client-key-path"));
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let cfg = Config {
+            client_cert_path: Some(fixture("missing.pem")),
+            client_key_path: Some(fixture("client-key.pem")),
+            ..Default::default()
+        };
+
+        let err = client_identity(&cfg).unwrap_err();
+
+        assert!(err.to_string().contains("missing.pem"), "{}", err);
+    }
+
+    #[test]
+    fn load_a_ca_certificate() {
+        let cfg = Config {
+            tls_ca_cert: Some(fixture("self-signed.pem")),
+            ..Default::default()
+        };
+
+        assert!(root_certificate(&cfg).unwrap().is_some());
+        assert!(root_certificate(&Config::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_ca_certificates_are_reported() {
+        let cfg = Config {
+            tls_ca_cert: Some(fixture("missing.pem")),
+            ..Default::default()
+        };
+        let err = root_certificate(&cfg).unwrap_err();
+        assert!(err.to_string().contains("missing.pem"), "{}", err);
+
+        let cfg = Config {
+            tls_ca_cert: Some(fixture("client-key.pem")),
+            ..Default::default()
+        };
+        let err = root_certificate(&cfg).unwrap_err();
+        assert!(err.to_string().contains("client-key.pem"), "{}", err);
+    }
+}
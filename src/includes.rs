@@ -0,0 +1,584 @@
+//! Reporting links which came from an `{{#include}}`d file at the place they
+//! were actually written.
+//!
+//! By the time a backend sees a chapter, `mdbook`'s `links` preprocessor has
+//! already replaced each `{{#include path}}` with the file's contents, so
+//! every span points into the expanded chapter. To work out where a link
+//! really came from we read the original chapter from disk and redo the
+//! expansion ourselves, remembering where each included line ended up. If our
+//! expansion doesn't match what `mdbook` produced (e.g. because a custom
+//! preprocessor changed the chapter) we give up and leave the links alone.
+
+use crate::{validate::ValidationOutcome, Link};
+use codespan::{ByteIndex, FileId, Files, Span};
+use mdbook::utils::{
+    take_anchored_lines, take_rustdoc_include_anchored_lines,
+    take_rustdoc_include_lines,
+};
+use regex::{Captures, Regex};
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+/// `mdbook` gives up after this many levels of nested includes.
+const MAX_NESTED_DEPTH: usize = 10;
+
+/// Where the text of each included file ended up in a chapter.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct IncludeMap {
+    regions: HashMap<FileId, Vec<Region>>,
+}
+
+/// A run of text copied verbatim from an included file.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Region {
+    /// Where the text starts in the expanded chapter.
+    start: usize,
+    /// Where the text ends in the expanded chapter.
+    end: usize,
+    /// The file the text was included from.
+    file: FileId,
+    /// Where the text starts in the included file.
+    offset: usize,
+}
+
+impl IncludeMap {
+    /// Redo the `{{#include}}` expansion for each chapter, adding any
+    /// included files to `files` so diagnostics can point at them.
+    pub fn new(
+        chapters: &[FileId],
+        files: &mut Files,
+        src_dir: &Path,
+    ) -> IncludeMap {
+        let mut map = IncludeMap::default();
+        let mut loaded = HashMap::new();
+
+        for &chapter in chapters {
+            let name = PathBuf::from(files.name(chapter));
+            let raw = match fs::read_to_string(src_dir.join(&name)) {
+                Ok(raw) => raw,
+                // draft chapters and chapters generated by a preprocessor
+                // don't exist on disk
+                Err(_) => continue,
+            };
+            if !raw.contains("{{") {
+                continue;
+            }
+
+            let dir = match name.parent() {
+                Some(parent) => src_dir.join(parent),
+                None => src_dir.to_path_buf(),
+            };
+            let mut includes = Includes {
+                files: &mut *files,
+                src_dir,
+                loaded: &mut loaded,
+            };
+            let expanded = includes.expand(vec![Segment::plain(raw)], &dir, 0);
+
+            let text: String =
+                expanded.iter().map(|segment| segment.text.as_str()).collect();
+            if text != files.source(chapter) {
+                log::debug!(
+                    "Unable to reproduce the {{{{#include}}}} expansion for \"{}\", so its links will be reported against the chapter",
+                    name.display()
+                );
+                continue;
+            }
+
+            let regions = regions(&expanded);
+            if !regions.is_empty() {
+                map.regions.insert(chapter, regions);
+            }
+        }
+
+        map
+    }
+
+    /// Figure out where some text from a chapter was originally written, if
+    /// it came from an included file.
+    pub fn locate(
+        &self,
+        chapter: FileId,
+        span: Span,
+    ) -> Option<(FileId, Span)> {
+        let start = span.start().to_usize();
+        let end = span.end().to_usize();
+
+        self.regions
+            .get(&chapter)?
+            .iter()
+            .find(|region| region.start <= start && end <= region.end)
+            .map(|region| {
+                let start = region.offset + (start - region.start);
+                let end = region.offset + (end - region.start);
+                let span = Span::new(
+                    ByteIndex(start as u32),
+                    ByteIndex(end as u32),
+                );
+                (region.file, span)
+            })
+    }
+
+    /// Point every link in the [`ValidationOutcome`] at the place it was
+    /// written.
+    ///
+    /// This needs to happen *after* validation because an included link is
+    /// rendered as part of the chapter, so relative links and fragments must
+    /// still be resolved relative to the chapter.
+    pub fn remap(&self, outcome: &mut ValidationOutcome) {
+        if self.regions.is_empty() {
+            return;
+        }
+
+        let links = outcome
+            .valid_links
+            .iter_mut()
+            .chain(outcome.invalid_links.iter_mut().map(|i| &mut i.link))
+            .chain(outcome.ignored.iter_mut())
+            .chain(outcome.suppressed.iter_mut())
+            .chain(outcome.unknown_schema.iter_mut())
+            .chain(outcome.skipped_offline.iter_mut())
+            .chain(outcome.insecure_links.iter_mut().map(|i| &mut i.link))
+            .chain(outcome.redirected_links.iter_mut().map(|r| &mut r.link))
+            .chain(outcome.disallowed_by_robots.iter_mut());
+
+        for link in links {
+            self.remap_link(link);
+        }
+    }
+
+    fn remap_link(&self, link: &mut Link) {
+        if let Some((file, span)) = self.locate(link.file, link.span) {
+            link.file = file;
+            link.span = span;
+        }
+    }
+}
+
+/// Some text in the expanded chapter, and where it was copied from (if it
+/// was copied verbatim from an included file).
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    text: String,
+    origin: Option<(FileId, usize)>,
+}
+
+impl Segment {
+    fn plain(text: String) -> Segment { Segment { text, origin: None } }
+}
+
+struct Includes<'a> {
+    files: &'a mut Files,
+    src_dir: &'a Path,
+    loaded: &'a mut HashMap<PathBuf, FileId>,
+}
+
+impl<'a> Includes<'a> {
+    /// Expand the directives in some text the same way `mdbook`'s `links`
+    /// preprocessor does.
+    fn expand(
+        &mut self,
+        segments: Vec<Segment>,
+        dir: &Path,
+        depth: usize,
+    ) -> Vec<Segment> {
+        let text: String =
+            segments.iter().map(|segment| segment.text.as_str()).collect();
+        let mut expanded = Vec::new();
+        let mut previous_end = 0;
+
+        for directive in directive_pattern().captures_iter(&text) {
+            let whole = directive.get(0).expect("Always present");
+            expanded.extend(slice(&segments, previous_end, whole.start()));
+
+            let directive = match Directive::parse(&directive) {
+                Some(d) => d,
+                None => {
+                    let text = slice(&segments, whole.start(), whole.end());
+                    expanded.extend(text);
+                    previous_end = whole.end();
+                    continue;
+                },
+            };
+
+            let (path, included) = match directive {
+                Directive::Escaped => {
+                    let start = whole.start() + 1;
+                    expanded.extend(slice(&segments, start, whole.end()));
+                    previous_end = whole.end();
+                    continue;
+                },
+                Directive::Include(path, start, end) => {
+                    let target = dir.join(&path);
+                    match self.include(&target, start, end) {
+                        Some(included) => (target, included),
+                        // mdbook leaves the directive alone, so we'll pick it
+                        // up with the next bit of text
+                        None => {
+                            previous_end = whole.start();
+                            continue;
+                        },
+                    }
+                },
+                Directive::Other(path, render) => {
+                    let target = dir.join(&path);
+                    match fs::read_to_string(&target) {
+                        Ok(contents) => {
+                            (target, vec![Segment::plain(render(&contents))])
+                        },
+                        Err(_) => {
+                            previous_end = whole.start();
+                            continue;
+                        },
+                    }
+                },
+            };
+
+            if depth < MAX_NESTED_DEPTH {
+                let nested_dir = path.parent().unwrap_or(dir).to_path_buf();
+                expanded.extend(self.expand(included, &nested_dir, depth + 1));
+            }
+            previous_end = whole.end();
+        }
+
+        expanded.extend(slice(&segments, previous_end, text.len()));
+        expanded
+    }
+
+    /// Read the selected lines from a file, remembering where each line came
+    /// from.
+    fn include(
+        &mut self,
+        target: &Path,
+        start: usize,
+        end: Bound<usize>,
+    ) -> Option<Vec<Segment>> {
+        let contents = fs::read_to_string(target).ok()?;
+        let file = self.file_id(target, &contents);
+        let lines = line_offsets(&contents)
+            .skip(start)
+            .take(line_count(start, end));
+        let mut segments = Vec::new();
+
+        for (i, (offset, line)) in lines.enumerate() {
+            // take_lines() joins the lines with "\n", whatever the file used
+            if i > 0 {
+                segments.push(Segment::plain(String::from("\n")));
+            }
+            segments.push(Segment {
+                text: line.to_string(),
+                origin: Some((file, offset)),
+            });
+        }
+
+        Some(segments)
+    }
+
+    fn file_id(&mut self, path: &Path, contents: &str) -> FileId {
+        let Includes {
+            files,
+            src_dir,
+            loaded,
+        } = self;
+
+        *loaded.entry(path.to_path_buf()).or_insert_with(|| {
+            let name = path.strip_prefix(*src_dir).unwrap_or(path);
+            files.add(name.display().to_string(), contents)
+        })
+    }
+}
+
+/// The bits of `{{#include}}`-like directives we care about.
+enum Directive {
+    /// `\{{#include ...}}`, which is left as-is (minus the backslash).
+    Escaped,
+    /// `{{#include path}}` or `{{#include path:start:end}}`, whose text we
+    /// can trace back to the included file.
+    Include(PathBuf, usize, Bound<usize>),
+    /// `{{#playpen}}`, `{{#rustdoc_include}}`, and anchored includes, whose
+    /// text we need to reproduce but which don't contain any links we can
+    /// trace.
+    Other(PathBuf, Box<dyn Fn(&str) -> String>),
+}
+
+/// Which part of a file to include.
+enum Selection {
+    /// A (0-based) start line and the end bound.
+    Lines(usize, Bound<usize>),
+    Anchor(String),
+}
+
+impl Directive {
+    fn parse(captures: &Captures<'_>) -> Option<Directive> {
+        let (kind, rest) = match (captures.get(1), captures.get(2)) {
+            (Some(kind), Some(rest)) => (kind.as_str(), rest.as_str()),
+            _ => return Some(Directive::Escaped),
+        };
+
+        let mut props = rest.split_whitespace();
+        let target = props.next()?;
+        let props: Vec<String> = props.map(String::from).collect();
+        let mut parts = target.splitn(2, ':');
+        let path = PathBuf::from(parts.next()?);
+        let selection = parse_selection(parts.next());
+
+        match kind {
+            "include" => match selection {
+                Selection::Anchor(anchor) => Some(Directive::Other(
+                    path,
+                    Box::new(move |s| take_anchored_lines(s, &anchor)),
+                )),
+                Selection::Lines(start, end) => {
+                    Some(Directive::Include(path, start, end))
+                },
+            },
+            "rustdoc_include" => match selection {
+                Selection::Anchor(anchor) => Some(Directive::Other(
+                    path,
+                    Box::new(move |s| {
+                        take_rustdoc_include_anchored_lines(s, &anchor)
+                    }),
+                )),
+                Selection::Lines(start, end) => Some(Directive::Other(
+                    path,
+                    Box::new(move |s| {
+                        let lines = (Bound::Included(start), end);
+                        take_rustdoc_include_lines(s, lines)
+                    }),
+                )),
+            },
+            "playpen" => Some(Directive::Other(
+                path,
+                Box::new(move |s| {
+                    let ftype = if props.is_empty() { "rust" } else { "rust," };
+                    format!("```{}{}\n{}\n```\n", ftype, props.join(","), s)
+                }),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Parse the `:10:20` or `:anchor` bit of an include, mirroring `mdbook`.
+fn parse_selection(raw: Option<&str>) -> Selection {
+    let mut parts = raw.unwrap_or("").splitn(3, ':').fuse();
+
+    let first = parts.next();
+    let start = match first.map(str::parse::<usize>) {
+        Some(Ok(line)) => Some(line.saturating_sub(1)),
+        _ => match first {
+            None | Some("") => None,
+            Some(anchor) => return Selection::Anchor(anchor.to_string()),
+        },
+    };
+    let end = parts.next().map(str::parse::<usize>);
+
+    match (start, end) {
+        (Some(start), Some(Ok(end))) => {
+            Selection::Lines(start, Bound::Excluded(end))
+        },
+        (Some(start), Some(Err(_))) => {
+            Selection::Lines(start, Bound::Unbounded)
+        },
+        (Some(start), None) => {
+            Selection::Lines(start, Bound::Excluded(start + 1))
+        },
+        (None, Some(Ok(end))) => Selection::Lines(0, Bound::Excluded(end)),
+        (None, _) => Selection::Lines(0, Bound::Unbounded),
+    }
+}
+
+/// How many lines to take after `start`, using the same rules as
+/// `take_lines()`.
+fn line_count(start: usize, end: Bound<usize>) -> usize {
+    match end {
+        Bound::Excluded(end) => end.saturating_sub(start),
+        Bound::Included(end) => (end + 1).saturating_sub(start),
+        Bound::Unbounded => usize::MAX,
+    }
+}
+
+/// Each line in some text (as returned by [`str::lines()`]), and the byte
+/// offset it starts at.
+fn line_offsets(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines().map(move |line| {
+        let offset = line.as_ptr() as usize - text.as_ptr() as usize;
+        (offset, line)
+    })
+}
+
+/// Get the segments covering `start..end` of their concatenated text.
+fn slice(segments: &[Segment], start: usize, end: usize) -> Vec<Segment> {
+    let mut sliced = Vec::new();
+    let mut position = 0;
+
+    for segment in segments {
+        let segment_start = position;
+        let segment_end = position + segment.text.len();
+        position = segment_end;
+
+        let from = start.max(segment_start);
+        let to = end.min(segment_end);
+        if from >= to {
+            continue;
+        }
+
+        sliced.push(Segment {
+            text: segment.text[from - segment_start..to - segment_start]
+                .to_string(),
+            origin: segment
+                .origin
+                .map(|(file, offset)| (file, offset + from - segment_start)),
+        });
+    }
+
+    sliced
+}
+
+fn regions(segments: &[Segment]) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let mut position = 0;
+
+    for segment in segments {
+        let start = position;
+        position += segment.text.len();
+
+        if let Some((file, offset)) = segment.origin {
+            regions.push(Region {
+                start,
+                end: position,
+                file,
+                offset,
+            });
+        }
+    }
+
+    regions
+}
+
+/// The same pattern `mdbook` uses to find `{{#include}}` and friends.
+fn directive_pattern() -> Regex {
+    Regex::new(
+        r"(?x)
+        \\\{\{\#.*\}\}
+        |
+        \{\{\s*
+        \#([a-zA-Z0-9_]+)
+        \s+
+        ([a-zA-Z0-9\s_.\-:/\\]+)
+        \s*\}\}",
+    )
+    .expect("The regex is always valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("includes")
+            .join("src")
+    }
+
+    fn located<'a>(
+        map: &IncludeMap,
+        files: &'a Files,
+        link: &Link,
+    ) -> Option<(&'a str, &'a str)> {
+        map.locate(link.file, link.span).map(|(file, span)| {
+            (files.name(file), files.source_slice(file, span).unwrap())
+        })
+    }
+
+    #[test]
+    fn links_in_included_files_point_at_that_file() {
+        let mut files = Files::new();
+        // what mdbook hands the backend after expanding the includes
+        let chapter = files.add(
+            "chapter_1.md",
+            "# Chapter 1\n\n[Chapter 2](./chapter_2.md)\n\nLinks which are shared between chapters.\n\n[broken](./missing.md)\n",
+        );
+
+        let map = IncludeMap::new(&[chapter], &mut files, &fixture_dir());
+        let (links, _) = crate::links::extract(vec![chapter], &files);
+
+        // the chapter's own link is left alone
+        assert_eq!(located(&map, &files, &links[0]), None);
+        assert_eq!(
+            located(&map, &files, &links[1]),
+            Some(("snippets/links.md", "[broken](./missing.md)"))
+        );
+    }
+
+    #[test]
+    fn line_ranges_and_escaped_includes() {
+        let mut files = Files::new();
+        let chapter = files.add(
+            "chapter_2.md",
+            "# Chapter 2\n\n[broken](./missing.md)\n\n{{#include snippets/links.md}}\n",
+        );
+
+        let map = IncludeMap::new(&[chapter], &mut files, &fixture_dir());
+        let (links, _) = crate::links::extract(vec![chapter], &files);
+
+        assert_eq!(links.len(), 1);
+        let (file, span) = map.locate(chapter, links[0].span).unwrap();
+        assert_eq!(files.name(file), "snippets/links.md");
+        assert_eq!(
+            files.location(file, span.start()).unwrap().line.to_usize(),
+            2
+        );
+    }
+
+    #[test]
+    fn give_up_if_the_chapter_was_changed() {
+        let mut files = Files::new();
+        let chapter = files.add(
+            "chapter_1.md",
+            "# Chapter 1\n\nSomething else rewrote this chapter\n\n[broken](./missing.md)\n",
+        );
+
+        let map = IncludeMap::new(&[chapter], &mut files, &fixture_dir());
+
+        assert_eq!(map, IncludeMap::default());
+    }
+
+    #[test]
+    fn parse_line_selections() {
+        let inputs = vec![
+            (None, 0, Bound::Unbounded),
+            (Some("3"), 2, Bound::Excluded(3)),
+            (Some("2:4"), 1, Bound::Excluded(4)),
+            (Some("2:"), 1, Bound::Unbounded),
+            (Some(":4"), 0, Bound::Excluded(4)),
+        ];
+
+        for (raw, start, end) in inputs {
+            match parse_selection(raw) {
+                Selection::Lines(s, e) => {
+                    assert_eq!((s, e), (start, end), "{:?}", raw)
+                },
+                Selection::Anchor(a) => panic!("{:?} gave {}", raw, a),
+            }
+        }
+
+        match parse_selection(Some("example")) {
+            Selection::Anchor(anchor) => assert_eq!(anchor, "example"),
+            Selection::Lines(..) => panic!("Expected an anchor"),
+        }
+    }
+
+    #[test]
+    fn lines_keep_track_of_their_offsets() {
+        let got: Vec<_> =
+            line_offsets("line 1\nline 2\r\nline 3\n").collect();
+
+        assert_eq!(got, vec![(0, "line 1"), (7, "line 2"), (15, "line 3")]);
+    }
+}
@@ -23,19 +23,52 @@ extern crate pretty_assertions;
 /// A semver range specifying which versions of `mdbook` this crate supports.
 pub const COMPATIBLE_MDBOOK_VERSIONS: &str = "^0.3.0";
 
+mod builder;
 mod cache;
+mod changes;
 mod config;
+mod contact;
+mod graph;
+mod identity;
+mod includes;
 mod links;
+mod listing;
+mod progress;
+mod proxy;
+mod report;
+mod robots;
+mod throttle;
+mod url_syntax;
 mod validate;
 mod hashed_regex;
+mod hashed_glob;
+#[cfg(test)]
+mod test_utils;
 
 pub use crate::{
-    cache::Cache,
-    config::{Config, WarningPolicy},
-    links::{extract as extract_links, IncompleteLink, Link},
+    builder::ConfigBuilder,
+    cache::{Cache, CacheStats},
+    config::{
+        BrokenLinkCount, Category, Config, DnsResolution, HttpMethod,
+        WarningPolicy, WebScheme,
+    },
+    graph::{find_orphan_pages, LinkEdge, LinkGraph},
+    includes::IncludeMap,
+    links::{
+        extract as extract_links, find_duplicate_anchors,
+        find_unused_references, DuplicateAnchor, IncompleteLink, Link,
+        UnusedReference,
+    },
+    listing::{write_link_list, LinkKind},
     hashed_regex::HashedRegex,
+    hashed_glob::HashedGlob,
+    report::{
+        read_json_report, write_json_report, LinkReport, LinkStatus, Summary,
+    },
     validate::{
-        validate, InvalidLink, Reason, UnknownScheme, ValidationOutcome,
+        validate, validate_link, validate_with, Action, InsecureLink,
+        InvalidLink, LinkResult, LinkValidator, Reason, RedirectedLink,
+        UnknownScheme, ValidationOutcome,
     },
 };
 
@@ -50,57 +83,232 @@ use mdbook::{
     renderer::RenderContext,
 };
 use semver::{Version, VersionReq};
-use std::{fs::File, path::Path};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::Write,
+    path::Path,
+};
+
+/// The outcome of [`run()`], used as the `mdbook-linkcheck` process's exit
+/// code.
+///
+/// These values are part of the public interface, so CI scripts can rely on
+/// them not changing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ExitCode {
+    /// Every link is fine (warnings may still have been emitted).
+    Success = 0,
+    /// There were errors, like a broken link. This is also used when
+    /// something else goes wrong, e.g. a web request couldn't be sent.
+    Errors = 1,
+    /// The configuration couldn't be loaded.
+    InvalidConfig = 2,
+    /// The only errors were warnings promoted to errors by a
+    /// [`WarningPolicy::Error`], or there were more warnings than
+    /// [`Config::fail_on_warning_count`] allows.
+    WarningsAsErrors = 3,
+}
+
+impl ExitCode {
+    /// The process exit code.
+    pub fn code(self) -> i32 { self as i32 }
+
+    /// The exit code to use when [`run()`] fails.
+    pub fn for_error(error: &Error) -> ExitCode {
+        match error.downcast_ref::<failure::Context<ExitCode>>() {
+            Some(context) => *context.get_context(),
+            None => ExitCode::Errors,
+        }
+    }
+}
+
+impl Display for ExitCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ExitCode::Success => "No broken links found",
+            ExitCode::Errors => "One or more incorrect links",
+            ExitCode::InvalidConfig => "The configuration is invalid",
+            ExitCode::WarningsAsErrors => {
+                "One or more warnings were treated as errors"
+            },
+        };
+
+        f.write_str(msg)
+    }
+}
+
+/// Mark an error as coming from the user's configuration.
+fn invalid_config(error: Error) -> Error {
+    error.context(ExitCode::InvalidConfig).into()
+}
 
 /// Run the link checking pipeline.
+///
+/// The `cache_file` is only used when [`Config::cache_file`] isn't set, and is
+/// never touched when [`Config::use_cache`] is `false`.
+///
+/// Problems with the links are reported using the returned [`ExitCode`],
+/// while any other failure is an `Err`. If the configuration couldn't be
+/// loaded, [`ExitCode::for_error()`] gives [`ExitCode::InvalidConfig`].
 pub fn run(
     cache_file: &Path,
     colour: ColorChoice,
     ctx: &RenderContext,
-) -> Result<(), Error> {
-    let cache = load_cache(cache_file);
-
+) -> Result<ExitCode, Error> {
     log::info!("Started the link checker");
 
-    let cfg = crate::get_config(&ctx.config)?;
+    let mut cfg = crate::get_config(&ctx.config).map_err(invalid_config)?;
     crate::version_check(&ctx.version)?;
 
+    cfg.load_ignore_file(&ctx.root).map_err(invalid_config)?;
+
+    if cfg.list_links {
+        list_links(ctx, &cfg)?;
+        return Ok(ExitCode::Success);
+    }
+
+    cfg.resolve_client_certificate(&ctx.root)
+        .map_err(invalid_config)?;
+
+    if cfg.allow_insecure_certificates {
+        log::warn!("TLS certificates won't be verified for any web links");
+    } else if !cfg.insecure_hosts.is_empty() {
+        log::warn!(
+            "TLS certificates won't be verified for hosts matching {:?}",
+            cfg.insecure_hosts.iter().map(|h| &h.string).collect::<Vec<_>>()
+        );
+    }
+
+    let cache_file = if cfg.use_cache {
+        Some(cfg.cache_location(&ctx.root, cache_file))
+    } else {
+        log::debug!("The cache is disabled");
+        None
+    };
+    let cache = match cache_file {
+        Some(ref cache_file) => load_cache(cache_file),
+        None => Cache::default(),
+    };
+
     if log::log_enabled!(log::Level::Trace) {
         for line in format!("{:#?}", cfg).lines() {
             log::trace!("{}", line);
         }
     }
 
-    let (files, outcome) = check_links(&ctx, &cache, &cfg).compat()?;
-    log::debug!(
-        "cache hits: {}, cache misses: {}",
-        cache.cache_hits(),
-        cache.cache_misses()
-    );
-    let diags = outcome.generate_diagnostics(&files, cfg.warning_policy);
+    let (files, chapters, outcome) =
+        check_links(&ctx, &cache, &cfg).compat()?;
+    log::debug!("{}", cache.stats());
+    let diags = outcome.generate_diagnostics(&files, &cfg);
     report_errors(&files, &diags, colour).compat()?;
+    report_offline(&outcome, colour).compat()?;
+    if cfg.print_summary {
+        let summary = outcome.summary(&chapters, &cfg);
+        report_summary(&summary, colour).compat()?;
+    }
+    if cfg.print_cache_stats {
+        report_summary(&cache.stats(), colour).compat()?;
+    }
+
+    save_reports(ctx, &cfg, &files, &chapters, &outcome).compat()?;
 
-    save_cache(cache_file, &cache);
+    if let Some(ref cache_file) = cache_file {
+        save_cache(cache_file, &cache);
+    }
+
+    let code = exit_code(&diags, &outcome, &files, &cfg);
+    log::info!("{} ({} broken links)", code, outcome.invalid_links.len());
+
+    Ok(code)
+}
+
+/// Figure out whether any errors were only caused by a
+/// [`WarningPolicy::Error`] or by exceeding [`Config::fail_on_warning_count`].
+///
+/// Real errors always win, so a broken link gives [`ExitCode::Errors`] no
+/// matter how many warnings there were.
+fn exit_code(
+    diags: &[Diagnostic],
+    outcome: &ValidationOutcome,
+    files: &Files,
+    cfg: &Config,
+) -> ExitCode {
+    let has_errors = |diags: &[Diagnostic]| {
+        diags.iter().any(|diag| diag.severity >= Severity::Error)
+    };
+    let warnings = diags
+        .iter()
+        .filter(|diag| diag.severity == Severity::Warning)
+        .count();
+    let too_many_warnings = match cfg.fail_on_warning_count {
+        Some(limit) if warnings > limit => {
+            log::warn!(
+                "Found {} warnings, but `fail-on-warning-count` is {}",
+                warnings,
+                limit
+            );
+            true
+        },
+        _ => false,
+    };
 
-    if diags.iter().any(|diag| diag.severity >= Severity::Error) {
-        log::info!("{} broken links found", outcome.invalid_links.len());
-        Err(failure::err_msg("One or more incorrect links"))
+    if !has_errors(diags) {
+        if too_many_warnings {
+            ExitCode::WarningsAsErrors
+        } else {
+            ExitCode::Success
+        }
+    } else if has_errors(
+        &outcome.generate_diagnostics(files, &cfg.without_promoted_warnings()),
+    ) {
+        ExitCode::Errors
     } else {
-        log::info!("No broken links found");
-        Ok(())
+        ExitCode::WarningsAsErrors
     }
 }
 
+/// Check the links in a single chapter without rendering the whole book.
+///
+/// The `source` is added to `files` under `path` (relative to the book's
+/// `src_dir`), so relative links resolve exactly like they would for the real
+/// chapter and the diagnostics can be emitted using `files`. This goes
+/// through the same code as the `mdbook` backend, so web links are still only
+/// checked when [`Config::follow_web_links`] is set. Nothing is cached.
+pub fn check_markdown(
+    source: &str,
+    path: &Path,
+    src_dir: &Path,
+    cfg: &Config,
+    files: &mut Files,
+) -> Result<Vec<Diagnostic>, Error> {
+    let id = files.add(path.display().to_string(), source);
+    let outcome = check_chapters(
+        &[id],
+        &[id],
+        files,
+        src_dir,
+        &Cache::default(),
+        cfg,
+        &IncludeMap::default(),
+    )?;
+
+    Ok(outcome.generate_diagnostics(files, cfg))
+}
+
 /// Get the configuration used by `mdbook-linkcheck`.
 pub fn get_config(cfg: &mdbook::Config) -> Result<Config, Error> {
-    match cfg.get("output.linkcheck") {
+    let mut cfg: Config = match cfg.get("output.linkcheck") {
         Some(raw) => raw
             .clone()
             .try_into()
-            .context("Unable to deserialize the `output.linkcheck` table.")
-            .map_err(Error::from),
-        None => Ok(Config::default()),
-    }
+            .context("Unable to deserialize the `output.linkcheck` table.")?,
+        None => Config::default(),
+    };
+
+    cfg.finalize()?;
+
+    Ok(cfg)
 }
 
 /// Check whether this library is compatible with the provided version string.
@@ -151,26 +359,198 @@ fn report_errors(
     Ok(())
 }
 
+/// Let the user know we didn't check any web links because of
+/// [`Config::offline`].
+fn report_offline(
+    outcome: &ValidationOutcome,
+    colour: ColorChoice,
+) -> Result<(), Error> {
+    if outcome.skipped_offline.is_empty() {
+        return Ok(());
+    }
+
+    let mut writer = StandardStream::stderr(colour);
+    writeln!(
+        writer,
+        "note: Skipped {} web links because offline mode is enabled",
+        outcome.skipped_offline.len()
+    )?;
+
+    Ok(())
+}
+
+fn report_summary(
+    summary: &dyn Display,
+    colour: ColorChoice,
+) -> Result<(), Error> {
+    let mut writer = StandardStream::stderr(colour);
+    writeln!(writer, "{}", summary)?;
+
+    Ok(())
+}
+
+/// Print the book's links to stdout, and what would happen to each of them,
+/// without checking them (see [`Config::list_links`]).
+fn list_links(ctx: &RenderContext, cfg: &Config) -> Result<(), Error> {
+    let mut files = Files::new();
+    let file_ids = crate::load_files_into_memory(&ctx.book, &mut files);
+    let (links, _) = crate::extract_links(file_ids, &files);
+    log::info!("Listing {} links", links.len());
+
+    let src = dunce::canonicalize(ctx.source_dir())
+        .context("Unable to resolve the source directory")?;
+    let stdout = std::io::stdout();
+    crate::write_link_list(&links, &files, cfg, &src, stdout.lock())
+}
+
 fn check_links(
     ctx: &RenderContext,
     cache: &Cache,
     cfg: &Config,
-) -> Result<(Files, ValidationOutcome), Error> {
+) -> Result<(Files, Vec<FileId>, ValidationOutcome), Error> {
     log::info!("Scanning book for links");
     let mut files = Files::new();
     let file_ids = crate::load_files_into_memory(&ctx.book, &mut files);
-    let (links, incomplete_links) = crate::extract_links(file_ids, &files);
+    let src_dir = ctx.source_dir();
+    let includes = IncludeMap::new(&file_ids, &mut files, &src_dir);
+
+    let to_check = match cfg.changed_since {
+        Some(ref git_ref) => {
+            let changed = crate::changes::changed_files(&src_dir, git_ref)?;
+            let to_check =
+                crate::changes::select_chapters(&file_ids, &files, &changed);
+            log::info!(
+                "Only checking the {} of {} chapters changed since \"{}\"",
+                to_check.len(),
+                file_ids.len(),
+                git_ref
+            );
+            to_check
+        },
+        None => file_ids.clone(),
+    };
+
+    let outcome = check_chapters(
+        &file_ids, &to_check, &files, &src_dir, cache, cfg, &includes,
+    )?;
+
+    Ok((files, file_ids, outcome))
+}
+
+/// Extract and validate the links in some chapters which have already been
+/// loaded into memory, then point any links from included files at the file
+/// they were written in.
+///
+/// Only the links in `to_check` are validated, but whole-book checks (e.g.
+/// [`Config::detect_orphans`]) still look at every chapter.
+fn check_chapters(
+    chapters: &[FileId],
+    to_check: &[FileId],
+    files: &Files,
+    src_dir: &Path,
+    cache: &Cache,
+    cfg: &Config,
+    includes: &IncludeMap,
+) -> Result<ValidationOutcome, Error> {
+    let (links, suppressed, incomplete_links) =
+        crate::links::extract_with_suppressed(to_check.iter().cloned(), files);
     log::info!(
         "Found {} links ({} incomplete links)",
         links.len(),
         incomplete_links.len()
     );
-    let src = dunce::canonicalize(ctx.source_dir())
+    let src = dunce::canonicalize(src_dir)
         .context("Unable to resolve the source directory")?;
-    let outcome =
-        crate::validate(&links, &cfg, &src, &cache, &files, incomplete_links)?;
+    let mut outcome =
+        crate::validate(&links, cfg, &src, cache, files, incomplete_links)?;
+    outcome.suppressed = suppressed;
+
+    if cfg.check_anchors {
+        outcome.duplicate_anchors =
+            crate::find_duplicate_anchors(to_check.iter().cloned(), files);
+    }
+
+    if cfg.detect_orphans {
+        let (all_links, _) =
+            crate::extract_links(chapters.iter().cloned(), files);
+        outcome.orphan_pages =
+            crate::find_orphan_pages(&all_links, chapters, files, &src)
+                .into_iter()
+                .filter(|page| to_check.contains(page))
+                .collect();
+    }
+
+    if cfg.detect_unused_references {
+        outcome.unused_references =
+            crate::find_unused_references(to_check.iter().cloned(), files);
+    }
+
+    includes.remap(&mut outcome);
+
+    Ok(outcome)
+}
+
+/// Write any machine-readable reports the user asked for.
+fn save_reports(
+    ctx: &RenderContext,
+    cfg: &Config,
+    files: &Files,
+    chapters: &[FileId],
+    outcome: &ValidationOutcome,
+) -> Result<(), Error> {
+    if let Some(ref json_report) = cfg.json_report {
+        let records = outcome.report(files);
+        save_report(&ctx.root.join(json_report), |f| {
+            crate::write_json_report(&records, f)
+        })?;
+    }
+
+    if let Some(ref sarif_report) = cfg.sarif_report {
+        // SARIF paths should be relative to the repository, which is usually
+        // the book's root directory
+        let src_dir = ctx.config.book.src.as_path();
+        let sarif = outcome.sarif(files, cfg, src_dir);
+        save_report(&ctx.root.join(sarif_report), |f| {
+            serde_json::to_writer_pretty(f, &sarif).map_err(Error::from)
+        })?;
+    }
 
-    Ok((files, outcome))
+    if let Some(ref junit_report) = cfg.junit_report {
+        let junit = outcome.junit(files, chapters, cfg);
+        save_report(&ctx.root.join(junit_report), |mut f| {
+            f.write_all(junit.as_bytes()).map_err(Error::from)
+        })?;
+    }
+
+    if let Some(ref link_graph) = cfg.link_graph {
+        let src_dir = dunce::canonicalize(ctx.source_dir())
+            .context("Unable to resolve the source directory")?;
+        let (links, _) = crate::extract_links(chapters.iter().cloned(), files);
+        let graph = LinkGraph::new(&links, chapters, files, &src_dir);
+        save_report(&ctx.root.join(link_graph), |f| {
+            serde_json::to_writer_pretty(f, &graph).map_err(Error::from)
+        })?;
+    }
+
+    Ok(())
+}
+
+fn save_report<F>(filename: &Path, write: F) -> Result<(), Error>
+where
+    F: FnOnce(File) -> Result<(), Error>,
+{
+    log::debug!("Saving a report to {}", filename.display());
+
+    if let Some(parent) = filename.parent() {
+        std::fs::create_dir_all(parent).with_context(|_| {
+            format!("Unable to create {}", parent.display())
+        })?;
+    }
+
+    let f = File::create(filename).with_context(|_| {
+        format!("Unable to create {}", filename.display())
+    })?;
+    write(f)
 }
 
 fn load_cache(filename: &Path) -> Cache {
@@ -213,9 +593,201 @@ fn save_cache(filename: &Path, cache: &Cache) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::PathBuf;
 
     #[test]
     fn always_stay_compatible_with_mdbook_dependency() {
         version_check(mdbook::MDBOOK_VERSION).unwrap();
     }
+
+    #[test]
+    fn exit_codes_for_broken_links_and_warnings() {
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let broken = || InvalidLink {
+            link: Link::parse("./missing.md", 0..1, id).unwrap(),
+            reason: Reason::FileNotFound,
+        };
+        let warning = || InvalidLink {
+            link: Link::parse("./chapter.md#nope", 0..1, id).unwrap(),
+            reason: Reason::AnchorNotFound(String::from("nope")),
+        };
+        let outcomes = || {
+            vec![
+                ("nothing", Vec::new()),
+                ("broken", vec![broken()]),
+                ("warning", vec![warning()]),
+                ("both", vec![broken(), warning()]),
+            ]
+        };
+        let policies = |warning_policy, local, broken_link_policy| Config {
+            warning_policy,
+            local_warning_policy: local,
+            broken_link_policy,
+            ..Default::default()
+        };
+        let (ignore, warn, error) =
+            (WarningPolicy::Ignore, WarningPolicy::Warn, WarningPolicy::Error);
+        let (success, errors, promoted) = (
+            ExitCode::Success,
+            ExitCode::Errors,
+            ExitCode::WarningsAsErrors,
+        );
+        let inputs = vec![
+            // broken links are always errors by default
+            (policies(ignore, None, None), [success, errors, success, errors]),
+            (policies(warn, None, None), [success, errors, success, errors]),
+            (policies(error, None, None), [success, errors, promoted, errors]),
+            // ... unless the local policy says otherwise
+            (
+                policies(warn, Some(warn), None),
+                [success, success, success, success],
+            ),
+            // a broken link policy splits the two
+            (
+                policies(warn, Some(warn), Some(error)),
+                [success, errors, success, errors],
+            ),
+            (
+                policies(error, None, Some(error)),
+                [success, errors, promoted, errors],
+            ),
+            (
+                policies(error, None, Some(warn)),
+                [success, success, promoted, promoted],
+            ),
+            (
+                policies(warn, None, Some(ignore)),
+                [success, success, success, success],
+            ),
+        ];
+
+        for (cfg, should_be) in inputs {
+            for ((name, invalid_links), should_be) in
+                outcomes().into_iter().zip(&should_be)
+            {
+                let outcome = ValidationOutcome {
+                    invalid_links,
+                    ..Default::default()
+                };
+                let diags = outcome.generate_diagnostics(&files, &cfg);
+
+                let got = exit_code(&diags, &outcome, &files, &cfg);
+
+                assert_eq!(
+                    got, *should_be,
+                    "{} with {:?}, {:?}, {:?}",
+                    name,
+                    cfg.warning_policy,
+                    cfg.local_warning_policy,
+                    cfg.broken_link_policy
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fail_when_there_are_too_many_warnings() {
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let warnings = |count: usize| -> Vec<InvalidLink> {
+            (0..count)
+                .map(|i| {
+                    let fragment = format!("missing-{}", i);
+                    let href = format!("./chapter.md#{}", fragment);
+                    InvalidLink {
+                        link: Link::parse(&href, 0..1, id).unwrap(),
+                        reason: Reason::AnchorNotFound(fragment),
+                    }
+                })
+                .collect()
+        };
+        let broken = InvalidLink {
+            link: Link::parse("./missing.md", 0..1, id).unwrap(),
+            reason: Reason::FileNotFound,
+        };
+        let cfg = |warning_policy, fail_on_warning_count| Config {
+            warning_policy,
+            fail_on_warning_count,
+            ..Default::default()
+        };
+        let run = |cfg: &Config, invalid_links: Vec<InvalidLink>| {
+            let outcome = ValidationOutcome {
+                invalid_links,
+                ..Default::default()
+            };
+            let diags = outcome.generate_diagnostics(&files, cfg);
+            exit_code(&diags, &outcome, &files, cfg)
+        };
+        let (warn, error, ignore) =
+            (WarningPolicy::Warn, WarningPolicy::Error, WarningPolicy::Ignore);
+        let inputs = vec![
+            // without a threshold, warnings never fail the build
+            (cfg(warn, None), 25, ExitCode::Success),
+            // only going over the threshold fails the build
+            (cfg(warn, Some(3)), 0, ExitCode::Success),
+            (cfg(warn, Some(3)), 3, ExitCode::Success),
+            (cfg(warn, Some(3)), 4, ExitCode::WarningsAsErrors),
+            (cfg(warn, Some(0)), 1, ExitCode::WarningsAsErrors),
+            // ignored warnings aren't counted
+            (cfg(ignore, Some(3)), 25, ExitCode::Success),
+            // promoted warnings fail the build regardless of the threshold
+            (cfg(error, Some(3)), 1, ExitCode::WarningsAsErrors),
+        ];
+
+        for (cfg, count, should_be) in inputs {
+            let got = run(&cfg, warnings(count));
+
+            assert_eq!(
+                got, should_be,
+                "{} warnings with {:?} and a threshold of {:?}",
+                count, cfg.warning_policy, cfg.fail_on_warning_count
+            );
+        }
+
+        // a real error still takes precedence over too many warnings
+        let mut invalid_links = warnings(5);
+        invalid_links.push(broken);
+        assert_eq!(run(&cfg(warn, Some(3)), invalid_links), ExitCode::Errors);
+    }
+
+    #[test]
+    fn only_check_chapters_which_changed() {
+        let src_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let unchanged = files.add("chapter_1.md", "[broken](./missing.md)");
+        let changed = files.add(
+            "nested/index.md",
+            "[anchor](../chapter_1.md#subheading) and [gone](./gone.md)",
+        );
+        let chapters = vec![unchanged, changed];
+        let changed_files =
+            vec![PathBuf::from("nested/index.md")].into_iter().collect();
+        let cfg = Config::default();
+
+        let to_check =
+            crate::changes::select_chapters(&chapters, &files, &changed_files);
+        assert_eq!(to_check, vec![changed]);
+
+        let outcome = check_chapters(
+            &chapters,
+            &to_check,
+            &files,
+            &src_dir,
+            &Cache::default(),
+            &cfg,
+            &IncludeMap::default(),
+        )
+        .unwrap();
+
+        let broken: Vec<_> = outcome
+            .invalid_links
+            .iter()
+            .map(|invalid| invalid.link.uri.to_string())
+            .collect();
+        assert_eq!(broken, vec!["./gone.md"]);
+    }
 }
@@ -1,9 +1,13 @@
 use codespan::{ByteIndex, FileId, Files, Span};
 use http::uri::{Parts, Uri};
+use lazy_static::lazy_static;
 use pulldown_cmark::{Event, OffsetIter, Parser, Tag};
+use regex::Regex;
 use std::{
     cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Debug,
+    ops::Range,
     path::{Component, Path, PathBuf},
 };
 
@@ -16,6 +20,8 @@ pub struct Link {
     pub span: Span,
     /// The file this link was originally found in.
     pub file: FileId,
+    /// The link's fragment (the bit after the `#`), if it has one.
+    pub fragment: Option<String>,
 }
 
 impl Link {
@@ -27,10 +33,17 @@ impl Link {
         let start = ByteIndex(range.start as u32);
         let end = ByteIndex(range.end as u32);
         let span = Span::new(start, end);
+        // Note: the fragment is never sent to a server, so `http::Uri` drops it
+        let fragment = uri.find('#').map(|ix| uri[ix + 1..].to_string());
 
         // it might be a valid URI already
         if let Ok(uri) = uri.parse() {
-            return Ok(Link { uri, span, file });
+            return Ok(Link {
+                uri,
+                span,
+                file,
+                fragment,
+            });
         }
 
         // otherwise, treat it like a relative path with no authority or scheme
@@ -38,7 +51,43 @@ impl Link {
         parts.path_and_query = Some(uri.parse()?);
         let uri = Uri::from_parts(parts)?;
 
-        Ok(Link { uri, span, file })
+        Ok(Link {
+            uri,
+            span,
+            file,
+            fragment,
+        })
+    }
+
+    /// Is this a scheme-relative link (e.g. `//example.com/page`), which
+    /// uses the same scheme as the page it's on?
+    pub fn is_scheme_relative(&self) -> bool {
+        self.uri.scheme_str().is_none()
+            && self.uri.authority_part().is_none()
+            && self.uri.path().starts_with("//")
+    }
+
+    /// Resolve a scheme-relative link (see [`Link::is_scheme_relative()`]) to
+    /// an absolute URL using the provided scheme. Other links are returned
+    /// as-is.
+    pub(crate) fn with_default_scheme(&self, scheme: &str) -> Link {
+        if !self.is_scheme_relative() {
+            return self.clone();
+        }
+
+        let path_and_query = self
+            .uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or_default();
+
+        match format!("{}:{}", scheme, path_and_query).parse() {
+            Ok(uri) => Link {
+                uri,
+                ..self.clone()
+            },
+            Err(_) => self.clone(),
+        }
     }
 
     pub(crate) fn as_filesystem_path(
@@ -100,23 +149,103 @@ fn decoded_path(percent_encoded_path: &str) -> PathBuf {
 
 /// Search every file in the [`Files`] and collate all the links that are
 /// found.
+///
+/// Links which are suppressed by a linkcheck directive (see
+/// [`ignored_lines()`]) are left out.
 pub fn extract<I>(
     target_files: I,
     files: &Files,
 ) -> (Vec<Link>, Vec<IncompleteLink>)
+where
+    I: IntoIterator<Item = FileId>,
+{
+    let (links, _, incomplete) = extract_with_suppressed(target_files, files);
+    (links, incomplete)
+}
+
+/// Like [`extract()`], but the links suppressed by a linkcheck directive are
+/// also returned (as the second item) so they can be reported as ignored.
+pub(crate) fn extract_with_suppressed<I>(
+    target_files: I,
+    files: &Files,
+) -> (Vec<Link>, Vec<Link>, Vec<IncompleteLink>)
 where
     I: IntoIterator<Item = FileId>,
 {
     let mut links = Vec::new();
+    let mut suppressed = Vec::new();
     let broken_links = RefCell::new(Vec::new());
 
     for file_id in target_files {
         let cb = on_broken_links(file_id, &broken_links);
         log::debug!("Scanning {}", files.name(file_id));
-        links.extend(Links::new(file_id, files, &cb));
+        let ignored = ignored_lines(files.source(file_id));
+
+        for link in Links::new(file_id, files, &cb) {
+            let line = files
+                .location(file_id, link.span.start())
+                .map(|location| location.line.to_usize())
+                .unwrap_or_default();
+
+            if ignored.contains(&line) {
+                log::debug!(
+                    "Ignoring \"{}\" on line {} because of a linkcheck directive",
+                    link.uri,
+                    line + 1
+                );
+                suppressed.push(link);
+            } else {
+                links.push(link);
+            }
+        }
+    }
+
+    (links, suppressed, broken_links.into_inner())
+}
+
+/// Find the (0-based) lines whose links should be ignored because of a
+/// linkcheck directive.
+///
+/// - `<!-- linkcheck-ignore -->` (or `<!-- linkcheck-ignore-line -->`)
+///   ignores every link on the same line
+/// - `<!-- linkcheck-disable-next-line -->` ignores every link on the next
+///   line
+/// - `<!-- linkcheck-ignore-block -->` ignores every link from that line up
+///   to the next blank line, so it's usually written on a line by itself
+///   just before a paragraph or list
+fn ignored_lines(src: &str) -> HashSet<usize> {
+    lazy_static! {
+        static ref IGNORE: Regex =
+            Regex::new(r"<!--\s*linkcheck-ignore(?:-line)?\s*-->")
+                .expect("The regex is always valid");
+        static ref NEXT_LINE: Regex =
+            Regex::new(r"<!--\s*linkcheck-disable-next-line\s*-->")
+                .expect("The regex is always valid");
+        static ref BLOCK: Regex =
+            Regex::new(r"<!--\s*linkcheck-ignore-block\s*-->")
+                .expect("The regex is always valid");
     }
 
-    (links, broken_links.into_inner())
+    let lines: Vec<&str> = src.lines().collect();
+    let mut ignored = HashSet::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        if IGNORE.is_match(line) {
+            ignored.insert(i);
+        }
+        if NEXT_LINE.is_match(line) {
+            ignored.insert(i + 1);
+        }
+        if BLOCK.is_match(line) {
+            let block = lines[i + 1..]
+                .iter()
+                .take_while(|line| !line.trim().is_empty())
+                .count();
+            ignored.extend(i..=i + block);
+        }
+    }
+
+    ignored
 }
 
 /// Get a closure which can be used as the broken links callback, adding a new
@@ -150,6 +279,8 @@ struct Links<'a> {
     events: OffsetIter<'a>,
     file: FileId,
     files: &'a Files,
+    /// Links found in a chunk of HTML which haven't been returned yet.
+    pending: VecDeque<Link>,
 }
 
 impl<'a> Links<'a> {
@@ -168,6 +299,34 @@ impl<'a> Links<'a> {
             .into_offset_iter(),
             file,
             files,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn parse(&self, dest: &str, range: Range<usize>) -> Option<Link> {
+        log::trace!(
+            "Found \"{}\" at {}..{} of file {:?}",
+            dest,
+            range.start,
+            range.end,
+            self.file,
+        );
+
+        match Link::parse(dest, range.clone(), self.file) {
+            Ok(link) => Some(link),
+            Err(e) => {
+                let location = self
+                    .files
+                    .location(self.file, range.start as u32)
+                    .unwrap();
+                log::warn!(
+                    "Unable to parse \"{}\" as a URI on line {}: {}",
+                    dest,
+                    location.line,
+                    e
+                );
+                None
+            },
         }
     }
 }
@@ -176,36 +335,312 @@ impl<'a> Iterator for Links<'a> {
     type Item = Link;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some((event, range)) = self.events.next() {
+        loop {
+            if let Some(link) = self.pending.pop_front() {
+                return Some(link);
+            }
+
+            let (event, range) = self.events.next()?;
+
             match event {
                 Event::Start(Tag::Link(_, dest, _))
                 | Event::Start(Tag::Image(_, dest, _)) => {
-                    log::trace!(
-                        "Found \"{}\" at {}..{} of file {:?}",
-                        dest,
-                        range.start,
-                        range.end,
-                        self.file,
-                    );
-
-                    match Link::parse(&dest, range.clone(), self.file) {
-                        Ok(link) => return Some(link),
-                        Err(e) => {
-                            let location = self
-                                .files
-                                .location(self.file, range.start as u32)
-                                .unwrap();
-                            log::warn!( "Unable to parse \"{}\" as a URI on line {}: {}", dest, location.line, e);
-
-                            continue;
-                        },
+                    if let Some(link) = self.parse(&dest, range) {
+                        return Some(link);
+                    }
+                },
+                Event::Html(html) => {
+                    for (dest, value) in html_links(&html) {
+                        let start = range.start + value.start;
+                        let end = range.start + value.end;
+
+                        if let Some(link) = self.parse(dest, start..end) {
+                            self.pending.push_back(link);
+                        }
                     }
                 },
                 _ => {},
             }
         }
+    }
+}
+
+/// Find the `href` and `src` attributes in a chunk of HTML, along with where
+/// each attribute's value is.
+///
+/// HTML comments are skipped, as are `javascript:` and `data:` URIs because
+/// there's nothing to check.
+fn html_links(html: &str) -> Vec<(&str, Range<usize>)> {
+    lazy_static! {
+        static ref COMMENT: Regex =
+            Regex::new(r"(?s)<!--.*?-->").expect("The regex is always valid");
+        static ref ATTRIBUTE: Regex =
+            Regex::new(r#"\b(?i:href|src)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+                .expect("The regex is always valid");
+    }
+
+    let comments: Vec<Range<usize>> =
+        COMMENT.find_iter(html).map(|m| m.start()..m.end()).collect();
+
+    ATTRIBUTE
+        .captures_iter(html)
+        .filter_map(|cap| cap.get(1).or_else(|| cap.get(2)))
+        .filter(|value| {
+            !comments.iter().any(|comment| comment.contains(&value.start()))
+        })
+        .map(|value| (value.as_str(), value.start()..value.end()))
+        .filter(|(dest, _)| {
+            let lower = dest.to_lowercase();
+            !dest.is_empty()
+                && !lower.starts_with("javascript:")
+                && !lower.starts_with("data:")
+        })
+        .collect()
+}
+
+/// Find all the anchors a link to this markdown document could point at.
+///
+/// Headings are given the same IDs `mdbook` would generate (see
+/// [`headings()`]), and the `id` or `name` of any inline HTML elements are
+/// included too.
+pub(crate) fn anchors(src: &str) -> HashSet<String> {
+    let mut anchors: HashSet<String> =
+        headings(src).into_iter().map(|heading| heading.id).collect();
+
+    for event in Parser::new_ext(src, pulldown_cmark::Options::all()) {
+        if let Event::Html(ref html) = event {
+            anchors.extend(html_anchors(html));
+        }
+    }
+
+    anchors
+}
+
+/// A heading and the anchor `mdbook` will give it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Heading {
+    pub id: String,
+    pub range: Range<usize>,
+}
+
+/// Find every heading in a markdown document, in the order they appear.
+///
+/// Duplicate headings get a `-1`, `-2`, etc. suffix the same way `mdbook`
+/// disambiguates them, and a heading can also set its ID explicitly (e.g.
+/// `## Installation {#install}`).
+pub(crate) fn headings(src: &str) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut id_counter = HashMap::new();
+    let mut heading: Option<String> = None;
+
+    for (event, range) in
+        Parser::new_ext(src, pulldown_cmark::Options::all()).into_offset_iter()
+    {
+        match event {
+            Event::Start(Tag::Heading(_)) => heading = Some(String::new()),
+            Event::Text(ref text) | Event::Code(ref text) => {
+                if let Some(ref mut heading) = heading {
+                    heading.push_str(text);
+                }
+            },
+            Event::End(Tag::Heading(_)) => {
+                let content = heading.take().unwrap_or_default();
+
+                let id = match explicit_heading_id(&content) {
+                    Some(id) => id.to_string(),
+                    None => {
+                        let raw_id = mdbook::utils::id_from_content(&content);
+                        let id_count =
+                            id_counter.entry(raw_id.clone()).or_insert(0);
+
+                        let id = match *id_count {
+                            0 => raw_id,
+                            other => format!("{}-{}", raw_id, other),
+                        };
+                        *id_count += 1;
+                        id
+                    },
+                };
+
+                headings.push(Heading { id, range });
+            },
+            _ => {},
+        }
+    }
+
+    headings
+}
+
+/// Find headings which `mdbook` will give the same anchor, even after
+/// disambiguating duplicates (e.g. a second "Overview" and an "Overview 1"
+/// both become `#overview-1`). Only the first heading is reachable by links.
+pub fn find_duplicate_anchors<I>(
+    target_files: I,
+    files: &Files,
+) -> Vec<DuplicateAnchor>
+where
+    I: IntoIterator<Item = FileId>,
+{
+    let mut duplicates = Vec::new();
+
+    for file in target_files {
+        let mut seen: HashMap<String, Span> = HashMap::new();
+
+        for Heading { id, range } in headings(files.source(file)) {
+            let span = Span::new(range.start as u32, range.end as u32);
+
+            match seen.get(&id) {
+                Some(&first) => duplicates.push(DuplicateAnchor {
+                    anchor: id,
+                    file,
+                    first,
+                    second: span,
+                }),
+                None => {
+                    seen.insert(id, span);
+                },
+            }
+        }
+    }
+
+    duplicates
+}
+
+/// Two headings in the same file which end up with the same anchor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateAnchor {
+    /// The anchor both headings were given.
+    pub anchor: String,
+    /// The file containing the headings.
+    pub file: FileId,
+    /// The heading which links to the anchor will actually go to.
+    pub first: Span,
+    /// The heading which can't be linked to.
+    pub second: Span,
+}
+
+/// Find reference definitions (e.g. `[rust]: https://www.rust-lang.org/`)
+/// which no link in the same file uses.
+///
+/// Definitions inside code blocks and footnote definitions (`[^note]: ...`)
+/// are skipped. Labels match regardless of case and whitespace, like
+/// CommonMark, and any `[label]` elsewhere in the file counts as a use.
+pub fn find_unused_references<I>(
+    target_files: I,
+    files: &Files,
+) -> Vec<UnusedReference>
+where
+    I: IntoIterator<Item = FileId>,
+{
+    lazy_static! {
+        static ref DEFINITION: Regex =
+            Regex::new(r"(?m)^ {0,3}\[((?:[^\]\\\n]|\\.)+)\]:[ \t]*\S.*$")
+                .expect("The regex is always valid");
+        static ref USAGE: Regex = Regex::new(r"\[((?:[^\[\]\\]|\\.)+)\]")
+            .expect("The regex is always valid");
+    }
+
+    let mut unused = Vec::new();
+
+    for file in target_files {
+        let src = files.source(file);
+        let code_blocks: Vec<Range<usize>> =
+            Parser::new_ext(src, pulldown_cmark::Options::all())
+                .into_offset_iter()
+                .filter_map(|(event, range)| match event {
+                    Event::Start(Tag::CodeBlock(_)) => Some(range),
+                    _ => None,
+                })
+                .collect();
+        let definitions: Vec<_> = DEFINITION.captures_iter(src).collect();
+
+        // a definition's own "[label]" isn't a use
+        let defined_at: HashSet<usize> = definitions
+            .iter()
+            .map(|cap| cap.get(1).expect("Always present").start() - 1)
+            .collect();
+        let used: HashSet<String> = USAGE
+            .captures_iter(src)
+            .filter(|cap| {
+                let whole = cap.get(0).expect("There is always a match");
+                !defined_at.contains(&whole.start())
+            })
+            .map(|cap| normalize_label(&cap[1]))
+            .collect();
+
+        for cap in definitions {
+            let whole = cap.get(0).expect("There is always a match");
+            let label = &cap[1];
+
+            if label.starts_with('^')
+                || code_blocks.iter().any(|code| code.contains(&whole.start()))
+                || used.contains(&normalize_label(label))
+            {
+                continue;
+            }
+
+            unused.push(UnusedReference {
+                label: label.to_string(),
+                file,
+                span: Span::new(
+                    ByteIndex(whole.start() as u32),
+                    ByteIndex(whole.end() as u32),
+                ),
+            });
+        }
+    }
 
+    unused
+}
+
+/// Labels match regardless of case and differences in whitespace.
+fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// A reference definition which nothing links to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnusedReference {
+    /// The reference's label (e.g. the `rust` in `[rust]: ...`).
+    pub label: String,
+    /// The file the definition is in.
+    pub file: FileId,
+    /// Where the definition is.
+    pub span: Span,
+}
+
+/// Find the `id` or `name` of every element in a HTML document.
+pub(crate) fn html_anchors(html: &str) -> HashSet<String> {
+    lazy_static! {
+        static ref ANCHOR: Regex =
+            Regex::new(r#"\b(?:id|name)\s*=\s*["']([^"']+)["']"#)
+                .expect("The regex is always valid");
+    }
+
+    ANCHOR
+        .captures_iter(html)
+        .map(|cap| cap[1].to_string())
+        .collect()
+}
+
+/// Get the ID from a heading ending in `{#custom-id}`, if there is one.
+fn explicit_heading_id(content: &str) -> Option<&str> {
+    let content = content.trim_end();
+    if !content.ends_with('}') {
+        return None;
+    }
+
+    let start = content.rfind("{#")?;
+    let id = content[start + 2..content.len() - 1].trim();
+
+    if id.is_empty() || id.contains(char::is_whitespace) {
         None
+    } else {
+        Some(id)
     }
 }
 
@@ -245,4 +680,210 @@ mod tests {
 
         assert_eq!(got, should_be);
     }
+
+    #[test]
+    fn headings_are_given_mdbook_style_anchors() {
+        let src = "# Getting Started\n\n## The `cargo` Command\n\n## Overview\n\n## Overview\n\n## Overview\n\n## Installing {#install}\n\n<a name=\"custom\"></a>\n";
+
+        let got = anchors(src);
+
+        let should_be: HashSet<String> = vec![
+            "getting-started",
+            "the-cargo-command",
+            "overview",
+            "overview-1",
+            "overview-2",
+            "install",
+            "custom",
+        ]
+        .into_iter()
+        .map(String::from)
+        .collect();
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn the_fragment_is_kept() {
+        let mut files = Files::new();
+        let id = files.add("whatever", "");
+
+        let got = Link::parse("./chapter.md#my-section", 0..1, id).unwrap();
+
+        assert_eq!(got.uri.path(), "./chapter.md");
+        assert_eq!(got.fragment, Some(String::from("my-section")));
+    }
+
+    #[test]
+    fn detect_headings_which_get_the_same_anchor() {
+        let src = "# Overview\n\n# Overview\n\n# Overview 1\n";
+        let mut files = Files::new();
+        let id = files.add("whatever", src);
+
+        let got = find_duplicate_anchors(vec![id], &files);
+
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].anchor, "overview-1");
+        let first = files.source_slice(id, got[0].first).unwrap();
+        assert_eq!(first, "# Overview\n");
+        let second = files.source_slice(id, got[0].second).unwrap();
+        assert_eq!(second, "# Overview 1\n");
+    }
+
+    #[test]
+    fn duplicate_headings_are_disambiguated_by_mdbook() {
+        let src = "# Overview\n\n# Overview\n\n## Overview\n";
+        let mut files = Files::new();
+        let id = files.add("whatever", src);
+
+        let got = find_duplicate_anchors(vec![id], &files);
+
+        assert!(got.is_empty());
+    }
+
+    #[test]
+    fn links_can_be_ignored_with_comments() {
+        let src = r#"[checked](./first.md)
+<!-- linkcheck-disable-next-line -->
+[skipped](./second.md) and [also skipped](./third.md)
+[skipped](./fourth.md) <!-- linkcheck-ignore -->
+[checked](./fifth.md)
+"#;
+        let mut files = Files::new();
+        let id = files.add("whatever", src);
+
+        let (got, _) = extract(vec![id], &files);
+
+        let paths: Vec<_> = got.iter().map(|link| link.uri.path()).collect();
+        assert_eq!(paths, vec!["./first.md", "./fifth.md"]);
+    }
+
+    #[test]
+    fn ignore_a_single_line() {
+        let src = r#"[checked](./first.md)
+[skipped](./second.md) <!-- linkcheck-ignore-line --> [skipped](./third.md)
+[checked](./fourth.md)
+<!-- linkcheck-ignore-line -->
+[checked](./fifth.md)
+"#;
+        let mut files = Files::new();
+        let id = files.add("whatever", src);
+
+        let (_, got, _) = extract_with_suppressed(vec![id], &files);
+
+        let suppressed: Vec<_> =
+            got.iter().map(|link| link.uri.path()).collect();
+        assert_eq!(suppressed, vec!["./second.md", "./third.md"]);
+    }
+
+    #[test]
+    fn ignore_a_whole_block() {
+        let src = r#"[checked](./first.md)
+
+<!-- linkcheck-ignore-block -->
+- [skipped](./second.md)
+- [skipped](./third.md)
+
+<!-- linkcheck-ignore -->
+[checked](./fourth.md)
+"#;
+        let mut files = Files::new();
+        let id = files.add("whatever", src);
+
+        let (links, suppressed, _) = extract_with_suppressed(vec![id], &files);
+
+        let checked: Vec<_> =
+            links.iter().map(|link| link.uri.path()).collect();
+        assert_eq!(checked, vec!["./first.md", "./fourth.md"]);
+        let suppressed: Vec<_> =
+            suppressed.iter().map(|link| link.uri.path()).collect();
+        assert_eq!(suppressed, vec!["./second.md", "./third.md"]);
+    }
+
+    #[test]
+    fn find_links_in_html() {
+        let src = r#"See <a href="./inline.md#top">this</a> or [that](./markdown.md).
+
+<div>
+  <img src='images/logo.png' alt="logo">
+  <a href="javascript:alert(1)">script</a>
+  <img src="data:image/png;base64,iVBORw0KGgo=">
+  <!-- <a href="./commented-out.md">old</a> -->
+  <A HREF="https://example.com/">shouting</A>
+</div>
+"#;
+        let mut files = Files::new();
+        let id = files.add("whatever", src);
+
+        let (got, _) = extract(vec![id], &files);
+
+        let targets: Vec<_> =
+            got.iter().map(|link| link.uri.to_string()).collect();
+        assert_eq!(
+            targets,
+            vec![
+                "./inline.md",
+                "./markdown.md",
+                "images/logo.png",
+                "https://example.com/",
+            ]
+        );
+        assert_eq!(got[0].fragment.as_deref(), Some("top"));
+        // the span covers the attribute's value
+        let span = got[2].span;
+        assert_eq!(
+            &src[span.start().to_usize()..span.end().to_usize()],
+            "images/logo.png"
+        );
+    }
+
+    #[test]
+    fn find_reference_definitions_nobody_uses() {
+        let src = r#"See [the book][The  Rust Book] and [std].[^note]
+[![A badge](./badge.svg)][Badge
+Link]
+
+[badge link]: https://example.com/badge
+[the rust book]: https://doc.rust-lang.org/book/
+[std]: https://doc.rust-lang.org/std/
+[unused]: https://example.com/
+[^note]: Footnotes aren't references.
+
+```
+[in a code block]: https://example.com/
+```
+"#;
+        let mut files = Files::new();
+        let id = files.add("whatever", src);
+
+        let got = find_unused_references(vec![id], &files);
+
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].label, "unused");
+        let span = got[0].span;
+        assert_eq!(
+            &src[span.start().to_usize()..span.end().to_usize()],
+            "[unused]: https://example.com/"
+        );
+    }
+
+    #[test]
+    fn resolve_scheme_relative_links() {
+        let mut files = Files::new();
+        let id = files.add("whatever", "");
+        let inputs = vec![
+            ("//example.com/page?q=1#top", true, "https://example.com/page?q=1"),
+            ("http://example.com/", false, "http://example.com/"),
+            ("./chapter_1.md", false, "./chapter_1.md"),
+            ("/absolute.md", false, "/absolute.md"),
+        ];
+
+        for (src, scheme_relative, should_be) in inputs {
+            let link = Link::parse(src, 0..src.len(), id).unwrap();
+            assert_eq!(link.is_scheme_relative(), scheme_relative, "{}", src);
+
+            let got = link.with_default_scheme("https");
+            assert_eq!(got.uri.to_string(), should_be, "{}", src);
+            assert_eq!(got.fragment, link.fragment);
+        }
+    }
 }
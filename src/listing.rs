@@ -0,0 +1,281 @@
+//! Listing a book's links without checking them (see
+//! [`Config::list_links`]).
+//!
+//! [`Config::list_links`]: crate::Config::list_links
+
+use crate::{contact::ContactScheme, Action, Config, Link};
+use codespan::Files;
+use failure::Error;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Display, Formatter},
+    io::Write,
+    path::Path,
+};
+
+/// The broad kind of thing a [`Link`] points at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LinkKind {
+    /// A page on the internet (`http://`, `https://`, or scheme-relative
+    /// links like `//example.com/`).
+    Web,
+    /// Another file in the book, possibly with a fragment.
+    File,
+    /// An anchor on the same page (e.g. `#installation`).
+    Fragment,
+    /// A `mailto:` or `tel:` link.
+    Contact,
+    /// A scheme we don't know how to check.
+    Other,
+}
+
+impl LinkKind {
+    /// Figure out what kind of link this is.
+    pub fn of(link: &Link) -> LinkKind {
+        if ContactScheme::of(link).is_some() {
+            return LinkKind::Contact;
+        }
+        if link.is_scheme_relative() {
+            return LinkKind::Web;
+        }
+
+        match link.uri.scheme_str() {
+            Some("http") | Some("https") => LinkKind::Web,
+            None if link.uri.path().is_empty() && link.fragment.is_some() => {
+                LinkKind::Fragment
+            },
+            None | Some("file") => LinkKind::File,
+            _ => LinkKind::Other,
+        }
+    }
+
+    /// The name used when printing this kind of link.
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkKind::Web => "web",
+            LinkKind::File => "file",
+            LinkKind::Fragment => "fragment",
+            LinkKind::Contact => "contact",
+            LinkKind::Other => "other",
+        }
+    }
+}
+
+impl Display for LinkKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+/// Write every link and what checking it would involve (see [`Action`]),
+/// grouped by the file it was found in, followed by a count of each
+/// [`Action`] for the lines which were written.
+///
+/// Files are sorted by name, and each file's links are sorted by
+/// [`LinkKind`] then target with duplicates removed, so the output is the
+/// same every time and diffs cleanly. Nothing is fetched and the cache isn't
+/// touched.
+pub fn write_link_list<W: Write>(
+    links: &[Link],
+    files: &Files,
+    cfg: &Config,
+    src_dir: &Path,
+    mut writer: W,
+) -> Result<(), Error> {
+    let mut by_file: BTreeMap<&str, BTreeSet<(LinkKind, String, Action)>> =
+        BTreeMap::new();
+
+    for link in links {
+        let action = Action::of(link, cfg, src_dir, files);
+        by_file.entry(files.name(link.file)).or_default().insert((
+            LinkKind::of(link),
+            target(link),
+            action,
+        ));
+    }
+
+    let (mut fetch, mut local, mut skip) = (0, 0, 0);
+
+    for (name, links) in by_file {
+        writeln!(writer, "{}", name)?;
+
+        for (kind, target, action) in links {
+            match action {
+                Action::Fetch => fetch += 1,
+                Action::Local => local += 1,
+                Action::Skip(_) => skip += 1,
+            }
+
+            match action {
+                Action::Skip(reason) => writeln!(
+                    writer,
+                    "  {:<8} skip  {} ({})",
+                    kind, target, reason
+                )?,
+                _ => {
+                    writeln!(writer, "  {:<8} {:<5} {}", kind, action, target)?
+                },
+            }
+        }
+    }
+
+    writeln!(
+        writer,
+        "Would fetch {} and check {}, skipping {}",
+        count(fetch, "web link"),
+        count(local, "local link"),
+        skip
+    )?;
+
+    Ok(())
+}
+
+/// Something like "1 web link" or "2 web links".
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("{} {}", n, noun)
+    } else {
+        format!("{} {}s", n, noun)
+    }
+}
+
+/// The link as it would have been written, fragment and all.
+fn target(link: &Link) -> String {
+    match link.fragment {
+        Some(ref fragment) => format!("{}#{}", link.uri, fragment),
+        None => link.uri.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{MockServer, Response},
+        HashedRegex,
+    };
+
+    fn links_in(files: &mut Files, name: &str, src: &str) -> Vec<Link> {
+        let id = files.add(name, src);
+        crate::links::extract(vec![id], files).0
+    }
+
+    #[test]
+    fn classify_links() {
+        let inputs = vec![
+            ("https://example.com/", LinkKind::Web),
+            ("http://example.com/page#section", LinkKind::Web),
+            ("//example.com/page", LinkKind::Web),
+            ("./chapter_1.md", LinkKind::File),
+            ("../chapter_1.md#intro", LinkKind::File),
+            ("/absolute.html", LinkKind::File),
+            ("#installation", LinkKind::Fragment),
+            ("mailto:alice@example.com", LinkKind::Contact),
+            ("tel:+1-201-555-0123", LinkKind::Contact),
+            ("ftp://example.com/file.txt", LinkKind::Other),
+        ];
+        let mut files = Files::new();
+        let id = files.add("index.md", "");
+
+        for (src, should_be) in inputs {
+            let link = Link::parse(src, 0..src.len(), id).unwrap();
+            assert_eq!(LinkKind::of(&link), should_be, "{}", src);
+            assert_eq!(target(&link), src);
+        }
+    }
+
+    #[test]
+    fn links_are_grouped_by_file_and_sorted() {
+        let mut files = Files::new();
+        let mut links = links_in(
+            &mut files,
+            "second.md",
+            "[a](https://example.com/)\n[b](#top)\n[c](./first.md)\n[d](https://example.com/)",
+        );
+        links.extend(links_in(
+            &mut files,
+            "first.md",
+            "[a](https://b.example.com/)\n[b](https://a.example.com/)",
+        ));
+        let mut buffer = Vec::new();
+
+        write_link_list(
+            &links,
+            &files,
+            &Config::default(),
+            Path::new("/src"),
+            &mut buffer,
+        )
+        .unwrap();
+
+        let got = String::from_utf8(buffer).unwrap();
+        let should_be = "first.md
+  web      skip  https://a.example.com/ (follow-web-links is off)
+  web      skip  https://b.example.com/ (follow-web-links is off)
+second.md
+  web      skip  https://example.com/ (follow-web-links is off)
+  file     local ./first.md
+  fragment local #top
+Would fetch 0 web links and check 2 local links, skipping 3
+";
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn show_what_a_run_would_do_without_touching_the_network() {
+        let server = MockServer::start(|_| Response::new(200));
+        let src = format!(
+            "[a]({})\n[b](https://internal.example.com/)\n[c](./chapter.md)\n[d](#top)\n[e](ftp://example.com/)",
+            server.url("/page")
+        );
+        let mut files = Files::new();
+        let links = links_in(&mut files, "index.md", &src);
+        let cfg = Config {
+            follow_web_links: true,
+            exclude: vec![HashedRegex::new("internal").unwrap()],
+            ..Default::default()
+        };
+        let src_dir = Path::new("/src");
+        let mut buffer = Vec::new();
+
+        write_link_list(&links, &files, &cfg, src_dir, &mut buffer).unwrap();
+
+        let got = String::from_utf8(buffer).unwrap();
+        let should_be = format!(
+            "index.md
+  web      fetch {}
+  web      skip  https://internal.example.com/ (excluded)
+  file     local ./chapter.md
+  fragment local #top
+  other    skip  ftp://example.com/ (unknown scheme)
+Would fetch 1 web link and check 2 local links, skipping 2
+",
+            server.url("/page")
+        );
+        assert_eq!(got, should_be);
+        assert!(server.requests().is_empty());
+
+        // web links are skipped unless we'd normally follow them
+        let link = &links[0];
+        let offline = Config {
+            offline: true,
+            ..cfg.clone()
+        };
+        assert_eq!(
+            Action::of(link, &offline, src_dir, &files),
+            Action::Skip("offline")
+        );
+        assert_eq!(
+            Action::of(link, &Config::default(), src_dir, &files),
+            Action::Skip("follow-web-links is off")
+        );
+        let loopback = Config {
+            skip_loopback: true,
+            ..cfg.clone()
+        };
+        assert_eq!(
+            Action::of(link, &loopback, src_dir, &files),
+            Action::Skip("loopback address")
+        );
+    }
+}
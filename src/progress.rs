@@ -0,0 +1,153 @@
+//! A simple progress indicator, so checking thousands of web links doesn't
+//! look like a hang (see [`Config::show_progress`]).
+//!
+//! Progress is also logged at the `info` level every time another tenth of
+//! the links have been checked, for runs where stderr isn't a terminal.
+//!
+//! [`Config::show_progress`]: crate::Config::show_progress
+
+use crate::Config;
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+/// Keeps track of how many links have been checked, redrawing a single line
+/// on stderr whenever something changes.
+#[derive(Debug)]
+pub(crate) struct Progress {
+    total: usize,
+    enabled: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    checked: usize,
+    host: String,
+    /// The last tenth of the links we logged about.
+    logged: usize,
+}
+
+impl Progress {
+    pub fn new(total: usize, cfg: &Config) -> Progress {
+        Progress {
+            total,
+            enabled: total > 0 && cfg.shows_progress(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// We're about to send a request to this host.
+    pub fn contacting(&self, host: &str) {
+        self.update(|state| state.host = host.to_string());
+    }
+
+    /// Another `count` links have been checked.
+    pub fn checked(&self, count: usize) {
+        self.update(|state| state.checked += count);
+    }
+
+    /// Clear the progress line so it doesn't get mixed up with diagnostics.
+    pub fn finish(&self) {
+        if self.enabled {
+            let _ = write!(io::stderr(), "\r\x1b[K");
+        }
+    }
+
+    fn update<F>(&self, change: F)
+    where
+        F: FnOnce(&mut State),
+    {
+        if self.total == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("Lock was poisoned");
+        change(&mut state);
+
+        let tenths = state.checked * 10 / self.total;
+        if tenths > state.logged {
+            state.logged = tenths;
+            log::info!("Checked {}/{} web links", state.checked, self.total);
+        }
+
+        if self.enabled {
+            // Note: keep holding the lock so lines from different threads
+            // can't be interleaved
+            let line = render(state.checked, self.total, &state.host);
+            let _ = write!(io::stderr(), "\r\x1b[K{}", line);
+        }
+    }
+}
+
+fn render(checked: usize, total: usize, host: &str) -> String {
+    let width = total.to_string().len();
+
+    if host.is_empty() {
+        format!("Checked {:>w$}/{} links", checked, total, w = width)
+    } else {
+        format!(
+            "Checked {:>w$}/{} links (contacting {})",
+            checked,
+            total,
+            host,
+            w = width
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_the_progress_line() {
+        assert_eq!(render(0, 120, ""), "Checked   0/120 links");
+        assert_eq!(
+            render(42, 120, "example.com"),
+            "Checked  42/120 links (contacting example.com)"
+        );
+    }
+
+    #[test]
+    fn log_every_tenth_of_the_links() {
+        let cfg = Config {
+            show_progress: Some(false),
+            ..Default::default()
+        };
+
+        let logs = crate::test_utils::capture_logs(|| {
+            let progress = Progress::new(40, &cfg);
+            for _ in 0..40 {
+                progress.checked(1);
+            }
+        });
+
+        let messages: Vec<_> = logs
+            .iter()
+            .filter(|(level, _)| *level == log::Level::Info)
+            .map(|(_, msg)| msg.as_str())
+            .collect();
+        assert_eq!(messages.len(), 10);
+        assert_eq!(messages[0], "Checked 4/40 web links");
+        assert_eq!(messages[9], "Checked 40/40 web links");
+    }
+
+    #[test]
+    fn progress_can_be_turned_off() {
+        let cfg = Config {
+            show_progress: Some(false),
+            ..Default::default()
+        };
+        assert!(!Progress::new(10, &cfg).enabled);
+
+        let cfg = Config {
+            show_progress: Some(true),
+            ..Default::default()
+        };
+        assert!(Progress::new(10, &cfg).enabled);
+        // there's nothing to show when there are no links
+        assert!(!Progress::new(0, &cfg).enabled);
+    }
+}
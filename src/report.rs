@@ -0,0 +1,1146 @@
+//! Machine-readable reports of the link checker's results.
+
+use crate::{
+    validate::{
+        disallowed_message, disallowed_severity, policy_severity,
+        ORPHAN_PAGE_MESSAGE,
+    },
+    BrokenLinkCount, Category, Config, InvalidLink, Link, Reason,
+    ValidationOutcome,
+};
+use codespan::{FileId, Files, Span};
+use codespan_reporting::diagnostic::Severity;
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    io::{Read, Write},
+    path::Path,
+};
+
+/// The result of checking a single link, as written to the JSON report (see
+/// [`crate::Config::json_report`]).
+///
+/// Fields are only ever added to this format, never renamed or removed, so
+/// downstream tooling can safely deserialize it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LinkReport {
+    /// The file the link was found in.
+    pub file: String,
+    /// The (1-based) line the link starts on.
+    pub line: usize,
+    /// The (1-based) column the link starts at, counted in characters.
+    #[serde(default)]
+    pub column: usize,
+    /// The (1-based) line the link ends on.
+    #[serde(default)]
+    pub end_line: usize,
+    /// The (1-based) column just after the end of the link.
+    #[serde(default)]
+    pub end_column: usize,
+    /// The link as it was written in the markdown source.
+    pub link: String,
+    /// What the link points to.
+    pub target: String,
+    /// The result of checking the link.
+    pub status: LinkStatus,
+    /// Why the link was broken, ignored, or produced a warning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// The category a [`LinkReport`] falls into.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkStatus {
+    /// The link is fine.
+    Valid,
+    /// The link is broken.
+    Broken,
+    /// The link wasn't checked.
+    Ignored,
+    /// The link may be a problem (e.g. an incomplete link or missing anchor),
+    /// depending on the [`crate::WarningPolicy`].
+    Warning,
+}
+
+impl ValidationOutcome {
+    /// Summarise every link in this [`ValidationOutcome`]. Warnings are always
+    /// included, regardless of the [`crate::WarningPolicy`].
+    pub fn report(&self, files: &Files) -> Vec<LinkReport> {
+        let mut records = Vec::new();
+
+        for link in &self.valid_links {
+            records.push(record(link, files, LinkStatus::Valid, None));
+        }
+
+        for invalid in &self.invalid_links {
+            let status = if invalid.reason.is_warning() {
+                LinkStatus::Warning
+            } else {
+                LinkStatus::Broken
+            };
+            let msg = invalid.reason.to_string();
+            records.push(record(&invalid.link, files, status, Some(msg)));
+        }
+
+        for link in &self.ignored {
+            let msg = String::from("Skipped because of the configuration");
+            records.push(record(link, files, LinkStatus::Ignored, Some(msg)));
+        }
+
+        for link in &self.suppressed {
+            let msg =
+                String::from("Skipped because of a linkcheck-ignore comment");
+            records.push(record(link, files, LinkStatus::Ignored, Some(msg)));
+        }
+
+        for link in &self.skipped_offline {
+            let msg = String::from("Skipped because we're offline");
+            records.push(record(link, files, LinkStatus::Ignored, Some(msg)));
+        }
+
+        for link in &self.unknown_schema {
+            let msg = String::from("Unknown URI scheme");
+            records.push(record(link, files, LinkStatus::Ignored, Some(msg)));
+        }
+
+        for incomplete in &self.incomplete_links {
+            let span = crate::validate::resolve_incomplete_link_span(
+                incomplete, files,
+            );
+            let (line, column) = position(files, incomplete.file, span.start());
+            let (end_line, end_column) =
+                position(files, incomplete.file, span.end());
+            records.push(LinkReport {
+                file: files.name(incomplete.file).to_string(),
+                line,
+                column,
+                end_line,
+                end_column,
+                link: format!("[{}]", incomplete.text),
+                target: String::new(),
+                status: LinkStatus::Warning,
+                message: Some(format!(
+                    "Did you forget to define a URL for `{}`?",
+                    incomplete.text
+                )),
+            });
+        }
+
+        records
+    }
+}
+
+/// The SARIF rules we report against, and a short description of each.
+const SARIF_RULES: &[(&str, &str)] = &[
+    ("missing-file", "The linked file doesn't exist"),
+    (
+        "forbidden-parent-directory",
+        "The link escapes the book's source directory",
+    ),
+    ("bad-anchor", "The linked page has no matching anchor"),
+    ("broken-web-link", "The web page couldn't be retrieved"),
+    ("incomplete-link", "The link reference was never defined"),
+    (
+        "duplicate-anchor",
+        "A heading has the same anchor as an earlier heading",
+    ),
+    ("insecure-link", "The link uses plain HTTP"),
+    (
+        "malformed-contact-link",
+        "A `mailto:` or `tel:` link is malformed",
+    ),
+    (
+        "unresolvable-email-domain",
+        "The domain in a `mailto:` link doesn't resolve",
+    ),
+    ("orphan-page", "No other chapter links to this page"),
+    ("unused-reference", "Nothing links to the reference definition"),
+    ("malformed-url", "The web link isn't a well-formed URL"),
+    ("redirected-link", "The web link redirects somewhere else"),
+    (
+        "disallowed-by-robots",
+        "The web link wasn't checked because robots.txt disallows it",
+    ),
+    ("rejected-link", "A custom validator rejected the link"),
+];
+
+impl ValidationOutcome {
+    /// Generate a [SARIF 2.1.0][sarif] log of every broken link and warning,
+    /// suitable for uploading to GitHub code scanning.
+    ///
+    /// File paths are made relative to `base_dir` (e.g. the book's `src/`
+    /// directory relative to the repository root). Each result's level is
+    /// the same as the severity of the equivalent diagnostic (see
+    /// [`InvalidLink::severity()`]), and incomplete links or duplicate anchors
+    /// follow [`Config::local_warning_policy_for()`].
+    ///
+    /// [sarif]: https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html
+    pub fn sarif(
+        &self,
+        files: &Files,
+        cfg: &Config,
+        base_dir: &Path,
+    ) -> Value {
+        let local_level = |category| {
+            policy_severity(cfg.local_warning_policy_for(category))
+                .map(sarif_level)
+        };
+        let location = |file: FileId, span: Span| {
+            let uri = base_dir.join(files.name(file));
+            let (start_line, start_column) =
+                match position(files, file, span.start()) {
+                    (0, _) => (1, 1),
+                    position => position,
+                };
+            let (end_line, end_column) =
+                match position(files, file, span.end()) {
+                    (0, _) => (start_line, start_column),
+                    position => position,
+                };
+
+            json!({
+                "physicalLocation": {
+                    "artifactLocation": {
+                        "uri": uri.to_string_lossy().replace('\\', "/"),
+                    },
+                    "region": {
+                        "startLine": start_line,
+                        "startColumn": start_column,
+                        "endLine": end_line,
+                        "endColumn": end_column,
+                    },
+                },
+            })
+        };
+        let mut results = Vec::new();
+
+        for invalid in &self.invalid_links {
+            let level = match invalid.severity(cfg) {
+                Some(severity) => sarif_level(severity),
+                None => continue,
+            };
+            let rule_id = invalid.reason.category().name();
+            let link = &invalid.link;
+
+            results.push(json!({
+                "ruleId": rule_id,
+                "level": level,
+                "message": { "text": invalid.to_string() },
+                "locations": [location(link.file, link.span)],
+            }));
+        }
+
+        for insecure in &self.insecure_links {
+            let level = match insecure.severity(cfg) {
+                Some(severity) => sarif_level(severity),
+                None => continue,
+            };
+            let link = &insecure.link;
+            let msg = match insecure.https_url {
+                Some(ref https_url) => format!(
+                    "\"{}\" is also available as \"{}\"",
+                    link.uri, https_url
+                ),
+                None => format!("\"{}\" uses plain HTTP", link.uri),
+            };
+
+            results.push(json!({
+                "ruleId": "insecure-link",
+                "level": level,
+                "message": { "text": msg },
+                "locations": [location(link.file, link.span)],
+            }));
+        }
+
+        for redirected in &self.redirected_links {
+            let level = match redirected.severity(cfg) {
+                Some(severity) => sarif_level(severity),
+                None => continue,
+            };
+            let link = &redirected.link;
+
+            results.push(json!({
+                "ruleId": "redirected-link",
+                "level": level,
+                "message": { "text": redirected.to_string() },
+                "locations": [location(link.file, link.span)],
+            }));
+        }
+
+        for link in &self.disallowed_by_robots {
+            let level = match disallowed_severity(link, cfg) {
+                Some(severity) => sarif_level(severity),
+                None => continue,
+            };
+
+            results.push(json!({
+                "ruleId": "disallowed-by-robots",
+                "level": level,
+                "message": { "text": disallowed_message(link) },
+                "locations": [location(link.file, link.span)],
+            }));
+        }
+
+        if let Some(level) = local_level(Category::IncompleteLink) {
+            for incomplete in &self.incomplete_links {
+                let span = crate::validate::resolve_incomplete_link_span(
+                    incomplete, files,
+                );
+                let msg = format!(
+                    "Did you forget to define a URL for `{}`?",
+                    incomplete.text
+                );
+
+                results.push(json!({
+                    "ruleId": "incomplete-link",
+                    "level": level,
+                    "message": { "text": msg },
+                    "locations": [location(incomplete.file, span)],
+                }));
+            }
+        }
+
+        if let Some(level) = local_level(Category::DuplicateAnchor) {
+            for duplicate in &self.duplicate_anchors {
+                let msg = format!(
+                    "This heading's anchor, `#{}`, is already used",
+                    duplicate.anchor
+                );
+
+                results.push(json!({
+                    "ruleId": "duplicate-anchor",
+                    "level": level,
+                    "message": { "text": msg },
+                    "locations": [location(duplicate.file, duplicate.second)],
+                    "relatedLocations": [
+                        location(duplicate.file, duplicate.first),
+                    ],
+                }));
+            }
+        }
+
+        if let Some(level) = local_level(Category::OrphanPage) {
+            for &file in &self.orphan_pages {
+                results.push(json!({
+                    "ruleId": "orphan-page",
+                    "level": level,
+                    "message": { "text": ORPHAN_PAGE_MESSAGE },
+                    "locations": [location(file, Span::initial())],
+                }));
+            }
+        }
+
+        if let Some(level) = local_level(Category::UnusedReference) {
+            for unused in &self.unused_references {
+                let msg = format!("Nothing links to `[{}]`", unused.label);
+
+                results.push(json!({
+                    "ruleId": "unused-reference",
+                    "level": level,
+                    "message": { "text": msg },
+                    "locations": [location(unused.file, unused.span)],
+                }));
+            }
+        }
+
+        let rules: Vec<_> = SARIF_RULES
+            .iter()
+            .map(|(id, description)| {
+                json!({
+                    "id": id,
+                    "shortDescription": { "text": description },
+                })
+            })
+            .collect();
+
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                        "rules": rules,
+                    },
+                },
+                "results": results,
+            }],
+        })
+    }
+}
+
+impl ValidationOutcome {
+    /// Generate a JUnit XML report where each chapter is a test case, failing
+    /// with one `<failure>` per broken link it contains.
+    ///
+    /// Only broken links which would be reported as errors (see
+    /// [`InvalidLink::severity()`]) count as failures.
+    pub fn junit(
+        &self,
+        files: &Files,
+        chapters: &[FileId],
+        cfg: &Config,
+    ) -> String {
+        let mut failures: HashMap<FileId, Vec<&InvalidLink>> = HashMap::new();
+
+        for invalid in &self.invalid_links {
+            if invalid.severity(cfg) == Some(Severity::Error) {
+                failures.entry(invalid.link.file).or_default().push(invalid);
+            }
+        }
+
+        let failed = chapters
+            .iter()
+            .filter(|id| failures.contains_key(id))
+            .count();
+        let mut xml =
+            String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(&format!(
+            "<testsuites tests=\"{0}\" failures=\"{1}\" errors=\"0\">\n",
+            chapters.len(),
+            failed
+        ));
+        xml.push_str(&format!(
+            "  <testsuite name=\"{0}\" tests=\"{1}\" failures=\"{2}\" errors=\"0\" skipped=\"0\">\n",
+            env!("CARGO_PKG_NAME"),
+            chapters.len(),
+            failed
+        ));
+
+        for &chapter in chapters {
+            let name = xml_escape(files.name(chapter));
+            let broken = failures.get(&chapter).map(Vec::as_slice);
+
+            match broken {
+                None | Some([]) => xml.push_str(&format!(
+                    "    <testcase name=\"{0}\" classname=\"linkcheck\" />\n",
+                    name
+                )),
+                Some(broken) => {
+                    xml.push_str(&format!(
+                        "    <testcase name=\"{0}\" classname=\"linkcheck\">\n",
+                        name
+                    ));
+
+                    for invalid in broken {
+                        let link = &invalid.link;
+                        let location = files
+                            .location(link.file, link.span.start())
+                            .map(|l| {
+                                format!(
+                                    "{}:{}",
+                                    l.line.to_usize() + 1,
+                                    l.column.to_usize() + 1
+                                )
+                            })
+                            .unwrap_or_default();
+                        xml.push_str(&format!(
+                            "      <failure message=\"{0}\" type=\"broken-link\">{1}:{2}: {0}</failure>\n",
+                            xml_escape(&invalid.to_string()),
+                            name,
+                            location,
+                        ));
+                    }
+
+                    xml.push_str("    </testcase>\n");
+                },
+            }
+        }
+
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+}
+
+/// A tally of how checking every link went, printed at the end of a run (see
+/// [`Config::print_summary`]).
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Summary {
+    /// The number of files which were scanned for links.
+    pub files: usize,
+    /// Links which are fine.
+    pub ok: usize,
+    /// Problems which were reported as warnings.
+    pub warnings: usize,
+    /// Problems which were reported as errors.
+    pub errors: usize,
+    /// Links which weren't checked, or whose problems were ignored because
+    /// of the [`crate::WarningPolicy`].
+    pub ignored: usize,
+    /// Reported problems with web links.
+    pub web_failures: usize,
+    /// Reported problems with links to local files (e.g. a missing file).
+    pub file_failures: usize,
+    /// Reported links to a heading or element which doesn't exist.
+    pub anchor_failures: usize,
+    /// Reported problems with `mailto:` or `tel:` links.
+    pub contact_failures: usize,
+    /// Reported problems which aren't about a broken link, like incomplete
+    /// links, redirects, or orphaned pages.
+    pub other_failures: usize,
+}
+
+impl Summary {
+    /// The total number of links.
+    pub fn links(&self) -> usize {
+        self.ok
+            + self.ignored
+            + self.web_failures
+            + self.file_failures
+            + self.anchor_failures
+            + self.contact_failures
+    }
+
+    fn add_problem(&mut self, severity: Severity) {
+        match severity {
+            Severity::Bug | Severity::Error => self.errors += 1,
+            _ => self.warnings += 1,
+        }
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Checked {} links across {} files: {} ok, {} warnings, {} errors",
+            self.links(),
+            self.files,
+            self.ok,
+            self.warnings,
+            self.errors
+        )?;
+        if self.ignored > 0 {
+            write!(f, ", {} ignored", self.ignored)?;
+        }
+
+        if self.warnings + self.errors > 0 {
+            write!(
+                f,
+                " ({} web, {} file, {} anchor",
+                self.web_failures, self.file_failures, self.anchor_failures
+            )?;
+            if self.contact_failures > 0 {
+                write!(f, ", {} contact", self.contact_failures)?;
+            }
+            if self.other_failures > 0 {
+                write!(f, ", {} other", self.other_failures)?;
+            }
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ValidationOutcome {
+    /// Tally up the results, using the same severities as the diagnostics
+    /// (see [`InvalidLink::severity()`]). Every problem which gets reported
+    /// counts as a warning or an error, not just broken links.
+    ///
+    /// With [`BrokenLinkCount::UniqueUrls`], links to a broken URL which was
+    /// already counted are skipped entirely.
+    pub fn summary(&self, chapters: &[FileId], cfg: &Config) -> Summary {
+        let mut summary = Summary {
+            files: chapters.len(),
+            ok: self.valid_links.len(),
+            ignored: self.ignored.len()
+                + self.suppressed.len()
+                + self.skipped_offline.len()
+                + self.unknown_schema.len()
+                + self.disallowed_by_robots.len(),
+            ..Default::default()
+        };
+
+        let mut counted_urls = HashSet::new();
+
+        for invalid in &self.invalid_links {
+            if cfg.broken_link_count == BrokenLinkCount::UniqueUrls {
+                if let Some(url) = crate::validate::group_key(&invalid.link) {
+                    if !counted_urls.insert(url) {
+                        continue;
+                    }
+                }
+            }
+
+            match invalid.severity(cfg) {
+                Some(severity) => summary.add_problem(severity),
+                None => {
+                    summary.ignored += 1;
+                    continue;
+                },
+            }
+
+            match invalid.reason {
+                Reason::AnchorNotFound(_) => summary.anchor_failures += 1,
+                Reason::FileNotFound | Reason::TraversesParentDirectories(_) => {
+                    summary.file_failures += 1
+                },
+                Reason::MalformedEmailAddress(_)
+                | Reason::MalformedPhoneNumber(_)
+                | Reason::UnresolvableEmailDomain(_) => {
+                    summary.contact_failures += 1
+                },
+                _ => summary.web_failures += 1,
+            }
+        }
+
+        let local = |category: Category, count: usize| {
+            let severity =
+                policy_severity(cfg.local_warning_policy_for(category));
+            std::iter::repeat_n(severity, count)
+        };
+        let other_problems = self
+            .insecure_links
+            .iter()
+            .map(|insecure| insecure.severity(cfg))
+            .chain(self.redirected_links.iter().map(|r| r.severity(cfg)))
+            .chain(
+                self.disallowed_by_robots
+                    .iter()
+                    .map(|link| disallowed_severity(link, cfg)),
+            )
+            .chain(local(Category::IncompleteLink, self.incomplete_links.len()))
+            .chain(local(
+                Category::DuplicateAnchor,
+                self.duplicate_anchors.len(),
+            ))
+            .chain(local(Category::OrphanPage, self.orphan_pages.len()))
+            .chain(local(
+                Category::UnusedReference,
+                self.unused_references.len(),
+            ))
+            .flatten();
+
+        for severity in other_problems {
+            summary.add_problem(severity);
+            summary.other_failures += 1;
+        }
+
+        summary
+    }
+}
+
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Bug | Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note | Severity::Help => "note",
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Write a JSON report.
+pub fn write_json_report<W: Write>(
+    records: &[LinkReport],
+    writer: W,
+) -> Result<(), Error> {
+    serde_json::to_writer_pretty(writer, records)?;
+    Ok(())
+}
+
+/// Read a report previously written with [`write_json_report()`].
+pub fn read_json_report<R: Read>(reader: R) -> Result<Vec<LinkReport>, Error> {
+    serde_json::from_reader(reader).map_err(Error::from)
+}
+
+fn record(
+    link: &Link,
+    files: &Files,
+    status: LinkStatus,
+    message: Option<String>,
+) -> LinkReport {
+    let mut target = link.uri.to_string();
+    if let Some(ref fragment) = link.fragment {
+        target.push('#');
+        target.push_str(fragment);
+    }
+
+    let (line, column) = position(files, link.file, link.span.start());
+    let (end_line, end_column) = position(files, link.file, link.span.end());
+
+    LinkReport {
+        file: files.name(link.file).to_string(),
+        line,
+        column,
+        end_line,
+        end_column,
+        link: files
+            .source_slice(link.file, link.span)
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        target,
+        status,
+        message,
+    }
+}
+
+/// The (1-based) line and column for a byte index.
+fn position(
+    files: &Files,
+    file: codespan::FileId,
+    index: codespan::ByteIndex,
+) -> (usize, usize) {
+    files
+        .location(file, index)
+        .map(|location| {
+            (location.line.to_usize() + 1, location.column.to_usize() + 1)
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::policy, IncompleteLink, RedirectedLink, WarningPolicy,
+    };
+
+    #[test]
+    fn round_trip_a_report() {
+        let src = "[valid](./chapter.md)\n[broken](./missing.md)\n\n[skipped](https://example.com/)\n[incomplete]\n";
+        let mut files = Files::new();
+        let id = files.add("chapter.md", src);
+        let link = |text: &str, target: &str| {
+            let start = src.find(text).unwrap();
+            Link::parse(target, start..start + text.len(), id).unwrap()
+        };
+        let outcome = ValidationOutcome {
+            valid_links: vec![link("[valid](./chapter.md)", "./chapter.md")],
+            invalid_links: vec![InvalidLink {
+                link: link("[broken](./missing.md)", "./missing.md"),
+                reason: Reason::FileNotFound,
+            }],
+            ignored: vec![link(
+                "[skipped](https://example.com/)",
+                "https://example.com/",
+            )],
+            incomplete_links: vec![IncompleteLink {
+                text: String::from("incomplete"),
+                file: id,
+            }],
+            ..Default::default()
+        };
+
+        let records = outcome.report(&files);
+        let mut buffer = Vec::new();
+        write_json_report(&records, &mut buffer).unwrap();
+        let got = read_json_report(buffer.as_slice()).unwrap();
+
+        assert_eq!(got, records);
+        let statuses: Vec<_> = got.iter().map(|r| (r.line, r.status)).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                (1, LinkStatus::Valid),
+                (2, LinkStatus::Broken),
+                (4, LinkStatus::Ignored),
+                (5, LinkStatus::Warning),
+            ]
+        );
+        assert_eq!(got[1].link, "[broken](./missing.md)");
+        assert_eq!(got[1].target, "./missing.md");
+        assert_eq!(got[1].message.as_ref().unwrap(), "File not found");
+    }
+
+    #[test]
+    fn suppressed_links_are_reported_as_ignored() {
+        let src = "[skipped](./missing.md) <!-- linkcheck-ignore -->\n";
+        let mut files = Files::new();
+        let id = files.add("chapter.md", src);
+        let (links, suppressed, _) =
+            crate::links::extract_with_suppressed(vec![id], &files);
+        assert!(links.is_empty());
+        let outcome = ValidationOutcome {
+            suppressed,
+            ..Default::default()
+        };
+
+        let records = outcome.report(&files);
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].status, LinkStatus::Ignored);
+        assert_eq!(
+            records[0].message.as_ref().unwrap(),
+            "Skipped because of a linkcheck-ignore comment"
+        );
+        assert_eq!(outcome.summary(&[id], &Config::default()).ignored, 1);
+    }
+
+    #[test]
+    fn tally_up_the_results() {
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let other = files.add("other.md", "");
+        let link = |target: &str| Link::parse(target, 0..1, id).unwrap();
+        let invalid = |target: &str, reason: Reason| InvalidLink {
+            link: link(target),
+            reason,
+        };
+        let outcome = ValidationOutcome {
+            valid_links: vec![link("./other.md"), link("https://example.com/")],
+            invalid_links: vec![
+                invalid("./missing.md", Reason::FileNotFound),
+                invalid(
+                    "./other.md#nope",
+                    Reason::AnchorNotFound(String::from("nope")),
+                ),
+                invalid(
+                    "https://example.com/missing",
+                    Reason::UnsuccessfulServerResponse(
+                        reqwest::StatusCode::NOT_FOUND,
+                    ),
+                ),
+            ],
+            ignored: vec![link("https://example.org/")],
+            ..Default::default()
+        };
+
+        let got = outcome.summary(&[id, other], &Config::default());
+
+        assert_eq!(
+            got,
+            Summary {
+                files: 2,
+                ok: 2,
+                warnings: 1,
+                errors: 2,
+                ignored: 1,
+                web_failures: 1,
+                file_failures: 1,
+                anchor_failures: 1,
+                contact_failures: 0,
+                other_failures: 0,
+            }
+        );
+        assert_eq!(got.links(), 6);
+        assert_eq!(
+            got.to_string(),
+            "Checked 6 links across 2 files: 2 ok, 1 warnings, 2 errors, 1 ignored (1 web, 1 file, 1 anchor)"
+        );
+
+        // ignored problems aren't failures
+        let got = outcome.summary(&[id], &policy(WarningPolicy::Ignore));
+        assert_eq!(got.warnings, 0);
+        assert_eq!(got.anchor_failures, 0);
+        assert_eq!(got.ignored, 2);
+    }
+
+    #[test]
+    fn problems_which_arent_broken_links_are_counted_too() {
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let link = Link::parse("http://example.com/", 0..1, id).unwrap();
+        let outcome = ValidationOutcome {
+            valid_links: vec![link.clone()],
+            incomplete_links: vec![IncompleteLink {
+                text: String::from("incomplete"),
+                file: id,
+            }],
+            redirected_links: vec![RedirectedLink {
+                link,
+                url: String::from("https://example.com/"),
+                permanent: true,
+            }],
+            orphan_pages: vec![id],
+            ..Default::default()
+        };
+        let got = outcome.summary(&[id], &policy(WarningPolicy::Warn));
+
+        assert_eq!(got.ok, 1);
+        assert_eq!(got.links(), 1);
+        assert_eq!(got.warnings, 3);
+        assert_eq!(got.other_failures, 3);
+        assert_eq!(
+            got.to_string(),
+            "Checked 1 links across 1 files: 1 ok, 3 warnings, 0 errors (0 web, 0 file, 0 anchor, 3 other)"
+        );
+
+        let got = outcome.summary(&[id], &policy(WarningPolicy::Ignore));
+        assert_eq!(got.warnings, 0);
+        assert_eq!(got.other_failures, 0);
+    }
+
+    #[test]
+    fn count_each_broken_url_once() {
+        let mut files = Files::new();
+        let chapters: Vec<_> = (1..=3)
+            .map(|i| files.add(format!("chapter_{}.md", i), ""))
+            .collect();
+        let not_found = |target: &str, id| InvalidLink {
+            link: Link::parse(target, 0..1, id).unwrap(),
+            reason: Reason::UnsuccessfulServerResponse(
+                reqwest::StatusCode::NOT_FOUND,
+            ),
+        };
+        let mut invalid_links: Vec<_> = chapters
+            .iter()
+            .map(|&id| not_found("https://example.com/missing", id))
+            .collect();
+        invalid_links.push(InvalidLink {
+            link: Link::parse("./missing.md", 0..1, chapters[0]).unwrap(),
+            reason: Reason::FileNotFound,
+        });
+        invalid_links.push(InvalidLink {
+            link: Link::parse("./missing.md", 0..1, chapters[1]).unwrap(),
+            reason: Reason::FileNotFound,
+        });
+        let outcome = ValidationOutcome {
+            invalid_links,
+            ..Default::default()
+        };
+
+        let got = outcome.summary(&chapters, &Config::default());
+        assert_eq!(got.errors, 5);
+
+        let cfg = Config {
+            broken_link_count: BrokenLinkCount::UniqueUrls,
+            ..Default::default()
+        };
+        let got = outcome.summary(&chapters, &cfg);
+        // relative links are still counted once per file
+        assert_eq!(got.errors, 3);
+        assert_eq!(got.web_failures, 1);
+        assert_eq!(got.file_failures, 2);
+    }
+
+    #[test]
+    fn columns_point_at_the_right_link() {
+        let src = "# Chapter\n\nSee [one](./one.md), [two](./missing.md), or [three](./three.md).\n";
+        let mut files = Files::new();
+        let id = files.add("chapter.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let outcome = ValidationOutcome {
+            invalid_links: vec![InvalidLink {
+                link: links[1].clone(),
+                reason: Reason::FileNotFound,
+            }],
+            ..Default::default()
+        };
+
+        let got = outcome.report(&files);
+
+        assert_eq!(got.len(), 1);
+        let record = &got[0];
+        assert_eq!(record.link, "[two](./missing.md)");
+        assert_eq!((record.line, record.column), (3, 22));
+        assert_eq!((record.end_line, record.end_column), (3, 41));
+        let line = src.lines().nth(2).unwrap();
+        assert_eq!(&line[record.column - 1..record.end_column - 1], record.link);
+
+        let sarif = outcome.sarif(&files, &Config::default(), Path::new(""));
+        let region = &sarif["runs"][0]["results"][0]["locations"][0]
+            ["physicalLocation"]["region"];
+        assert_eq!(region["startColumn"], 22);
+        assert_eq!(region["endColumn"], 41);
+    }
+
+    #[test]
+    fn the_json_format_is_stable() {
+        let record = LinkReport {
+            file: String::from("chapter.md"),
+            line: 3,
+            column: 5,
+            end_line: 3,
+            end_column: 23,
+            link: String::from("[link](./other.md)"),
+            target: String::from("./other.md"),
+            status: LinkStatus::Broken,
+            message: Some(String::from("File not found")),
+        };
+
+        let got = serde_json::to_value(&record).unwrap();
+
+        let should_be = serde_json::json!({
+            "file": "chapter.md",
+            "line": 3,
+            "column": 5,
+            "end-line": 3,
+            "end-column": 23,
+            "link": "[link](./other.md)",
+            "target": "./other.md",
+            "status": "broken",
+            "message": "File not found",
+        });
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn generate_a_sarif_log() {
+        let src = "[broken](./missing.md)\n[anchor](./chapter.md#nope)\n";
+        let mut files = Files::new();
+        let id = files.add("chapter.md", src);
+        let outcome = ValidationOutcome {
+            invalid_links: vec![
+                InvalidLink {
+                    link: Link::parse("./missing.md", 1..2, id).unwrap(),
+                    reason: Reason::FileNotFound,
+                },
+                InvalidLink {
+                    link: Link::parse("./chapter.md#nope", 24..25, id).unwrap(),
+                    reason: Reason::AnchorNotFound(String::from("nope")),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let got =
+            outcome.sarif(&files, &Config::default(), Path::new("src"));
+
+        assert_eq!(got["version"], "2.1.0");
+        let run = &got["runs"][0];
+        assert_eq!(run["tool"]["driver"]["name"], "mdbook-linkcheck");
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ruleId"], "missing-file");
+        assert_eq!(results[0]["level"], "error");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/chapter.md");
+        assert_eq!(location["region"]["startLine"], 1);
+        assert_eq!(location["region"]["startColumn"], 2);
+        assert_eq!(results[1]["ruleId"], "bad-anchor");
+        assert_eq!(results[1]["level"], "warning");
+        let location = &results[1]["locations"][0]["physicalLocation"];
+        assert_eq!(location["region"]["startLine"], 2);
+
+        let cfg = policy(WarningPolicy::Ignore);
+        let got = outcome.sarif(&files, &cfg, Path::new("src"));
+        assert_eq!(got["runs"][0]["results"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn sarif_log_for_a_small_book() {
+        let src = "# Intro\n\n[web](https://example.com/)\n[ref]\n";
+        let mut files = Files::new();
+        let id = files.add("intro.md", src);
+        let outcome = ValidationOutcome {
+            invalid_links: vec![InvalidLink {
+                link: Link::parse("https://example.com/", 9..35, id).unwrap(),
+                reason: Reason::UnsuccessfulServerResponse(
+                    reqwest::StatusCode::NOT_FOUND,
+                ),
+            }],
+            incomplete_links: vec![IncompleteLink {
+                text: String::from("ref"),
+                file: id,
+            }],
+            ..Default::default()
+        };
+
+        let got = outcome.sarif(&files, &Config::default(), Path::new(""));
+
+        let results = &got["runs"][0]["results"];
+        let should_be = json!([
+            {
+                "ruleId": "broken-web-link",
+                "level": "error",
+                "message": {
+                    "text": "The server responded with 404 Not Found for \"https://example.com/\"",
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "intro.md" },
+                        "region": {
+                            "startLine": 3,
+                            "startColumn": 1,
+                            "endLine": 3,
+                            "endColumn": 27,
+                        },
+                    },
+                }],
+            },
+            {
+                "ruleId": "incomplete-link",
+                "level": "warning",
+                "message": {
+                    "text": "Did you forget to define a URL for `ref`?",
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": "intro.md" },
+                        "region": {
+                            "startLine": 4,
+                            "startColumn": 1,
+                            "endLine": 4,
+                            "endColumn": 6,
+                        },
+                    },
+                }],
+            },
+        ]);
+        assert_eq!(results, &should_be);
+
+        let rules = got["runs"][0]["tool"]["driver"]["rules"].as_array();
+        let rule_ids: Vec<_> =
+            rules.unwrap().iter().map(|rule| &rule["id"]).collect();
+        assert!(rule_ids.contains(&&json!("broken-web-link")));
+        assert!(rule_ids.contains(&&json!("incomplete-link")));
+    }
+
+    #[test]
+    fn junit_report_with_one_test_case_per_chapter() {
+        let mut files = Files::new();
+        let first = files.add("first.md", "[ok](./second.md)\n");
+        let second = files.add(
+            "second.md",
+            "[a](./missing.md)\n\n[b](<./also & missing.md>)\n",
+        );
+        let third = files.add("third.md", "[anchor](./first.md#nope)\n");
+        let outcome = ValidationOutcome {
+            valid_links: vec![Link::parse("./second.md", 0..17, first).unwrap()],
+            invalid_links: vec![
+                InvalidLink {
+                    link: Link::parse("./missing.md", 0..17, second).unwrap(),
+                    reason: Reason::FileNotFound,
+                },
+                InvalidLink {
+                    link: Link::parse("./also%20&%20missing.md", 19..45, second)
+                        .unwrap(),
+                    reason: Reason::FileNotFound,
+                },
+                InvalidLink {
+                    link: Link::parse("./first.md#nope", 0..25, third).unwrap(),
+                    reason: Reason::AnchorNotFound(String::from("nope")),
+                },
+            ],
+            ..Default::default()
+        };
+        let chapters = vec![first, second, third];
+
+        let got = outcome.junit(&files, &chapters, &Config::default());
+
+        let suite = r#"<testsuite name="mdbook-linkcheck" tests="3" failures="1" errors="0" skipped="0">"#;
+        assert!(got.contains(suite), "{}", got);
+        for passing in &["first.md", "third.md"] {
+            let testcase = format!(
+                r#"<testcase name="{}" classname="linkcheck" />"#,
+                passing
+            );
+            assert!(got.contains(&testcase), "{}", got);
+        }
+        assert_eq!(got.matches("<failure ").count(), 2);
+        let escaped =
+            "second.md:3:1: File not found: ./also%20&amp;%20missing.md";
+        assert!(got.contains(escaped), "{}", got);
+
+        let cfg = policy(WarningPolicy::Error);
+
+        let got = outcome.junit(&files, &chapters, &cfg);
+        assert!(got.contains(r#"tests="3" failures="2""#));
+        assert_eq!(got.matches("<failure ").count(), 3);
+    }
+}
@@ -0,0 +1,282 @@
+//! Honouring a web site's `robots.txt` (see [`Config::respect_robots_txt`]).
+//!
+//! Only the parts of the format which matter to a link checker are
+//! supported: `User-agent` groups, `Allow` and `Disallow` rules (including
+//! the `*` and `$` wildcards), and `Crawl-delay`.
+//!
+//! [`Config::respect_robots_txt`]: crate::Config::respect_robots_txt
+
+use crate::Config;
+use reqwest::Client;
+use std::{io::Read, time::Duration};
+
+/// The rules from a `robots.txt` file which apply to us.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Robots {
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+/// A `User-agent` group, with the agents it applies to.
+#[derive(Debug, Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+impl Robots {
+    /// Parse a `robots.txt` file, keeping the group which best matches our
+    /// `user_agent` (falling back to the `*` group).
+    pub fn parse(src: &str, user_agent: &str) -> Robots {
+        let groups = parse_groups(src);
+        let user_agent = user_agent.to_ascii_lowercase();
+
+        // the most specific agent wins, so "mdbook-linkcheck" beats "*"
+        let specificity = |agent: &str| match agent {
+            "*" => Some(0),
+            _ if user_agent.contains(agent) => Some(agent.len()),
+            _ => None,
+        };
+        let best = groups
+            .iter()
+            .flat_map(|group| group.agents.iter())
+            .filter_map(|agent| specificity(agent).map(|s| (s, agent)))
+            .max_by_key(|&(s, _)| s)
+            .map(|(_, agent)| agent.clone());
+
+        let best = match best {
+            Some(best) => best,
+            None => return Robots::default(),
+        };
+
+        let mut robots = Robots::default();
+        for group in groups.into_iter().filter(|g| g.agents.contains(&best)) {
+            robots.rules.extend(group.rules);
+            robots.crawl_delay = robots.crawl_delay.or(group.crawl_delay);
+        }
+
+        robots
+    }
+
+    /// May we request this path (including the query string)?
+    ///
+    /// The longest matching rule wins, with `Allow` winning ties.
+    pub fn allows(&self, path: &str) -> bool {
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        self.rules
+            .iter()
+            .filter(|rule| matches(&rule.pattern, path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .map(|rule| rule.allow)
+            .unwrap_or(true)
+    }
+
+    /// How long the site would like us to wait between requests.
+    pub fn crawl_delay(&self) -> Option<Duration> { self.crawl_delay }
+}
+
+fn parse_groups(src: &str) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    // consecutive "User-agent" lines share a group
+    let mut in_agent_list = false;
+
+    for line in src.lines() {
+        let line = match line.find('#') {
+            Some(ix) => &line[..ix],
+            None => line,
+        };
+        let (key, value) = match line.find(':') {
+            Some(ix) => (&line[..ix], line[ix + 1..].trim()),
+            None => continue,
+        };
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "user-agent" => {
+                if !in_agent_list {
+                    groups.push(Group::default());
+                }
+                in_agent_list = true;
+                if let Some(group) = groups.last_mut() {
+                    group.agents.push(value.to_ascii_lowercase());
+                }
+            },
+            key @ "allow" | key @ "disallow" => {
+                in_agent_list = false;
+                // an empty "Disallow:" means everything is allowed
+                match groups.last_mut() {
+                    Some(group) if !value.is_empty() => group.rules.push(Rule {
+                        allow: key == "allow",
+                        pattern: value.to_string(),
+                    }),
+                    _ => {},
+                }
+            },
+            "crawl-delay" => {
+                in_agent_list = false;
+                // absurdly long delays are capped by the caller, so they
+                // saturate rather than being thrown away
+                let delay = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                    .map(|secs| {
+                        Duration::try_from_secs_f64(secs)
+                            .unwrap_or(Duration::MAX)
+                    });
+                if let Some(group) = groups.last_mut() {
+                    group.crawl_delay = delay;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    groups
+}
+
+/// Does a `robots.txt` pattern match the start of this path? A `*` matches
+/// any sequence of characters and a trailing `$` anchors the pattern to the
+/// end of the path.
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let mut pieces = pattern.split('*');
+    let first = pieces.next().unwrap_or_default();
+
+    if !path.starts_with(first) {
+        return false;
+    }
+
+    let mut rest = &path[first.len()..];
+    let pieces: Vec<&str> = pieces.collect();
+
+    for (i, piece) in pieces.iter().enumerate() {
+        let is_last = i == pieces.len() - 1;
+
+        if is_last && anchored {
+            return rest.ends_with(piece);
+        }
+        match rest.find(piece) {
+            Some(ix) => rest = &rest[ix + piece.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+/// Fetch and parse the `robots.txt` for an origin (e.g.
+/// `https://example.com`).
+///
+/// A missing or unreadable `robots.txt` means every path is allowed.
+pub(crate) fn fetch(origin: &str, client: &Client, cfg: &Config) -> Robots {
+    let url = format!("{}/robots.txt", origin);
+    log::debug!("Fetching \"{}\"", url);
+
+    let response = match client.get(&url).send() {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            log::debug!("\"{}\" replied with {}", url, response.status());
+            return Robots::default();
+        },
+        Err(e) => {
+            log::debug!("Unable to fetch \"{}\": {}", url, e);
+            return Robots::default();
+        },
+    };
+
+    let mut body = Vec::new();
+    if let Err(e) = response.take(cfg.max_body_size).read_to_end(&mut body) {
+        log::debug!("Unable to read \"{}\": {}", url, e);
+        return Robots::default();
+    }
+
+    Robots::parse(&String::from_utf8_lossy(&body), &cfg.user_agent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = include_str!("../tests/robots.txt");
+
+    #[test]
+    fn allow_and_disallow_paths() {
+        let robots = Robots::parse(FIXTURE, "Mozilla/5.0 (some-browser)");
+        let inputs = vec![
+            ("/", true),
+            ("/docs/intro.html", true),
+            ("/private/", false),
+            ("/private/notes.html", false),
+            ("/private/public/page.html", true),
+            ("/search?q=rust", false),
+            ("/downloads/book.pdf", false),
+            ("/downloads/book.pdf?v=1", true),
+            ("/downloads/index.html", true),
+            ("/robots.txt", true),
+        ];
+
+        for (path, should_be) in inputs {
+            assert_eq!(robots.allows(path), should_be, "{}", path);
+        }
+        assert_eq!(robots.crawl_delay(), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn huge_crawl_delays_dont_overflow() {
+        let robots = Robots::parse("User-agent: *\nCrawl-delay: 1e20\n", "me");
+
+        assert_eq!(robots.crawl_delay(), Some(Duration::MAX));
+    }
+
+    #[test]
+    fn the_most_specific_user_agent_wins() {
+        let robots = Robots::parse(FIXTURE, "mdbook-linkcheck-0.5.1");
+
+        assert!(robots.allows("/private/notes.html"));
+        assert!(!robots.allows("/drafts/"));
+        assert_eq!(robots.crawl_delay(), None);
+    }
+
+    #[test]
+    fn no_matching_group_allows_everything() {
+        let src = "User-agent: some-crawler\nDisallow: /\n";
+
+        let robots = Robots::parse(src, "mdbook-linkcheck");
+
+        assert!(robots.allows("/anything"));
+        assert_eq!(robots, Robots::default());
+    }
+
+    #[test]
+    fn wildcard_patterns() {
+        let inputs = vec![
+            ("/fish", "/fish.html", true),
+            ("/fish", "/Fish.html", false),
+            ("/*.php", "/index.php", true),
+            ("/*.php", "/folder/index.php?x=1", true),
+            ("/*.php$", "/index.php?x=1", false),
+            ("/*.php$", "/index.php", true),
+            ("/fish*salmon", "/fish/and/salmon.html", true),
+            ("/$", "/", true),
+            ("/$", "/page", false),
+        ];
+
+        for (pattern, path, should_be) in inputs {
+            let got = matches(pattern, path);
+            assert_eq!(got, should_be, "{} {}", pattern, path);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Helpers shared between unit tests.
+
+use crate::{Config, WarningPolicy};
+use log::{Level, Log, Metadata, Record};
+use native_tls::{Identity, TlsAcceptor};
+use std::{
+    cell::RefCell,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::{Arc, Mutex, Once},
+    thread,
+};
+
+/// A tiny HTTP server which replies to every request using a handler
+/// function, recording the requests it receives.
+#[derive(Debug)]
+pub(crate) struct MockServer {
+    addr: SocketAddr,
+    scheme: &'static str,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub fn start<F>(handler: F) -> MockServer
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        MockServer::start_with_tls(None, handler)
+    }
+
+    /// Start a server which speaks HTTPS using a self-signed certificate for
+    /// `localhost` and `127.0.0.1`.
+    pub fn start_https<F>(handler: F) -> MockServer
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("self-signed.p12");
+        let der = std::fs::read(path).unwrap();
+        let identity = Identity::from_pkcs12(&der, "mdbook-linkcheck").unwrap();
+        let acceptor = TlsAcceptor::new(identity).unwrap();
+
+        MockServer::start_with_tls(Some(acceptor), handler)
+    }
+
+    fn start_with_tls<F>(tls: Option<TlsAcceptor>, handler: F) -> MockServer
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let scheme = if tls.is_some() { "https" } else { "http" };
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+        let tls = tls.map(Arc::new);
+
+        let recorded = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                let handler = Arc::clone(&handler);
+                let recorded = Arc::clone(&recorded);
+                let tls = tls.clone();
+
+                thread::spawn(move || match tls {
+                    Some(tls) => {
+                        // clients which reject the certificate just hang up
+                        if let Ok(stream) = tls.accept(stream) {
+                            handle_connection(stream, &*handler, &recorded)
+                        }
+                    },
+                    None => handle_connection(stream, &*handler, &recorded),
+                });
+            }
+        });
+
+        MockServer {
+            addr,
+            scheme,
+            requests,
+        }
+    }
+
+    /// Get the URL for a path on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}://{}{}", self.scheme, self.addr, path)
+    }
+
+    /// All the requests received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn handle_connection<S, F>(
+    stream: S,
+    handler: &F,
+    recorded: &Mutex<Vec<Request>>,
+) where
+    S: Read + Write,
+    F: Fn(&Request) -> Response,
+{
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {},
+        }
+
+        let mut words = line.split_whitespace();
+        let method = words.next().unwrap_or_default().to_string();
+        let path = words.next().unwrap_or_default().to_string();
+        let mut headers = Vec::new();
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(ix) = line.find(':') {
+                headers.push((
+                    line[..ix].trim().to_string(),
+                    line[ix + 1..].trim().to_string(),
+                ));
+            }
+        }
+
+        let request = Request {
+            method,
+            path,
+            headers,
+        };
+        let response = handler(&request);
+        recorded.lock().unwrap().push(request.clone());
+
+        let mut raw = format!(
+            "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\n",
+            response.status,
+            response.body.len()
+        );
+        for (name, value) in &response.headers {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        raw.push_str("\r\n");
+        if request.method != "HEAD" {
+            raw.push_str(&response.body);
+        }
+
+        let stream = reader.get_mut();
+        if stream.write_all(raw.as_bytes()).is_err()
+            || stream.flush().is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// A request received by the [`MockServer`].
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+/// The response a [`MockServer`] should send back.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: &str) -> Response {
+        self.body = body.to_string();
+        self
+    }
+}
+
+thread_local! {
+    static CAPTURED_LOGS: RefCell<Option<Vec<(Level, String)>>> =
+        const { RefCell::new(None) };
+}
+
+/// A logger which records messages logged by threads inside
+/// [`capture_logs()`], so tests running in parallel don't see each other's
+/// messages.
+struct CapturingLogger;
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool { true }
+
+    fn log(&self, record: &Record<'_>) {
+        CAPTURED_LOGS.with(|logs| {
+            if let Some(ref mut logs) = *logs.borrow_mut() {
+                logs.push((record.level(), record.args().to_string()));
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Run a function, returning everything it logged on the current thread.
+pub(crate) fn capture_logs<F: FnOnce()>(f: F) -> Vec<(Level, String)> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_boxed_logger(Box::new(CapturingLogger))
+            .expect("Another logger was already installed");
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+
+    CAPTURED_LOGS.with(|logs| *logs.borrow_mut() = Some(Vec::new()));
+    f();
+    CAPTURED_LOGS.with(|logs| logs.borrow_mut().take().unwrap_or_default())
+}
+
+/// A default [`Config`] with a particular [`WarningPolicy`].
+pub(crate) fn policy(warning_policy: WarningPolicy) -> Config {
+    Config {
+        warning_policy,
+        ..Default::default()
+    }
+}
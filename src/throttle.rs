@@ -0,0 +1,310 @@
+//! Primitives for limiting how hard we hit web servers.
+
+use crate::Config;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Keeps track of the limits applied to outgoing web requests.
+#[derive(Debug, Default)]
+pub(crate) struct Throttle {
+    global: Option<Arc<Semaphore>>,
+    max_per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    rate: Option<RateLimiter>,
+    per_host_rate: u32,
+    host_rates: Mutex<HashMap<String, Arc<RateLimiter>>>,
+}
+
+impl Throttle {
+    pub fn new(cfg: &Config) -> Throttle {
+        let global = match cfg.max_concurrency {
+            0 => None,
+            n => Some(Arc::new(Semaphore::new(n))),
+        };
+
+        let rate = match cfg.requests_per_second {
+            None | Some(0) => None,
+            Some(n) => Some(RateLimiter::new(n)),
+        };
+
+        Throttle {
+            global,
+            max_per_host: cfg.max_concurrency_per_host,
+            hosts: Mutex::new(HashMap::new()),
+            rate,
+            per_host_rate: cfg.requests_per_host_per_second.unwrap_or(0),
+            host_rates: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait until we're allowed to send a request to the provided host,
+    /// returning a [`Permit`] which must be held until the request completes.
+    pub fn acquire(&self, host: &str) -> Permit {
+        let mut permit = Permit { held: Vec::new() };
+
+        // Note: we wait for the host before the global limit so requests
+        // queued up behind a busy host don't stop other hosts being checked
+        if self.max_per_host > 0 {
+            let semaphore = self
+                .hosts
+                .lock()
+                .expect("Lock was poisoned")
+                .entry(host.to_string())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+                .clone();
+            semaphore.acquire();
+            permit.held.push(semaphore);
+        }
+
+        // hosts may also have a crawl delay from their robots.txt
+        let rate = {
+            let mut host_rates =
+                self.host_rates.lock().expect("Lock was poisoned");
+            match host_rates.get(host) {
+                Some(rate) => Some(Arc::clone(rate)),
+                None if self.per_host_rate > 0 => {
+                    let rate = Arc::new(RateLimiter::new(self.per_host_rate));
+                    host_rates.insert(host.to_string(), Arc::clone(&rate));
+                    Some(rate)
+                },
+                None => None,
+            }
+        };
+        if let Some(rate) = rate {
+            rate.wait();
+        }
+
+        if let Some(ref global) = self.global {
+            global.acquire();
+            permit.held.push(Arc::clone(global));
+        }
+
+        // the rate limit is applied on top of any concurrency limits
+        if let Some(ref rate) = self.rate {
+            rate.wait();
+        }
+
+        permit
+    }
+
+    /// Make sure requests to a host are at least `delay` apart (e.g. the
+    /// `Crawl-delay` from its `robots.txt`), on top of any
+    /// [`Config::requests_per_host_per_second`] limit.
+    ///
+    /// We've only just contacted the host, so the next request waits too.
+    /// Delays too long to represent as an [`Instant`] are never waited for.
+    pub fn set_crawl_delay(&self, host: &str, delay: Duration) {
+        let mut host_rates = self.host_rates.lock().expect("Lock was poisoned");
+        let interval = match self.per_host_rate {
+            0 => delay,
+            n => delay.max(Duration::from_secs(1) / n),
+        };
+
+        host_rates.insert(
+            host.to_string(),
+            Arc::new(RateLimiter {
+                interval,
+                next_slot: Mutex::new(Instant::now().checked_add(interval)),
+            }),
+        );
+    }
+}
+
+/// Spaces out events so they never happen more than `n` times per second.
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    fn new(per_second: u32) -> RateLimiter {
+        RateLimiter {
+            interval: Duration::from_secs(1) / per_second,
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    /// Block until the next slot is available.
+    fn wait(&self) {
+        let now = Instant::now();
+
+        let slot = {
+            let mut next_slot =
+                self.next_slot.lock().expect("Lock was poisoned");
+            let slot = match *next_slot {
+                Some(slot) if slot > now => slot,
+                _ => now,
+            };
+            *next_slot = slot.checked_add(self.interval);
+            slot
+        };
+
+        if slot > now {
+            thread::sleep(slot - now);
+        }
+    }
+}
+
+/// A guard which releases its slots in one or more [`Semaphore`]s when
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct Permit {
+    held: Vec<Arc<Semaphore>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        for semaphore in self.held.drain(..) {
+            semaphore.release();
+        }
+    }
+}
+
+/// A simple counting semaphore.
+#[derive(Debug)]
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) {
+        let mut available = self.available.lock().expect("Lock was poisoned");
+
+        while *available == 0 {
+            available =
+                self.released.wait(available).expect("Lock was poisoned");
+        }
+
+        *available -= 1;
+    }
+
+    fn release(&self) {
+        *self.available.lock().expect("Lock was poisoned") += 1;
+        self.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn the_global_limit_is_respected() {
+        let cfg = Config {
+            max_concurrency: 2,
+            max_concurrency_per_host: 0,
+            ..Default::default()
+        };
+        let throttle = Arc::new(Throttle::new(&cfg));
+        let active = Arc::new(AtomicUsize::new(0));
+        let most_active = Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let throttle = Arc::clone(&throttle);
+                let active = Arc::clone(&active);
+                let most_active = Arc::clone(&most_active);
+
+                thread::spawn(move || {
+                    let _permit = throttle.acquire(&format!("host-{}", i));
+                    let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    most_active.fetch_max(now_active, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    active.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(most_active.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn requests_are_spaced_out_by_the_rate_limit() {
+        let cfg = Config {
+            requests_per_second: Some(20),
+            ..Default::default()
+        };
+        let throttle = Throttle::new(&cfg);
+        let start = Instant::now();
+
+        for _ in 0..5 {
+            let _permit = throttle.acquire("example.com");
+        }
+
+        // the first request goes through immediately, then 4 more at 50ms
+        // intervals
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn requests_to_each_host_are_rate_limited_independently() {
+        let cfg = Config {
+            requests_per_host_per_second: Some(10),
+            ..Default::default()
+        };
+        let throttle = Throttle::new(&cfg);
+        let start = Instant::now();
+
+        // first contact with a host is never delayed
+        drop(throttle.acquire("first.com"));
+        drop(throttle.acquire("second.com"));
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        drop(throttle.acquire("first.com"));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn a_crawl_delay_spaces_out_requests_to_that_host() {
+        let throttle = Throttle::new(&Config::default());
+        let start = Instant::now();
+
+        throttle.set_crawl_delay("slow.com", Duration::from_millis(100));
+        throttle.set_crawl_delay("never.com", Duration::MAX);
+        drop(throttle.acquire("fast.com"));
+        // a delay too long to wait for doesn't overflow
+        drop(throttle.acquire("never.com"));
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        drop(throttle.acquire("slow.com"));
+        drop(throttle.acquire("slow.com"));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn a_limit_of_zero_means_unbounded() {
+        let cfg = Config {
+            max_concurrency: 0,
+            max_concurrency_per_host: 0,
+            ..Default::default()
+        };
+        let throttle = Throttle::new(&cfg);
+
+        let permits: Vec<_> =
+            (0..100).map(|_| throttle.acquire("example.com")).collect();
+
+        assert_eq!(permits.len(), 100);
+    }
+}
@@ -0,0 +1,187 @@
+//! Syntax checks for web links (see [`Config::check_url_syntax`]).
+//!
+//! These never touch the network, so they can catch typos like `htps://` or
+//! a missing host even in [`Config::offline`] mode.
+//!
+//! [`Config::check_url_syntax`]: crate::Config::check_url_syntax
+//! [`Config::offline`]: crate::Config::offline
+
+use crate::{validate::Reason, Link};
+
+/// The schemes a web link should use.
+const WEB_SCHEMES: &[&str] = &["http", "https"];
+
+/// Make sure something which looks like a web link is well-formed.
+///
+/// Links with other schemes (e.g. `ftp://`) and links to local files are
+/// left alone, unless they look like a mistyped web link.
+pub(crate) fn check(link: &Link) -> Result<(), Reason> {
+    let uri = link.uri.to_string();
+
+    match link.uri.scheme_str() {
+        Some(scheme) if is_web_scheme(scheme) => {
+            check_host(link.uri.host().unwrap_or_default())
+        },
+        Some(scheme) => match misspelled_web_scheme(scheme) {
+            Some(suggestion) => Err(Reason::MalformedUrl(format!(
+                "Unknown scheme \"{}\", did you mean \"{}\"?",
+                scheme, suggestion
+            ))),
+            None => Ok(()),
+        },
+        // "https:///path" and "http:example.com" aren't valid URIs, so they
+        // get treated as local paths
+        None if starts_with_web_scheme(&uri) => {
+            Err(Reason::MalformedUrl(String::from("The URL has no host")))
+        },
+        None => Ok(()),
+    }
+}
+
+fn is_web_scheme(scheme: &str) -> bool {
+    WEB_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme))
+}
+
+fn starts_with_web_scheme(uri: &str) -> bool {
+    match uri.find(':') {
+        Some(ix) => is_web_scheme(&uri[..ix]),
+        None => false,
+    }
+}
+
+/// Is this scheme one typo away from `http` or `https` (e.g. `htps` or
+/// `htttp`)?
+fn misspelled_web_scheme(scheme: &str) -> Option<&'static str> {
+    let scheme = scheme.to_ascii_lowercase();
+
+    WEB_SCHEMES
+        .iter()
+        .rev()
+        .find(|candidate| edit_distance(&scheme, candidate) == 1)
+        .copied()
+}
+
+/// Make sure a host is either an IP address or a plausible domain name.
+fn check_host(host: &str) -> Result<(), Reason> {
+    if host.is_empty() {
+        return Err(Reason::MalformedUrl(String::from("The URL has no host")));
+    }
+
+    // IPv6 addresses are checked by the URI parser
+    if host.starts_with('[') {
+        return Ok(());
+    }
+
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    };
+
+    // a trailing dot is allowed (e.g. "example.com.")
+    let name = host.strip_suffix('.').unwrap_or(host);
+
+    if name.split('.').all(valid_label) {
+        Ok(())
+    } else {
+        Err(Reason::MalformedUrl(format!(
+            "\"{}\" isn't a valid host name",
+            host
+        )))
+    }
+}
+
+/// The Levenshtein distance between two (short) strings.
+fn edit_distance(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+
+    for (i, l) in left.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, r) in right.iter().enumerate() {
+            let substitution = previous[j] + if l == *r { 0 } else { 1 };
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(substitution.min(insertion).min(deletion));
+        }
+
+        previous = current;
+    }
+
+    previous[right.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codespan::Files;
+
+    fn check_src(src: &str) -> Result<(), Reason> {
+        let mut files = Files::new();
+        let id = files.add("index.md", src);
+        let link = Link::parse(src, 0..src.len(), id).unwrap();
+
+        check(&link)
+    }
+
+    #[test]
+    fn well_formed_links_are_fine() {
+        let inputs = vec![
+            "https://example.com/",
+            "http://example.com/page?q=1#section",
+            "HTTPS://Example.COM/",
+            "https://docs.example.com.:8080/",
+            "http://127.0.0.1/",
+            "http://[::1]/",
+            "http://localhost/",
+            "ftp://example.com/file.txt",
+            "./chapter_1.md",
+            "#installation",
+        ];
+
+        for src in inputs {
+            assert!(check_src(src).is_ok(), "{}", src);
+        }
+    }
+
+    #[test]
+    fn malformed_links_are_errors() {
+        let inputs = vec![
+            (
+                "htps://example.com/",
+                "Unknown scheme \"htps\", did you mean \"https\"?",
+            ),
+            (
+                "htp://example.com/",
+                "Unknown scheme \"htp\", did you mean \"http\"?",
+            ),
+            (
+                "httpss://example.com/",
+                "Unknown scheme \"httpss\", did you mean \"https\"?",
+            ),
+            ("https:///path", "The URL has no host"),
+            ("http://:80/", "The URL has no host"),
+            ("https://example..com/", "\"example..com\" isn't a valid host name"),
+            ("https://-bad-.com/", "\"-bad-.com\" isn't a valid host name"),
+        ];
+
+        for (src, should_be) in inputs {
+            let err = check_src(src).unwrap_err();
+            assert_eq!(err.to_string(), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn typos_are_one_edit_away() {
+        assert_eq!(edit_distance("https", "https"), 0);
+        assert_eq!(edit_distance("htps", "https"), 1);
+        assert_eq!(edit_distance("hhttps", "https"), 1);
+        assert_eq!(edit_distance("ftp", "http"), 2);
+        assert_eq!(misspelled_web_scheme("ftp"), None);
+        assert_eq!(misspelled_web_scheme("HTPS"), Some("https"));
+    }
+}
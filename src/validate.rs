@@ -1,22 +1,31 @@
 use crate::{
-    cache::{Cache, CacheEntry},
-    Config, IncompleteLink, Link, WarningPolicy,
+    cache::{Cache, CacheEntry, Lookup},
+    contact::ContactScheme,
+    progress::Progress,
+    proxy::Proxies,
+    throttle::Throttle,
+    Category, Config, DuplicateAnchor, HttpMethod, IncompleteLink, Link,
+    UnusedReference, WarningPolicy,
 };
-use codespan::{Files, Span};
+use codespan::{FileId, Files, Span};
 use codespan_reporting::diagnostic::{Diagnostic, Label, Severity};
 use either::Either;
-use failure::Error;
+use failure::{Error, ResultExt};
 use http::HeaderMap;
 use rayon::prelude::*;
-use reqwest::{Client, StatusCode};
+use reqwest::{Client, RedirectPolicy, RequestBuilder, Response, StatusCode};
 use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
     ffi::OsStr,
     fmt::{self, Display, Formatter},
-    path::Path,
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
     time::{Duration, SystemTime},
 };
 
-#[allow(unused_imports)]
 use http::Uri;
 
 /// Try to validate the provided [`Link`]s.
@@ -27,64 +36,268 @@ pub fn validate(
     cache: &Cache,
     files: &Files,
     incomplete_links: Vec<IncompleteLink>,
+) -> Result<ValidationOutcome, Error> {
+    validate_with(links, cfg, src_dir, cache, files, incomplete_links, &[])
+}
+
+/// Like [`validate()`], but each link is first offered to some custom
+/// [`LinkValidator`]s, in order. The first one to return a [`LinkResult`]
+/// decides what happens to the link, and links which every validator passes
+/// on get the usual checks.
+pub fn validate_with(
+    links: &[Link],
+    cfg: &Config,
+    src_dir: &Path,
+    cache: &Cache,
+    files: &Files,
+    incomplete_links: Vec<IncompleteLink>,
+    validators: &[&dyn LinkValidator],
 ) -> Result<ValidationOutcome, Error> {
     let mut outcome = ValidationOutcome {
         incomplete_links,
         ..Default::default()
     };
 
+    let scheme = cfg.scheme_relative_default.name();
+    let links: Vec<Link> = links
+        .iter()
+        .map(|link| link.with_default_scheme(scheme))
+        .collect();
+
+    let links = if validators.is_empty() {
+        links
+    } else {
+        apply_custom_validators(links, validators, &mut outcome)
+    };
+
+    let links = if cfg.check_url_syntax {
+        remove_malformed_urls(links, cfg, &mut outcome)
+    } else {
+        links
+    };
+
     let buckets =
-        sort_into_buckets(links, |link| outcome.unknown_schema.push(link));
+        sort_into_buckets(&links, |link| outcome.unknown_schema.push(link));
 
     log::debug!("Checking {} local links", buckets.file.len());
-    validate_local_links(
-        &buckets.file,
-        cfg.traverse_parent_directories,
-        src_dir,
-        &mut outcome,
-        files,
-    );
+    validate_local_links(&buckets.file, cfg, src_dir, &mut outcome, files);
+    let dns_timeout = Duration::from_secs(cfg.request_timeout);
+    validate_contact_links(buckets.contact, cfg, &mut outcome, |domain| {
+        crate::contact::domain_resolves(domain, dns_timeout)
+    });
 
-    if cfg.follow_web_links {
-        log::debug!("Checking {} web links", buckets.web.len());
-        let mut web = buckets.web;
-        remove_skipped_links(&mut web, &mut outcome, &cfg, files);
+    let web = remove_skipped_links(buckets.web, &mut outcome, cfg, files);
+    if cfg.follow_web_links && !cfg.offline {
+        log::debug!("Checking {} web links", web.len());
         validate_web_links(&web, cfg, &mut outcome, cache)?;
-    } else {
-        log::debug!("Ignoring {} web links", buckets.web.len());
-        outcome.ignored.extend(buckets.web);
+    }
+
+    if cfg.warn_on_insecure_http {
+        add_plain_http_links(&links, cfg, &mut outcome, files);
     }
 
     Ok(outcome)
 }
 
-/// Removes any web links we'd normally skip, adding them to the list of ignored
-/// links.
+/// The result of checking a single link with [`validate_link()`].
+#[derive(Debug)]
+pub enum LinkResult {
+    /// The link is fine.
+    Valid,
+    /// The link is broken.
+    Invalid(Reason),
+    /// The link wasn't checked because of the [`Config`] (e.g. it's a web
+    /// link and [`Config::follow_web_links`] is turned off, or it matched
+    /// [`Config::exclude`]).
+    Ignored,
+    /// We don't know how to check links with this scheme.
+    UnknownScheme,
+}
+
+/// A custom check for links the built-in logic can't handle, like an
+/// internal `jira://` scheme, which can be passed to [`validate_with()`].
+///
+/// This is only available when using `mdbook-linkcheck` as a library.
+/// Problems reported with [`Reason::Rejected`] use the
+/// [`Category::RejectedLink`] warning policy.
+///
+/// ```rust
+/// use mdbook_linkcheck::{LinkResult, LinkValidator, Reason};
+///
+/// /// Only tickets which look like "jira://PROJ-123" exist.
+/// struct Jira;
+///
+/// impl LinkValidator for Jira {
+///     fn validate(&self, link: &str) -> Option<LinkResult> {
+///         let ticket = link.strip_prefix("jira://")?.trim_end_matches('/');
+///         let valid = ticket
+///             .split_once('-')
+///             .map_or(false, |(project, number)| {
+///                 !project.is_empty() && number.parse::<u32>().is_ok()
+///             });
+///
+///         Some(if valid {
+///             LinkResult::Valid
+///         } else {
+///             LinkResult::Invalid(Reason::Rejected(format!(
+///                 "\"{}\" isn't a ticket number",
+///                 ticket
+///             )))
+///         })
+///     }
+/// }
+///
+/// assert!(matches!(
+///     Jira.validate("jira://PROJ-123/"),
+///     Some(LinkResult::Valid)
+/// ));
+/// assert!(matches!(
+///     Jira.validate("jira://oops"),
+///     Some(LinkResult::Invalid(_))
+/// ));
+/// // anything else gets the normal checks
+/// assert!(Jira.validate("https://example.com/").is_none());
+/// ```
+///
+/// Closures with the same signature can be used as validators too.
+pub trait LinkValidator {
+    /// Check a link (e.g. `"jira://PROJ-123/"`), or return `None` to leave
+    /// it to the next validator and then the built-in checks.
+    ///
+    /// The link is normalized the same way it's printed in diagnostics, so
+    /// a URL without a path gets a trailing `/`, and scheme-relative links
+    /// have already been resolved (see [`Config::scheme_relative_default`]).
+    /// Any fragment is kept.
+    fn validate(&self, link: &str) -> Option<LinkResult>;
+}
+
+impl<F> LinkValidator for F
+where
+    F: Fn(&str) -> Option<LinkResult>,
+{
+    fn validate(&self, link: &str) -> Option<LinkResult> { self(link) }
+}
+
+/// Give each link to the custom [`LinkValidator`]s, returning the links they
+/// didn't have an opinion on.
+fn apply_custom_validators(
+    links: Vec<Link>,
+    validators: &[&dyn LinkValidator],
+    outcome: &mut ValidationOutcome,
+) -> Vec<Link> {
+    let mut remaining = Vec::new();
+
+    for link in links {
+        let uri = match link.fragment {
+            Some(ref fragment) => format!("{}#{}", link.uri, fragment),
+            None => link.uri.to_string(),
+        };
+        let result = validators
+            .iter()
+            .find_map(|validator| validator.validate(&uri));
+
+        match result {
+            Some(LinkResult::Valid) => outcome.valid_links.push(link),
+            Some(LinkResult::Invalid(reason)) => {
+                outcome.invalid_links.push(InvalidLink { link, reason })
+            },
+            Some(LinkResult::Ignored) => outcome.ignored.push(link),
+            Some(LinkResult::UnknownScheme) => {
+                outcome.unknown_schema.push(link)
+            },
+            None => remaining.push(link),
+        }
+    }
+
+    remaining
+}
+
+/// Check a single link (e.g. `"./chapter_1.md#intro"` or
+/// `"https://example.com/"`) without needing a whole book.
+///
+/// Local links are resolved as if they were written in a chapter at the top
+/// of `src_dir`. This goes through [`validate()`], so the same [`Config`]
+/// fields are used:
+///
+/// - [`Config::follow_web_links`], [`Config::offline`], [`Config::include`],
+///   and [`Config::exclude`] decide whether a web link is checked at all,
+///   and [`Config::scheme_relative_default`] is used for links like
+///   `//example.com/`
+/// - [`Config::traverse_parent_directories`],
+///   [`Config::local_link_extensions`], [`Config::check_anchors`], and
+///   [`Config::case_insensitive_fragments`] apply to local links
+/// - the HTTP settings (e.g. [`Config::request_timeout`],
+///   [`Config::accepted_status_codes`], [`Config::http_headers`], or
+///   [`Config::check_remote_fragments`]) apply to web links
+/// - [`Config::check_mailto`] and [`Config::check_tel`] apply to `mailto:`
+///   and `tel:` links
+///
+/// Results are never cached, and this blocks until any web requests finish.
+pub fn validate_link(
+    link: &str,
+    cfg: &Config,
+    src_dir: &Path,
+) -> Result<LinkResult, Error> {
+    let src_dir = dunce::canonicalize(src_dir)
+        .context("Unable to resolve the source directory")?;
+    let mut files = Files::new();
+    let file = files.add("<link>", link);
+    let link = Link::parse(link, 0..link.len(), file)?;
+
+    let mut outcome = validate(
+        &[link],
+        cfg,
+        &src_dir,
+        &Cache::default(),
+        &files,
+        Vec::new(),
+    )?;
+
+    let result = if let Some(invalid) = outcome.invalid_links.pop() {
+        LinkResult::Invalid(invalid.reason)
+    } else if !outcome.valid_links.is_empty() {
+        LinkResult::Valid
+    } else if !outcome.unknown_schema.is_empty() {
+        LinkResult::UnknownScheme
+    } else {
+        LinkResult::Ignored
+    };
+
+    Ok(result)
+}
+
+/// Removes any web links we'd normally skip (see [`web_action()`]), adding
+/// them to the list of ignored or offline links, and returns the rest.
 fn remove_skipped_links(
-    links: &mut Vec<Link>,
+    links: Vec<Link>,
     outcome: &mut ValidationOutcome,
     cfg: &Config,
     files: &Files,
-) {
-    links.retain(|link| {
-        let uri = link.uri.to_string();
-
-        if cfg.should_skip(&uri) {
-            let location =
-                files.location(link.file, link.span.start()).unwrap();
-            let name = files.name(link.file);
-            log::debug!(
-                "Skipping \"{}\" in {}, line {}",
-                uri,
-                name,
-                location.line,
-            );
-            outcome.ignored.push(link.clone());
-            false
-        } else {
-            true
+) -> Vec<Link> {
+    let mut remaining = Vec::new();
+
+    for link in links {
+        match web_action(&link, cfg, files) {
+            Action::Skip(_) if cfg.offline => {
+                outcome.skipped_offline.push(link)
+            },
+            Action::Skip(reason) => {
+                let location =
+                    files.location(link.file, link.span.start()).unwrap();
+                log::debug!(
+                    "Skipping \"{}\" in {}, line {} ({})",
+                    link.uri,
+                    files.name(link.file),
+                    location.line,
+                    reason,
+                );
+                outcome.ignored.push(link);
+            },
+            _ => remaining.push(link),
         }
-    })
+    }
+
+    remaining
 }
 
 fn sort_into_buckets<F: FnMut(Link)>(
@@ -94,19 +307,247 @@ fn sort_into_buckets<F: FnMut(Link)>(
     let mut buckets = Buckets::default();
 
     for link in links {
-        match link.uri.scheme_str() {
-            Some("http") | Some("https") => buckets.web.push(link.clone()),
-            None | Some("file") => buckets.file.push(link.clone()),
-            _ => unknown_schema(link.clone()),
+        match Bucket::of(link) {
+            Bucket::File => buckets.file.push(link.clone()),
+            Bucket::Contact(scheme) => {
+                buckets.contact.push((link.clone(), scheme))
+            },
+            Bucket::Web => buckets.web.push(link.clone()),
+            Bucket::Unknown => unknown_schema(link.clone()),
         }
     }
 
     buckets
 }
 
+/// Which part of [`validate()`] is responsible for checking a link.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Bucket {
+    File,
+    Contact(ContactScheme),
+    Web,
+    Unknown,
+}
+
+impl Bucket {
+    fn of(link: &Link) -> Bucket {
+        if let Some(scheme) = ContactScheme::of(link) {
+            return Bucket::Contact(scheme);
+        }
+
+        match link.uri.scheme_str() {
+            Some("http") | Some("https") => Bucket::Web,
+            None | Some("file") => Bucket::File,
+            _ => Bucket::Unknown,
+        }
+    }
+}
+
+/// What [`validate()`] would do with a link, worked out from the [`Config`]
+/// without checking anything (see [`crate::write_link_list()`]).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Action {
+    /// The link would be checked over the network.
+    Fetch,
+    /// The link would be checked without touching the network (e.g. a link
+    /// to another chapter).
+    Local,
+    /// The link wouldn't be checked, for the provided reason.
+    Skip(&'static str),
+}
+
+impl Action {
+    /// Work out what [`validate()`] would do with this link, where `src_dir`
+    /// is the same (absolute) source directory it would be given.
+    ///
+    /// Only the [`Config`] is consulted, so links which pass are still
+    /// subject to things which need the network (e.g. `robots.txt`).
+    pub fn of(
+        link: &Link,
+        cfg: &Config,
+        src_dir: &Path,
+        files: &Files,
+    ) -> Action {
+        let link = link.with_default_scheme(cfg.scheme_relative_default.name());
+
+        match Bucket::of(&link) {
+            Bucket::File => local_action(&link, cfg, src_dir, files),
+            Bucket::Contact(scheme) => contact_action(scheme, cfg),
+            Bucket::Web => web_action(&link, cfg, files),
+            Bucket::Unknown => Action::Skip("unknown scheme"),
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Fetch => f.pad("fetch"),
+            Action::Local => f.pad("local"),
+            Action::Skip(reason) => write!(f, "skip ({})", reason),
+        }
+    }
+}
+
+/// Should this web link be fetched?
+fn web_action(link: &Link, cfg: &Config, files: &Files) -> Action {
+    if cfg.offline {
+        Action::Skip("offline")
+    } else if !cfg.follow_web_links {
+        Action::Skip("follow-web-links is off")
+    } else if let Some(reason) = web_skip_reason(link, cfg, files) {
+        Action::Skip(reason)
+    } else {
+        Action::Fetch
+    }
+}
+
+/// Why the user has asked for this web link to be left alone, if they have.
+fn web_skip_reason(
+    link: &Link,
+    cfg: &Config,
+    files: &Files,
+) -> Option<&'static str> {
+    let uri = link.uri.to_string();
+    let name = Path::new(files.name(link.file));
+
+    if !cfg.should_check(&uri) || cfg.should_skip_in(name, &uri) {
+        Some("excluded")
+    } else if cfg.skips_loopback(&link.uri) {
+        Some("loopback address")
+    } else {
+        None
+    }
+}
+
+/// Should this `mailto:` or `tel:` link be checked?
+fn contact_action(scheme: ContactScheme, cfg: &Config) -> Action {
+    match scheme {
+        ContactScheme::Mailto if !cfg.check_mailto => {
+            Action::Skip("check-mailto is off")
+        },
+        ContactScheme::Tel if !cfg.check_tel => {
+            Action::Skip("check-tel is off")
+        },
+        _ => Action::Local,
+    }
+}
+
+/// Should this link to a file in the book (or a heading on the same page)
+/// be checked?
+fn local_action(
+    link: &Link,
+    cfg: &Config,
+    root_dir: &Path,
+    files: &Files,
+) -> Action {
+    if link.uri.path().is_empty() {
+        // it's a link within the same document
+        return match link.fragment {
+            Some(_) if cfg.check_anchors => Action::Local,
+            Some(_) => Action::Skip("check-anchors is off"),
+            None => Action::Skip("empty link"),
+        };
+    }
+
+    let path = link.as_filesystem_path(root_dir, files);
+    if cfg.checks_local_extension(&path) {
+        Action::Local
+    } else {
+        Action::Skip("local-link-extensions")
+    }
+}
+
+/// Report links which aren't well-formed web URLs (see
+/// [`Config::check_url_syntax`]), returning the rest.
+fn remove_malformed_urls(
+    links: Vec<Link>,
+    cfg: &Config,
+    outcome: &mut ValidationOutcome,
+) -> Vec<Link> {
+    let mut remaining = Vec::new();
+
+    for link in links {
+        let checked = cfg.should_check(&link.uri.to_string());
+
+        match crate::url_syntax::check(&link) {
+            Err(reason) if checked => {
+                log::debug!("\"{}\" is malformed: {}", link.uri, reason);
+                outcome.invalid_links.push(InvalidLink { link, reason });
+            },
+            _ => remaining.push(link),
+        }
+    }
+
+    remaining
+}
+
+/// Make sure `mailto:` and `tel:` links are well-formed, if the user asked us
+/// to (see [`Config::check_mailto`] and [`Config::check_tel`]), optionally
+/// looking up email domains with `resolves` (see
+/// [`Config::check_mailto_dns`]).
+///
+/// Each domain is only looked up once, and the lookups happen in parallel.
+/// Nothing is looked up when we're [`Config::offline`].
+fn validate_contact_links<R>(
+    links: Vec<(Link, ContactScheme)>,
+    cfg: &Config,
+    outcome: &mut ValidationOutcome,
+    resolves: R,
+) where
+    R: Fn(&str) -> bool + Sync,
+{
+    let look_up_domains = cfg.check_mailto_dns && !cfg.offline;
+    let mut needs_lookup = Vec::new();
+
+    for (link, scheme) in links {
+        if let Action::Skip(_) = contact_action(scheme, cfg) {
+            outcome.ignored.push(link);
+            continue;
+        }
+
+        match scheme.check(&link) {
+            Err(reason) => {
+                outcome.invalid_links.push(InvalidLink { link, reason })
+            },
+            Ok(()) if scheme == ContactScheme::Mailto && look_up_domains => {
+                needs_lookup.push(link)
+            },
+            Ok(()) => outcome.valid_links.push(link),
+        }
+    }
+
+    let domains: HashSet<String> = needs_lookup
+        .iter()
+        .flat_map(crate::contact::email_domains)
+        .collect();
+    log::debug!("Looking up {} email domains", domains.len());
+    let resolved: HashMap<String, bool> = domains
+        .into_par_iter()
+        .map(|domain| {
+            let ok = resolves(&domain);
+            (domain, ok)
+        })
+        .collect();
+
+    for link in needs_lookup {
+        let unresolvable = crate::contact::email_domains(&link)
+            .into_iter()
+            .find(|domain| !resolved[domain]);
+
+        match unresolvable {
+            Some(domain) => outcome.invalid_links.push(InvalidLink {
+                link,
+                reason: Reason::UnresolvableEmailDomain(domain),
+            }),
+            None => outcome.valid_links.push(link),
+        }
+    }
+}
+
 fn validate_local_links(
     links: &[Link],
-    traverse_parent_directories: bool,
+    cfg: &Config,
     root_dir: &Path,
     outcome: &mut ValidationOutcome,
     files: &Files,
@@ -116,31 +557,118 @@ fn validate_local_links(
         "The root directory should be absolute"
     );
 
+    let mut anchors_by_file = HashMap::new();
+    let mut anchors_by_chapter = HashMap::new();
+
     for link in links {
-        if link.uri.path() == "" {
-            // it's a link within the same document
+        if let Action::Skip(reason) = local_action(link, cfg, root_dir, files)
+        {
+            log::debug!("Ignoring \"{}\" ({})", link.uri, reason);
+            outcome.ignored.push(link.clone());
             continue;
         }
 
-        let path = link.as_filesystem_path(root_dir, files);
-        match validate_local_link(
-            link,
-            root_dir,
-            &path,
-            traverse_parent_directories,
-        ) {
+        let result = if link.uri.path() == "" {
+            // it's a link within the same document
+            match link.fragment {
+                Some(ref fragment) => {
+                    let anchors =
+                        anchors_by_chapter.entry(link.file).or_insert_with(
+                            || crate::links::anchors(files.source(link.file)),
+                        );
+                    check_anchor(
+                        fragment,
+                        anchors,
+                        cfg.case_insensitive_fragments,
+                    )
+                },
+                None => continue,
+            }
+        } else {
+            let path = link.as_filesystem_path(root_dir, files);
+            validate_local_link(
+                root_dir,
+                &path,
+                cfg.traverse_parent_directories,
+            )
+            .and_then(|target| match link.fragment {
+                Some(ref fragment) if cfg.check_anchors => {
+                    match anchors_in_file(&target, &mut anchors_by_file) {
+                        Some(anchors) => check_anchor(
+                            fragment,
+                            anchors,
+                            cfg.case_insensitive_fragments,
+                        ),
+                        None => Ok(()),
+                    }
+                },
+                _ => Ok(()),
+            })
+        };
+
+        match result {
             Ok(()) => outcome.valid_links.push(link.clone()),
-            Err(e) => outcome.invalid_links.push(e),
+            Err(reason) => outcome.invalid_links.push(InvalidLink {
+                link: link.clone(),
+                reason,
+            }),
         }
     }
 }
 
-fn validate_local_link(
-    link: &Link,
+/// Get the anchors defined by a markdown file, reusing the results from
+/// previous calls. Returns `None` if the file isn't markdown or can't be read.
+fn anchors_in_file<'a>(
+    path: &Path,
+    cache: &'a mut HashMap<PathBuf, Option<HashSet<String>>>,
+) -> Option<&'a HashSet<String>> {
+    cache
+        .entry(path.to_path_buf())
+        .or_insert_with(|| {
+            if path.extension() != Some(OsStr::new("md")) {
+                return None;
+            }
+
+            match fs::read_to_string(path) {
+                Ok(src) => Some(crate::links::anchors(&src)),
+                Err(e) => {
+                    log::warn!("Unable to read {}: {}", path.display(), e);
+                    None
+                },
+            }
+        })
+        .as_ref()
+}
+
+fn check_anchor(
+    fragment: &str,
+    anchors: &HashSet<String>,
+    ignore_case: bool,
+) -> Result<(), Reason> {
+    let decoded = percent_encoding::percent_decode_str(fragment)
+        .decode_utf8_lossy();
+    let found = if ignore_case {
+        let lowercase = decoded.to_lowercase();
+        anchors.iter().any(|anchor| anchor.to_lowercase() == lowercase)
+    } else {
+        anchors.contains(decoded.as_ref())
+    };
+
+    // an empty fragment just points at the top of the page
+    if decoded.is_empty() || found {
+        Ok(())
+    } else {
+        log::trace!("There is no \"#{}\" anchor", decoded);
+        Err(Reason::AnchorNotFound(decoded.into_owned()))
+    }
+}
+
+/// Check a link to a local file, returning the file it actually resolves to.
+pub(crate) fn validate_local_link(
     root_dir: &Path,
     path: &Path,
     traverse_parent_directories: bool,
-) -> Result<(), InvalidLink> {
+) -> Result<PathBuf, Reason> {
     let path = match dunce::canonicalize(&path) {
         Ok(p) => p,
 
@@ -149,7 +677,6 @@ fn validate_local_link(
         Err(_) if path.extension() == Some(OsStr::new("html")) => {
             let path = path.with_extension("md");
             return validate_local_link(
-                link,
                 root_dir,
                 &path,
                 traverse_parent_directories,
@@ -157,11 +684,19 @@ fn validate_local_link(
         },
 
         Err(e) => {
+            // a missing file outside the book is still an attempt to escape it
+            let normalized = normalize_path(path);
+            if !normalized.starts_with(root_dir) && !traverse_parent_directories
+            {
+                log::trace!(
+                    "\"{}\" lies outside the root directory and that is forbidden",
+                    normalized.display()
+                );
+                return Err(Reason::TraversesParentDirectories(normalized));
+            }
+
             log::warn!("Unable to canonicalize {}: {}", path.display(), e);
-            return Err(InvalidLink {
-                link: link.clone(),
-                reason: Reason::FileNotFound,
-            });
+            return Err(Reason::FileNotFound);
         },
     };
 
@@ -169,41 +704,52 @@ fn validate_local_link(
 
     if !path.starts_with(root_dir) && !traverse_parent_directories {
         log::trace!("It lies outside the root directory and that is forbidden");
-        Err(InvalidLink {
-            link: link.clone(),
-            reason: Reason::TraversesParentDirectories,
-        })
-    } else if file_exists(&path) {
-        Ok(())
+        Err(Reason::TraversesParentDirectories(path))
+    } else if let Some(target) = resolve_file(&path) {
+        Ok(target)
     } else {
         log::trace!("It doesn't exist");
-        Err(InvalidLink {
-            link: link.clone(),
-            reason: Reason::FileNotFound,
-        })
+        Err(Reason::FileNotFound)
+    }
+}
+
+/// Resolve any `.` and `..` components without touching the filesystem, for
+/// paths which can't be canonicalized because they don't exist.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                normalized.pop();
+            },
+            other => normalized.push(other),
+        }
     }
+
+    normalized
 }
 
-fn file_exists(path: &Path) -> bool {
+/// Find the file a path refers to, if it exists.
+fn resolve_file(path: &Path) -> Option<PathBuf> {
     if path.is_file() {
-        return true;
+        return Some(path.to_path_buf());
     }
 
     // as a special case, handle links to the rendered html file
     if path.extension() == Some("html".as_ref())
         && path.with_extension("md").is_file()
     {
-        return true;
+        return Some(path.with_extension("md"));
     }
 
     // e.g. "./some-dir/" -> "./some-dir/index.md"
-    if path.is_dir()
-        && (path.join("index.md").is_file() || path.join("index.md").is_file())
-    {
-        return true;
+    if path.is_dir() && path.join("index.md").is_file() {
+        return Some(path.join("index.md"));
     }
 
-    false
+    None
 }
 
 fn validate_web_links(
@@ -212,283 +758,3593 @@ fn validate_web_links(
     outcome: &mut ValidationOutcome,
     cache: &Cache,
 ) -> Result<(), Error> {
-    let client = create_client(cfg)?;
+    let clients = Clients::new(cfg)?;
+    let throttle = Throttle::new(cfg);
+
+    let allowed;
+    let links = if cfg.respect_robots_txt {
+        let (ok, disallowed) =
+            apply_robots_txt(links, cfg, &clients, &throttle);
+        outcome.disallowed_by_robots = disallowed;
+        allowed = ok;
+        &allowed[..]
+    } else {
+        links
+    };
+
+    // a book will often link to the same page from lots of places, so each
+    // target is only checked once and the result shared between its links
+    let mut targets: HashMap<String, (&Link, usize)> = HashMap::new();
+    for link in links {
+        targets.entry(target_key(link, cfg)).or_insert((link, 0)).1 += 1;
+    }
+
+    let progress = Progress::new(links.len(), cfg);
+    let results: HashMap<String, Result<Option<Redirect>, Reason>> = targets
+        .into_par_iter()
+        .map(|(key, (link, count))| {
+            progress.contacting(link.uri.host().unwrap_or_default());
+            let result = check_link(link, &clients, cfg, cache, &throttle);
+            progress.checked(count);
+            (key, result)
+        })
+        .collect();
+    progress.finish();
 
     let (valid, invalid): (Vec<_>, Vec<_>) =
         links.par_iter().partition_map(|link| {
-            match check_link(link, &client, cfg, cache) {
+            match results[&target_key(link, cfg)] {
                 Ok(_) => Either::Left(link.clone()),
-                Err(e) => Either::Right(InvalidLink {
+                Err(ref reason) => Either::Right(InvalidLink {
                     link: link.clone(),
-                    reason: e,
+                    reason: reason.clone(),
                 }),
             }
         });
 
+    if cfg.warn_on_http {
+        outcome.insecure_links = find_insecure_links(&valid, |https| {
+            check_link(https, &clients, cfg, cache, &throttle).is_ok()
+        });
+    }
+
+    if cfg.warn_on_redirect {
+        outcome.redirected_links = valid
+            .iter()
+            .filter_map(|link| match results[&target_key(link, cfg)] {
+                Ok(Some(ref redirect)) => redirect.for_link(link, cfg),
+                _ => None,
+            })
+            .collect();
+    }
+
     outcome.valid_links.extend(valid);
     outcome.invalid_links.extend(invalid);
 
     Ok(())
 }
 
-fn create_client(cfg: &Config) -> Result<Client, Error> {
-    let mut headers = HeaderMap::new();
-    headers.insert(reqwest::header::USER_AGENT, cfg.user_agent.parse()?);
-
-    let client = Client::builder()
-        .use_sys_proxy()
-        .default_headers(headers)
-        .build()?;
-
-    Ok(client)
-}
-
-fn check_link(
-    link: &Link,
-    client: &Client,
+/// Split links into the ones each site's `robots.txt` allows us to check and
+/// the ones it doesn't (see [`Config::respect_robots_txt`]), fetching every
+/// `robots.txt` once.
+fn apply_robots_txt(
+    links: &[Link],
     cfg: &Config,
-    cache: &Cache,
-) -> Result<(), Reason> {
-    let url = link.uri.to_string();
-
-    if let Some(entry) = cache.lookup(&url) {
-        if entry.successful
-            && entry.elapsed() < Duration::from_secs(cfg.cache_timeout)
-        {
-            log::trace!(
-                "Cached entry for \"{}\" is still fresh and was successful",
-                url
-            );
-            return Ok(());
+    clients: &Clients,
+    throttle: &Throttle,
+) -> (Vec<Link>, Vec<Link>) {
+    let mut origins: HashMap<(String, &str), &Link> = HashMap::new();
+    for link in links {
+        if let Some(key) = robots_origin(link) {
+            origins.entry(key).or_insert(link);
         }
     }
 
-    let mut request = client.get(&url);
+    let robots: HashMap<String, crate::robots::Robots> = origins
+        .into_par_iter()
+        .map(|((origin, host), link)| {
+            let permit = throttle.acquire(host);
+            let client = clients.for_link(link, cfg);
+            let robots = crate::robots::fetch(&origin, client, cfg);
+            drop(permit);
 
-    for (pattern, headers) in cfg.http_headers.iter() {
-        if pattern.find(&url).is_some() {
-            log::trace!("Applying extra headers to `{}`", url);
-            for header in headers {
-                log::trace!("  Applying `{}`", header.interpolated_value);
-                request = request.header(&header.name, &header.interpolated_value);
+            if let Some(delay) = robots.crawl_delay() {
+                let delay = delay.min(Duration::from_secs(cfg.max_retry_after));
+                log::debug!("Waiting {:?} between requests to {}", delay, host);
+                throttle.set_crawl_delay(host, delay);
             }
+
+            (origin, robots)
+        })
+        .collect();
+
+    links.iter().cloned().partition(|link| {
+        let path = link
+            .uri
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+
+        match robots_origin(link) {
+            Some((origin, _)) if !robots[&origin].allows(path) => {
+                log::debug!("\"{}\" is disallowed by robots.txt", link.uri);
+                false
+            },
+            _ => true,
         }
-    }
+    })
+}
 
-    log::trace!("Sending a GET request to \"{}\"", url);
+/// The `scheme://authority` a link's `robots.txt` lives at, and the
+/// authority (the key used by the [`Throttle`]).
+fn robots_origin(link: &Link) -> Option<(String, &str)> {
+    let scheme = link.uri.scheme_str()?;
+    let authority = link.uri.authority_part()?.as_str();
 
-    match request.send() {
-        Ok(ref response) if response.status().is_success() => {
-            cache.insert(url, CacheEntry::new(SystemTime::now(), true));
-            Ok(())
-        },
-        Ok(response) => {
-            let status = response.status();
-            log::trace!("\"{}\" replied with {}", url, status);
-            cache.insert(url, CacheEntry::new(SystemTime::now(), false));
-            Err(Reason::UnsuccessfulServerResponse(status))
-        },
-        Err(e) => {
-            log::trace!("Request to \"{}\" failed: {}", url, e);
-            cache.insert(url, CacheEntry::new(SystemTime::now(), false));
-            Err(Reason::Client(e))
+    Some((format!("{}://{}", scheme, authority), authority))
+}
+
+/// The thing a web link actually checks, used to spot links which would make
+/// identical requests. The fragment only matters when we're going to look
+/// for it in the page.
+fn target_key(link: &Link, cfg: &Config) -> String {
+    let url = canonical_url(&link.uri);
+
+    match link.fragment {
+        Some(ref fragment)
+            if cfg.check_remote_fragments && !fragment.is_empty() =>
+        {
+            format!("{}#{}", url, fragment)
         },
+        _ => url,
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
-struct Buckets {
-    web: Vec<Link>,
-    file: Vec<Link>,
+/// Normalise a URL so equivalent spellings compare equal, lowercasing the
+/// scheme and host and dropping the port when it's the scheme's default.
+fn canonical_url(uri: &Uri) -> String {
+    let scheme = match uri.scheme_str() {
+        Some(scheme) => scheme.to_ascii_lowercase(),
+        None => return uri.to_string(),
+    };
+    let host = uri.host().unwrap_or_default().to_ascii_lowercase();
+    let port = match (scheme.as_str(), uri.port_part()) {
+        ("http", Some(port)) if port.as_u16() == 80 => None,
+        ("https", Some(port)) if port.as_u16() == 443 => None,
+        (_, port) => port,
+    };
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    match port {
+        Some(port) => format!("{}://{}:{}{}", scheme, host, port, path),
+        None => format!("{}://{}{}", scheme, host, path),
+    }
 }
 
-/// The outcome of validating a set of links.
-#[derive(Debug, Default)]
-pub struct ValidationOutcome {
-    /// Valid links.
-    pub valid_links: Vec<Link>,
-    /// Links where validation failed.
-    pub invalid_links: Vec<InvalidLink>,
-    /// Links which have been ignored (e.g. due to
-    /// [`Config::follow_web_links`]).
-    pub ignored: Vec<Link>,
-    /// Links which we don't know how to handle.
-    pub unknown_schema: Vec<Link>,
-    /// Potentially incomplete links.
-    pub incomplete_links: Vec<IncompleteLink>,
+/// Find the `http://` links which also work when upgraded to `https://`.
+fn find_insecure_links<F>(links: &[Link], works: F) -> Vec<InsecureLink>
+where
+    F: Fn(&Link) -> bool + Sync,
+{
+    links
+        .par_iter()
+        .filter_map(|link| {
+            let https = https_equivalent(link)?;
+
+            if works(&https) {
+                log::debug!("\"{}\" also works over HTTPS", link.uri);
+                Some(InsecureLink {
+                    link: link.clone(),
+                    https_url: Some(https.uri.to_string()),
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
 }
 
-impl ValidationOutcome {
-    /// Generate a list of [`Diagnostic`] messages from this
-    /// [`ValidationOutcome`].
-    pub fn generate_diagnostics(
-        &self,
-        files: &Files,
-        warning_policy: WarningPolicy,
-    ) -> Vec<Diagnostic> {
-        let mut diags = Vec::new();
+/// Flag every `http://` link which wasn't already found to work over
+/// `https://` (see [`Config::warn_on_insecure_http`]). Links are never
+/// fetched, so this also works offline, but links which would be skipped by
+/// web link validation are left alone.
+fn add_plain_http_links(
+    links: &[Link],
+    cfg: &Config,
+    outcome: &mut ValidationOutcome,
+    files: &Files,
+) {
+    let already_flagged: HashSet<(FileId, Span)> = outcome
+        .insecure_links
+        .iter()
+        .map(|i| (i.link.file, i.link.span))
+        .collect();
 
-        self.add_invalid_link_diagnostics(&mut diags);
+    let plain_http = links
+        .iter()
+        .filter(|link| link.uri.scheme_str() == Some("http"))
+        .filter(|link| web_skip_reason(link, cfg, files).is_none())
+        .filter(|link| !already_flagged.contains(&(link.file, link.span)))
+        .map(|link| InsecureLink {
+            link: link.clone(),
+            https_url: None,
+        })
+        .collect::<Vec<_>>();
 
-        match warning_policy {
-            WarningPolicy::Error => self.add_incomplete_link_diagnostics(
-                Severity::Error,
-                &mut diags,
-                files,
-            ),
-            WarningPolicy::Warn => self.add_incomplete_link_diagnostics(
-                Severity::Warning,
-                &mut diags,
-                files,
-            ),
-            WarningPolicy::Ignore => {},
-        }
+    log::debug!("Found {} plain \"http://\" links", plain_http.len());
+    outcome.insecure_links.extend(plain_http);
+}
 
-        diags
+/// The same link, but using `https://`. Links with an explicit port are
+/// skipped because the same port is unlikely to speak both protocols.
+fn https_equivalent(link: &Link) -> Option<Link> {
+    if link.uri.scheme_str() != Some("http")
+        || link.uri.port_part().is_some()
+    {
+        return None;
     }
 
-    fn add_incomplete_link_diagnostics(
-        &self,
-        severity: Severity,
-        diags: &mut Vec<Diagnostic>,
-        files: &Files,
-    ) {
-        for incomplete in &self.incomplete_links {
-            let IncompleteLink { ref text, file } = incomplete;
-            let span = resolve_incomplete_link_span(incomplete, files);
-            let msg =
-                format!("Did you forget to define a URL for `{0}`?", text);
-            let label = Label::new(*file, span, msg);
-            let note = format!(
-                "hint: declare the link's URL. For example: `[{}]: http://example.com/`",
-                text
-            );
-            let diag =
-                Diagnostic::new(severity, "Potential incomplete link", label)
-                    .with_notes(vec![note]);
-            diags.push(diag)
+    let mut parts = link.uri.clone().into_parts();
+    parts.scheme = Some(http::uri::Scheme::HTTPS);
+    let uri = Uri::from_parts(parts).ok()?;
+
+    Some(Link { uri, ..link.clone() })
+}
+
+/// The HTTP clients used to check web links. Certificates are only skipped
+/// for hosts which need it, so those get a client of their own.
+struct Clients {
+    default: Client,
+    insecure: Option<Client>,
+    /// The redirects each client followed, when we want to report them (see
+    /// [`Config::warn_on_redirect`]).
+    redirects: Option<Arc<RedirectLog>>,
+}
+
+impl Clients {
+    fn new(cfg: &Config) -> Result<Clients, Error> {
+        let redirects = if cfg.warn_on_redirect {
+            Some(Arc::new(RedirectLog::default()))
+        } else {
+            None
+        };
+        let default = create_client(
+            cfg,
+            cfg.allow_insecure_certificates,
+            redirects.clone(),
+        )?;
+        let insecure = if cfg.insecure_hosts.is_empty() {
+            None
+        } else {
+            Some(create_client(cfg, true, redirects.clone())?)
+        };
+
+        Ok(Clients {
+            default,
+            insecure,
+            redirects,
+        })
+    }
+
+    fn for_link(&self, link: &Link, cfg: &Config) -> &Client {
+        match (&self.insecure, link.uri.host()) {
+            (Some(insecure), Some(host))
+                if cfg.allows_insecure_certificates(host) =>
+            {
+                insecure
+            },
+            _ => &self.default,
         }
     }
+}
 
-    fn add_invalid_link_diagnostics(&self, diags: &mut Vec<Diagnostic>) {
-        for broken_link in &self.invalid_links {
-            let link = &broken_link.link;
-            let diag = Diagnostic::new_error(
-                broken_link.to_string(),
-                Label::new(
-                    link.file,
-                    link.span,
-                    broken_link.reason.to_string(),
-                ),
-            );
-            diags.push(diag);
+/// The status codes of the redirects followed for each URL, keyed by the
+/// URL which was originally requested.
+#[derive(Debug, Default)]
+pub(crate) struct RedirectLog(Mutex<HashMap<String, Vec<StatusCode>>>);
+
+impl RedirectLog {
+    /// Called by the [`RedirectPolicy`] every time a server tells us to go
+    /// somewhere else.
+    fn record(&self, previous: &[reqwest::Url], status: StatusCode) {
+        let original = match previous.first() {
+            Some(url) => url.as_str().to_string(),
+            None => return,
+        };
+        let mut log = self.0.lock().expect("The lock was poisoned");
+        let statuses = log.entry(original).or_default();
+
+        // this is the first hop of a new request (e.g. a retry)
+        if previous.len() == 1 {
+            statuses.clear();
         }
+        statuses.push(status);
+    }
+
+    /// Take the status codes of the redirects followed when requesting
+    /// `url`.
+    fn take(&self, url: &str) -> Vec<StatusCode> {
+        let key = match reqwest::Url::parse(url) {
+            Ok(url) => url.into_string(),
+            Err(_) => url.to_string(),
+        };
+
+        self.0
+            .lock()
+            .expect("The lock was poisoned")
+            .remove(&key)
+            .unwrap_or_default()
     }
 }
 
-/// HACK: this is a workaround for
-/// [pulldown-cmark#165](https://github.com/raphlinus/pulldown-cmark/issues/165)
-/// which uses good ol' string searching to find where an incomplete link may
-/// have been defined.
-fn resolve_incomplete_link_span(
-    incomplete: &IncompleteLink,
-    files: &Files,
-) -> Span {
-    let needle = format!("[{}]", incomplete.text);
-    let src = files.source(incomplete.file);
+pub(crate) fn create_client(
+    cfg: &Config,
+    accept_invalid_certs: bool,
+    redirect_log: Option<Arc<RedirectLog>>,
+) -> Result<Client, Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert(reqwest::header::USER_AGENT, cfg.user_agent.parse()?);
 
-    match src.find(&needle).map(|ix| ix as u32) {
-        Some(start_ix) => Span::new(start_ix, start_ix + needle.len() as u32),
-        None => files.source_span(incomplete.file),
+    let timeout = match cfg.request_timeout {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    };
+    let redirects = match cfg.max_redirects {
+        0 => RedirectPolicy::none(),
+        // reqwest counts the original URL as part of the redirect chain
+        n => RedirectPolicy::limited(n + 1),
+    };
+    let redirects = match redirect_log {
+        Some(log) => RedirectPolicy::custom(move |attempt| {
+            log.record(attempt.previous(), attempt.status());
+            redirects.redirect(attempt)
+        }),
+        None => redirects,
+    };
+
+    let mut builder = Client::builder()
+        .danger_accept_invalid_certs(accept_invalid_certs)
+        .proxy(Proxies::from_env(cfg)?.into_proxy())
+        .default_headers(headers)
+        .connect_timeout(timeout)
+        .timeout(timeout)
+        .local_address(cfg.dns_resolution.local_address())
+        .redirect(redirects);
+
+    if let Some(identity) = crate::identity::client_identity(cfg)? {
+        builder = builder.identity(identity);
+    }
+    if let Some(cert) = crate::identity::root_certificate(cfg)? {
+        builder = builder.add_root_certificate(cert);
     }
+
+    let client = builder.build()?;
+
+    Ok(client)
 }
 
-/// An invalid [`Link`] and the [`Reason`] for why it isn't valid.
-#[derive(Debug)]
-pub struct InvalidLink {
-    /// The dodgy link.
-    pub link: Link,
-    /// Why the link isn't valid.
-    pub reason: Reason,
+/// Where a link ended up after following redirects (see
+/// [`Config::warn_on_redirect`]).
+#[derive(Debug, Clone, PartialEq)]
+struct Redirect {
+    url: String,
+    /// Was every redirect along the way permanent (`301` or `308`)?
+    permanent: bool,
 }
 
-impl Display for InvalidLink {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self.reason {
-            Reason::FileNotFound => write!(f, "File not found: {}", self.link.uri),
-            Reason::TraversesParentDirectories => {
-                write!(f, "\"{}\" links outside of the book directory, but this is forbidden", self.link.uri)
-            },
-            Reason::UnsuccessfulServerResponse(code) => {
-                write!(f, "The server responded with {} for \"{}\"", code, self.link.uri)
-            },
-            Reason::Client(ref err) => write!(f, "Unable to retrieve \"{}\": {}", self.link.uri, err),
+impl Redirect {
+    /// The [`RedirectedLink`] to report for this link, if any.
+    fn for_link(&self, link: &Link, cfg: &Config) -> Option<RedirectedLink> {
+        if !self.permanent && !cfg.warn_on_temporary_redirect {
+            return None;
         }
+
+        let url = match link.fragment {
+            Some(ref fragment) if !fragment.is_empty() => {
+                format!("{}#{}", self.url, fragment)
+            },
+            _ => self.url.clone(),
+        };
+
+        Some(RedirectedLink {
+            link: link.clone(),
+            url,
+            permanent: self.permanent,
+        })
     }
 }
 
-/// Why is this [`Link`] invalid?
-#[derive(Debug)]
-pub enum Reason {
-    /// The link points to a file that doesn't exist.
-    FileNotFound,
-    /// The link points to a file outside of the book directory, and traversing
-    /// outside the book directory is forbidden.
-    TraversesParentDirectories,
-    /// The server replied with an unsuccessful status code (according to
-    /// [`StatusCode::is_success()`]).
-    UnsuccessfulServerResponse(StatusCode),
-    /// An error was encountered while checking a web link.
-    Client(reqwest::Error),
+fn check_link(
+    link: &Link,
+    clients: &Clients,
+    cfg: &Config,
+    cache: &Cache,
+    throttle: &Throttle,
+) -> Result<Option<Redirect>, Reason> {
+    let url = link.uri.to_string();
+    let fragment = link
+        .fragment
+        .as_ref()
+        .filter(|fragment| cfg.check_remote_fragments && !fragment.is_empty());
+    // a page may be fine while the fragment isn't, so they're cached separately
+    let cache_key = match fragment {
+        Some(fragment) => format!("{}#{}", url, fragment),
+        None => url.clone(),
+    };
+
+    let lookup = cache.lookup(&cache_key, |entry| {
+        // failures can only be reused if we know why they failed
+        entry.elapsed() < cfg.cache_ttl(&url, entry.successful)
+            && (entry.successful || entry.failure.is_some())
+    });
+
+    let previous = match lookup {
+        Lookup::Hit(entry) if entry.successful => {
+            log::trace!(
+                "Cached entry for \"{}\" is still fresh and was successful",
+                url
+            );
+            let permanent = entry.permanent_redirect;
+            let redirect = entry
+                .redirected_to
+                .map(|url| Redirect { url, permanent });
+            return Ok(redirect);
+        },
+        Lookup::Hit(entry) => {
+            log::trace!(
+                "Cached entry for \"{}\" is still fresh and failed",
+                url
+            );
+            return Err(Reason::CachedFailure(
+                entry.failure.unwrap_or_default(),
+            ));
+        },
+        Lookup::Expired(entry) => Some(entry),
+        Lookup::Miss => None,
+    };
+
+    let host = link
+        .uri
+        .authority_part()
+        .map(|authority| authority.as_str())
+        .unwrap_or_default();
+    let result = fetch_with_retries(
+        &url,
+        fragment.map(String::as_str),
+        previous.as_ref().filter(|entry| entry.can_revalidate()),
+        host,
+        clients.for_link(link, cfg),
+        cfg,
+        throttle,
+    );
+
+    // only redirects we know the status codes for can be reported
+    let statuses = match clients.redirects {
+        Some(ref log) => log.take(&url),
+        None => Vec::new(),
+    };
+    let redirect = match result {
+        Ok(Validators {
+            final_url: Some(ref final_url),
+            ..
+        }) if !statuses.is_empty() => Some(Redirect {
+            url: final_url.clone(),
+            permanent: statuses.iter().all(|&status| {
+                status == StatusCode::MOVED_PERMANENTLY
+                    || status == StatusCode::PERMANENT_REDIRECT
+            }),
+        }),
+        _ => None,
+    };
+
+    let mut entry = CacheEntry::new(SystemTime::now(), result.is_ok());
+    match result {
+        Ok(ref validators) => {
+            entry.etag = validators.etag.clone();
+            entry.last_modified = validators.last_modified.clone();
+        },
+        Err(ref reason) => entry.failure = Some(reason.to_string()),
+    }
+    if let Some(ref redirect) = redirect {
+        entry.redirected_to = Some(redirect.url.clone());
+        entry.permanent_redirect = redirect.permanent;
+    }
+    cache.insert(cache_key, entry);
+
+    result.map(|_| redirect)
 }
 
-impl Reason {
-    /// A convenience function for determining if the underlying request timed
-    /// out.
-    pub fn timed_out(&self) -> bool {
-        if let Reason::Client(ref inner) = self {
-            inner.is_timeout()
-        } else {
-            false
+/// The `ETag` and `Last-Modified` headers from a successful response, which
+/// let us make a conditional request the next time the link is checked.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Where we ended up, if the server redirected us somewhere else.
+    final_url: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Validators {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+
+        Validators {
+            etag: get(reqwest::header::ETAG),
+            last_modified: get(reqwest::header::LAST_MODIFIED),
+            final_url: None,
+        }
+    }
+
+    /// Update the validators with any new values, keeping the old ones if
+    /// they weren't resent.
+    fn or(self, previous: &CacheEntry) -> Validators {
+        Validators {
+            etag: self.etag.or_else(|| previous.etag.clone()),
+            last_modified: self
+                .last_modified
+                .or_else(|| previous.last_modified.clone()),
+            final_url: self.final_url,
         }
     }
 }
 
-impl Display for Reason {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Reason::FileNotFound => "File not found".fmt(f),
-            Reason::TraversesParentDirectories => {
-                "Linking outside of the book directory is forbidden".fmt(f)
+/// Repeatedly [`fetch()`] a URL until it succeeds, fails with a non-transient
+/// error, or we run out of retries.
+///
+/// If we have a `previous` successful result with an `ETag` or
+/// `Last-Modified` header, a conditional request is sent and a
+/// `304 Not Modified` response means the link is still valid.
+fn fetch_with_retries(
+    url: &str,
+    fragment: Option<&str>,
+    previous: Option<&CacheEntry>,
+    host: &str,
+    client: &Client,
+    cfg: &Config,
+    throttle: &Throttle,
+) -> Result<Validators, Reason> {
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+
+        let permit = throttle.acquire(host);
+        let outcome = fetch(url, fragment, previous, client, cfg);
+        drop(permit);
+
+        match outcome {
+            Err(Failure {
+                ref reason,
+                retry_after,
+            }) if reason.is_transient() && attempts <= cfg.max_retries => {
+                let max_wait = Duration::from_secs(cfg.max_retry_after);
+                let delay = retry_after
+                    .map(|d| d.min(max_wait))
+                    .unwrap_or_else(|| retry_delay(cfg, attempts));
+                // the authority may contain credentials (e.g.
+                // "user:password@host"), so only the host is logged
+                log::info!(
+                    "Retrying a request to {} in {:?} ({})",
+                    host.rsplit('@').next().unwrap_or_default(),
+                    delay,
+                    reason
+                );
+                log::debug!(
+                    "Attempt {} for \"{}\" failed ({}), retrying in {:?}",
+                    attempts,
+                    url,
+                    reason,
+                    delay
+                );
+                thread::sleep(delay);
             },
-            Reason::UnsuccessfulServerResponse(code) => {
-                write!(f, "Server responded with {}", code)
+            Err(Failure { reason, .. }) if attempts > 1 => {
+                return Err(Reason::RetriesExhausted {
+                    attempts,
+                    last: Box::new(reason),
+                })
             },
-            Reason::Client(ref err) => err.fmt(f),
+            Err(Failure { reason, .. }) => return Err(reason),
+            Ok(validators) => return Ok(validators),
         }
     }
 }
 
-/// An unknown [`Uri::scheme_str()`].
-#[derive(Debug, Clone, PartialEq)]
-pub struct UnknownScheme(pub Link);
+/// The URL a response actually came from, if we were redirected somewhere
+/// other than the `requested` URL.
+fn redirected_to(requested: &str, response: &Response) -> Option<String> {
+    let same = match reqwest::Url::parse(requested) {
+        Ok(requested) => requested == *response.url(),
+        Err(_) => response.url().as_str() == requested,
+    };
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use codespan::Files;
+    if same {
+        None
+    } else {
+        log::debug!(
+            "\"{}\" was redirected to \"{}\"",
+            requested,
+            response.url()
+        );
+        Some(response.url().to_string())
+    }
+}
 
-    #[test]
-    fn sort_links_into_buckets() {
-        let mut files = Files::new();
-        let id = files.add("asd", "");
-        let links = vec![Link::parse("path/to/file.md", 0..1, id).unwrap()];
+/// Why a single [`fetch()`] failed.
+#[derive(Debug)]
+struct Failure {
+    reason: Reason,
+    /// How long the server asked us to wait before trying again.
+    retry_after: Option<Duration>,
+}
 
-        let got = sort_into_buckets(&links, |unknown| {
-            panic!("Unknown schema: {:?}", unknown)
-        });
+impl From<Reason> for Failure {
+    fn from(reason: Reason) -> Failure {
+        Failure {
+            reason,
+            retry_after: None,
+        }
+    }
+}
 
-        assert_eq!(got.file.len(), 1);
-        assert_eq!(got.file[0], links[0]);
+/// Parse the value of a `Retry-After` header, which may either be a number of
+/// seconds or a HTTP date.
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// How long to wait before the next attempt, using exponential backoff seeded
+/// by [`Config::retry_backoff_ms`] and capped at [`Config::max_retry_after`].
+fn retry_delay(cfg: &Config, attempts: u32) -> Duration {
+    let factor = 1_u32 << attempts.saturating_sub(1).min(16);
+    let max_wait = Duration::from_secs(cfg.max_retry_after);
+
+    Duration::from_millis(cfg.retry_backoff_ms)
+        .checked_mul(factor)
+        .map_or(max_wait, |delay| delay.min(max_wait))
+}
+
+/// Send a single request to a URL, making sure the page contains the fragment
+/// (if provided).
+fn fetch(
+    url: &str,
+    fragment: Option<&str>,
+    previous: Option<&CacheEntry>,
+    client: &Client,
+    cfg: &Config,
+) -> Result<Validators, Failure> {
+    // we need the page's body to look for a fragment or check its contents
+    let needs_body = fragment.is_some() || cfg.body_checks_for(url).count() > 0;
+    let method = if needs_body {
+        HttpMethod::Get
+    } else {
+        cfg.http_method_for(url)
+    };
+    let method = http::Method::from(method);
+    let mut response =
+        build_request(client, method.clone(), url, previous, cfg).send();
+
+    let head_was_rejected = match response {
+        Ok(ref r) => rejects_head_requests(r.status()),
+        Err(_) => false,
+    };
+
+    if method == http::Method::HEAD && cfg.fallback_to_get && head_was_rejected
+    {
+        log::trace!("\"{}\" rejected a HEAD request, trying GET", url);
+        response =
+            build_request(client, http::Method::GET, url, previous, cfg).send();
+    }
+
+    match response {
+        Ok(response)
+            if response.status() == StatusCode::NOT_MODIFIED
+                && previous.is_some() =>
+        {
+            log::trace!("\"{}\" hasn't changed since it was last checked", url);
+            let previous = previous.expect("Checked by the match guard");
+            Ok(Validators {
+                final_url: redirected_to(url, &response),
+                ..Validators::from_headers(response.headers()).or(previous)
+            })
+        },
+        Ok(response) if is_successful(url, response.status(), cfg) => {
+            let validators = Validators {
+                final_url: redirected_to(url, &response),
+                ..Validators::from_headers(response.headers())
+            };
+
+            if !needs_body {
+                return Ok(validators);
+            }
+
+            let body = match read_body(url, response, cfg)? {
+                Some(body) => body,
+                None => return Ok(validators),
+            };
+            check_body(url, &body, cfg)?;
+            if let Some(fragment) = fragment {
+                check_remote_fragment(url, fragment, &body, cfg)?;
+            }
+
+            Ok(validators)
+        },
+        Ok(response) => {
+            let status = response.status();
+            log::trace!("\"{}\" replied with {}", url, status);
+            let retry_after = match status {
+                StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::SERVICE_UNAVAILABLE => response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| {
+                        parse_retry_after(value, SystemTime::now())
+                    }),
+                _ => None,
+            };
+
+            Err(Failure {
+                reason: Reason::UnsuccessfulServerResponse(status),
+                retry_after,
+            })
+        },
+        Err(ref e) if e.is_timeout() => {
+            log::trace!("Request to \"{}\" timed out", url);
+            Err(Reason::TimedOut(Duration::from_secs(cfg.request_timeout))
+                .into())
+        },
+        Err(ref e) if e.is_redirect() => {
+            let last_url = e.url().map(ToString::to_string).unwrap_or_default();
+            log::trace!("Request to \"{}\" redirected too many times", url);
+            Err(Reason::TooManyRedirects {
+                limit: cfg.max_redirects,
+                last_url,
+            }
+            .into())
+        },
+        Err(e) => {
+            log::trace!("Request to \"{}\" failed: {}", url, e);
+            Err(Reason::Client(Arc::new(e)).into())
+        },
+    }
+}
+
+/// Build a request for a URL, with any extra headers from the [`Config`] and
+/// conditional request headers for revalidating a `previous` result.
+fn build_request(
+    client: &Client,
+    method: http::Method,
+    url: &str,
+    previous: Option<&CacheEntry>,
+    cfg: &Config,
+) -> RequestBuilder {
+    log::trace!("Sending a {} request to \"{}\"", method, url);
+    let mut request = client.request(method, url);
+
+    if let Some(previous) = previous {
+        if let Some(ref etag) = previous.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, &etag[..]);
+        }
+        if let Some(ref last_modified) = previous.last_modified {
+            request = request
+                .header(reqwest::header::IF_MODIFIED_SINCE, &last_modified[..]);
+        }
+    }
+
+    // Note: headers are appended, so repeating a header (e.g. several
+    // `Cookie` lines) sends every value instead of just the last one
+    for header in cfg.headers_for(url) {
+        log::trace!("Applying `{}` to `{}`", header.value, url);
+        request = request.header(&header.name, &header.interpolated_value);
+    }
+
+    request
+}
+
+/// Status codes servers commonly use when they (or a CDN in front of them)
+/// don't support `HEAD` requests.
+fn rejects_head_requests(status: StatusCode) -> bool {
+    status == StatusCode::METHOD_NOT_ALLOWED
+        || status == StatusCode::NOT_IMPLEMENTED
+        || status == StatusCode::FORBIDDEN
+}
+
+/// (Up to [`Config::max_body_size`] bytes of) a web page's body.
+#[derive(Debug, Clone, PartialEq)]
+struct Body {
+    text: String,
+    /// Did we stop reading before the end of the page?
+    truncated: bool,
+}
+
+/// Read the body of a successful response, or `None` if it couldn't be read
+/// for reasons which aren't the link's fault.
+fn read_body(
+    url: &str,
+    response: Response,
+    cfg: &Config,
+) -> Result<Option<Body>, Reason> {
+    let mut body = Vec::new();
+    // read one byte past the limit so we know whether the body was truncated
+    let read = response
+        .take(cfg.max_body_size.saturating_add(1))
+        .read_to_end(&mut body);
+
+    if let Err(e) = read {
+        // reqwest wraps its own errors (e.g. timeouts) in an io::Error
+        let e = match e.into_inner().map(|inner| inner.downcast()) {
+            Some(Ok(inner)) => return Err(Reason::Client(Arc::new(*inner))),
+            Some(Err(other)) => other.to_string(),
+            None => String::from("unknown error"),
+        };
+        log::warn!("Unable to read the body of \"{}\": {}", url, e);
+        return Ok(None);
+    }
+
+    let truncated = body.len() as u64 > cfg.max_body_size;
+    body.truncate(cfg.max_body_size as usize);
+
+    Ok(Some(Body {
+        text: String::from_utf8_lossy(&body).into_owned(),
+        truncated,
+    }))
+}
+
+/// Make sure a web page contains an element with the fragment as its `id` or
+/// `name`.
+fn check_remote_fragment(
+    url: &str,
+    fragment: &str,
+    body: &Body,
+    cfg: &Config,
+) -> Result<(), Reason> {
+    let anchors = crate::links::html_anchors(&body.text);
+
+    // ids in a HTML page are case-sensitive, so remote fragments always are
+    match check_anchor(fragment, &anchors, false) {
+        Err(_) if body.truncated => {
+            log::warn!(
+                "Unable to find \"#{}\" in the first {} bytes of \"{}\", assuming it exists",
+                fragment,
+                cfg.max_body_size,
+                url
+            );
+            Ok(())
+        },
+        other => other,
+    }
+}
+
+/// Check a web page against [`Config::body_must_contain`] and
+/// [`Config::body_must_not_contain`].
+fn check_body(url: &str, body: &Body, cfg: &Config) -> Result<(), Reason> {
+    for (must_contain, pattern) in cfg.body_checks_for(url) {
+        let found = pattern.is_match(&body.text);
+
+        if must_contain && !found && body.truncated {
+            log::warn!(
+                "Unable to find `{}` in the first {} bytes of \"{}\", assuming it's there",
+                pattern.string,
+                cfg.max_body_size,
+                url
+            );
+        } else if must_contain != found {
+            return Err(Reason::UnexpectedBody {
+                pattern: pattern.string.clone(),
+                must_contain,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Is this a successful response, either because it's a `2xx` or because the
+/// user told us to accept it via [`Config::accepted_status_codes`] or
+/// [`Config::accepted_status_codes_by_url`]?
+fn is_successful(url: &str, status: StatusCode, cfg: &Config) -> bool {
+    status.is_success() || cfg.is_accepted_status(url, status.as_u16())
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Buckets {
+    web: Vec<Link>,
+    file: Vec<Link>,
+    contact: Vec<(Link, ContactScheme)>,
+}
+
+/// The outcome of validating a set of links.
+#[derive(Debug, Default)]
+pub struct ValidationOutcome {
+    /// Valid links.
+    pub valid_links: Vec<Link>,
+    /// Links where validation failed.
+    pub invalid_links: Vec<InvalidLink>,
+    /// Links which have been ignored (e.g. due to
+    /// [`Config::follow_web_links`]).
+    pub ignored: Vec<Link>,
+    /// Links which weren't checked because of a linkcheck directive like
+    /// `<!-- linkcheck-ignore -->`.
+    pub suppressed: Vec<Link>,
+    /// Links which we don't know how to handle.
+    pub unknown_schema: Vec<Link>,
+    /// Web links which weren't checked because of [`Config::offline`].
+    pub skipped_offline: Vec<Link>,
+    /// Potentially incomplete links.
+    pub incomplete_links: Vec<IncompleteLink>,
+    /// Headings which can't be linked to because an earlier heading has the
+    /// same anchor (see [`Config::check_anchors`]).
+    pub duplicate_anchors: Vec<DuplicateAnchor>,
+    /// Valid `http://` links which also work over `https://` (see
+    /// [`Config::warn_on_http`]), and any other `http://` links when
+    /// [`Config::warn_on_insecure_http`] is set.
+    pub insecure_links: Vec<InsecureLink>,
+    /// Valid web links which redirect somewhere else (see
+    /// [`Config::warn_on_redirect`]).
+    pub redirected_links: Vec<RedirectedLink>,
+    /// Web links which weren't checked because the site's `robots.txt`
+    /// disallows it (see [`Config::respect_robots_txt`]).
+    pub disallowed_by_robots: Vec<Link>,
+    /// Chapters which no other chapter links to (see
+    /// [`Config::detect_orphans`]).
+    pub orphan_pages: Vec<FileId>,
+    /// Reference definitions which no link uses (see
+    /// [`Config::detect_unused_references`]).
+    pub unused_references: Vec<UnusedReference>,
+}
+
+pub(crate) const ORPHAN_PAGE_MESSAGE: &str =
+    "No other chapter links to this page";
+
+/// A `http://` link which should be upgraded to `https://`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsecureLink {
+    /// The insecure link.
+    pub link: Link,
+    /// The equivalent `https://` URL, if we've checked that it's valid (see
+    /// [`Config::warn_on_http`]).
+    pub https_url: Option<String>,
+}
+
+impl InsecureLink {
+    /// How severe is this insecure link, or `None` if it should be ignored?
+    pub fn severity(&self, cfg: &Config) -> Option<Severity> {
+        let policy = cfg
+            .warning_policy_for(Category::InsecureLink)
+            .or_else(|| cfg.warning_policy_override(&self.link))
+            .unwrap_or(cfg.warning_policy);
+
+        policy_severity(policy)
+    }
+}
+
+/// A web link which redirects somewhere else.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedirectedLink {
+    /// The link which was redirected.
+    pub link: Link,
+    /// Where the link ended up, including the link's fragment.
+    pub url: String,
+    /// Was every redirect along the way permanent (`301` or `308`)?
+    pub permanent: bool,
+}
+
+impl RedirectedLink {
+    /// How severe is this redirect, or `None` if it should be ignored?
+    pub fn severity(&self, cfg: &Config) -> Option<Severity> {
+        let policy = cfg
+            .warning_policy_for(Category::RedirectedLink)
+            .or_else(|| cfg.warning_policy_override(&self.link))
+            .unwrap_or(cfg.warning_policy);
+
+        policy_severity(policy)
+    }
+}
+
+impl Display for RedirectedLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.permanent {
+            write!(f, "\"{}\" redirects to \"{}\"", self.link.uri, self.url)
+        } else {
+            write!(
+                f,
+                "\"{}\" temporarily redirects to \"{}\"",
+                self.link.uri, self.url
+            )
+        }
+    }
+}
+
+/// How severe is skipping a link because of `robots.txt`, or `None` if it
+/// should be ignored?
+pub(crate) fn disallowed_severity(
+    link: &Link,
+    cfg: &Config,
+) -> Option<Severity> {
+    let policy = cfg
+        .warning_policy_for(Category::DisallowedByRobots)
+        .or_else(|| cfg.warning_policy_override(link))
+        .unwrap_or(cfg.warning_policy);
+
+    policy_severity(policy)
+}
+
+pub(crate) fn disallowed_message(link: &Link) -> String {
+    format!("\"{}\" was skipped per robots.txt", link.uri)
+}
+
+impl ValidationOutcome {
+    /// Generate a list of [`Diagnostic`] messages from this
+    /// [`ValidationOutcome`].
+    pub fn generate_diagnostics(
+        &self,
+        files: &Files,
+        cfg: &Config,
+    ) -> Vec<Diagnostic> {
+        let mut diags = Vec::new();
+
+        self.add_invalid_link_diagnostics(cfg, &mut diags);
+        self.add_insecure_link_diagnostics(cfg, &mut diags);
+        self.add_redirected_link_diagnostics(cfg, &mut diags);
+        self.add_disallowed_link_diagnostics(cfg, &mut diags);
+
+        // incomplete links and duplicate anchors are problems with the book's
+        // own files
+        let policy = cfg.local_warning_policy_for(Category::IncompleteLink);
+        if let Some(severity) = policy_severity(policy) {
+            self.add_incomplete_link_diagnostics(severity, &mut diags, files);
+        }
+        let policy = cfg.local_warning_policy_for(Category::DuplicateAnchor);
+        if let Some(severity) = policy_severity(policy) {
+            self.add_duplicate_anchor_diagnostics(severity, &mut diags);
+        }
+        let policy = cfg.local_warning_policy_for(Category::OrphanPage);
+        if let Some(severity) = policy_severity(policy) {
+            self.add_orphan_page_diagnostics(severity, &mut diags);
+        }
+        let policy = cfg.local_warning_policy_for(Category::UnusedReference);
+        if let Some(severity) = policy_severity(policy) {
+            self.add_unused_reference_diagnostics(severity, &mut diags);
+        }
+
+        diags
+    }
+
+    fn add_incomplete_link_diagnostics(
+        &self,
+        severity: Severity,
+        diags: &mut Vec<Diagnostic>,
+        files: &Files,
+    ) {
+        for incomplete in &self.incomplete_links {
+            let IncompleteLink { ref text, file } = incomplete;
+            let span = resolve_incomplete_link_span(incomplete, files);
+            let msg =
+                format!("Did you forget to define a URL for `{0}`?", text);
+            let label = Label::new(*file, span, msg);
+            let note = format!(
+                "hint: declare the link's URL. For example: `[{}]: http://example.com/`",
+                text
+            );
+            let diag =
+                Diagnostic::new(severity, "Potential incomplete link", label)
+                    .with_code(Category::IncompleteLink.code())
+                    .with_notes(vec![note]);
+            diags.push(diag)
+        }
+    }
+
+    fn add_duplicate_anchor_diagnostics(
+        &self,
+        severity: Severity,
+        diags: &mut Vec<Diagnostic>,
+    ) {
+        for duplicate in &self.duplicate_anchors {
+            let DuplicateAnchor {
+                ref anchor,
+                file,
+                first,
+                second,
+            } = *duplicate;
+            let msg =
+                format!("This heading's anchor, `#{}`, is already used", anchor);
+            let diag = Diagnostic::new(
+                severity,
+                "Duplicate heading anchor",
+                Label::new(file, second, msg),
+            )
+            .with_code(Category::DuplicateAnchor.code())
+            .with_secondary_labels(vec![Label::new(
+                file,
+                first,
+                "Links to the anchor will go here",
+            )])
+            .with_notes(vec![String::from(
+                "hint: rename one of the headings or give it an explicit ID (e.g. `## Heading {#my-id}`)",
+            )]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_orphan_page_diagnostics(
+        &self,
+        severity: Severity,
+        diags: &mut Vec<Diagnostic>,
+    ) {
+        for &file in &self.orphan_pages {
+            let diag = Diagnostic::new(
+                severity,
+                "Orphaned page",
+                Label::new(file, Span::initial(), ORPHAN_PAGE_MESSAGE),
+            )
+            .with_code(Category::OrphanPage.code())
+            .with_notes(vec![String::from(
+                "hint: link to it from a related chapter, or remove it if it's no longer needed",
+            )]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_unused_reference_diagnostics(
+        &self,
+        severity: Severity,
+        diags: &mut Vec<Diagnostic>,
+    ) {
+        for unused in &self.unused_references {
+            let msg = format!("Nothing links to `[{}]`", unused.label);
+            let diag = Diagnostic::new(
+                severity,
+                "Unused link reference",
+                Label::new(unused.file, unused.span, msg),
+            )
+            .with_code(Category::UnusedReference.code())
+            .with_notes(vec![format!(
+                "hint: link to it with `[text][{}]`, or remove the definition",
+                unused.label
+            )]);
+            diags.push(diag);
+        }
+    }
+
+    fn add_insecure_link_diagnostics(
+        &self,
+        cfg: &Config,
+        diags: &mut Vec<Diagnostic>,
+    ) {
+        for insecure in &self.insecure_links {
+            let severity = match insecure.severity(cfg) {
+                Some(severity) => severity,
+                None => continue,
+            };
+            let link = &insecure.link;
+            let diag = match insecure.https_url {
+                Some(ref https_url) => Diagnostic::new(
+                    severity,
+                    format!("\"{}\" is also available over HTTPS", link.uri),
+                    Label::new(
+                        link.file,
+                        link.span,
+                        format!("Use \"{}\" instead", https_url),
+                    ),
+                ),
+                None => Diagnostic::new(
+                    severity,
+                    format!("\"{}\" uses plain HTTP", link.uri),
+                    Label::new(link.file, link.span, "Consider using HTTPS"),
+                ),
+            };
+            diags.push(diag.with_code(Category::InsecureLink.code()));
+        }
+    }
+
+    fn add_redirected_link_diagnostics(
+        &self,
+        cfg: &Config,
+        diags: &mut Vec<Diagnostic>,
+    ) {
+        for redirected in &self.redirected_links {
+            let severity = match redirected.severity(cfg) {
+                Some(severity) => severity,
+                None => continue,
+            };
+            let link = &redirected.link;
+            let msg =
+                format!("Consider linking to \"{}\" instead", redirected.url);
+            let diag = Diagnostic::new(
+                severity,
+                redirected.to_string(),
+                Label::new(link.file, link.span, msg),
+            );
+            diags.push(diag.with_code(Category::RedirectedLink.code()));
+        }
+    }
+
+    fn add_disallowed_link_diagnostics(
+        &self,
+        cfg: &Config,
+        diags: &mut Vec<Diagnostic>,
+    ) {
+        for link in &self.disallowed_by_robots {
+            let severity = match disallowed_severity(link, cfg) {
+                Some(severity) => severity,
+                None => continue,
+            };
+            let diag = Diagnostic::new(
+                severity,
+                disallowed_message(link),
+                Label::new(
+                    link.file,
+                    link.span,
+                    "The site's robots.txt disallows checking this link",
+                ),
+            );
+            diags.push(diag.with_code(Category::DisallowedByRobots.code()));
+        }
+    }
+
+    fn add_invalid_link_diagnostics(
+        &self,
+        cfg: &Config,
+        diags: &mut Vec<Diagnostic>,
+    ) {
+        // each broken link, and the other places linking to the same URL
+        let mut groups: Vec<(&InvalidLink, Severity, Vec<&Link>)> = Vec::new();
+        let mut group_index = HashMap::new();
+
+        for broken_link in &self.invalid_links {
+            let severity = match broken_link.severity(cfg) {
+                Some(severity) => severity,
+                None => continue,
+            };
+            let key = if cfg.group_by_url {
+                group_key(&broken_link.link).map(|url| {
+                    (url, broken_link.reason.to_string(), severity as u8)
+                })
+            } else {
+                None
+            };
+
+            let existing = key.and_then(|key| match group_index.entry(key) {
+                Entry::Occupied(entry) => Some(*entry.get()),
+                Entry::Vacant(entry) => {
+                    entry.insert(groups.len());
+                    None
+                },
+            });
+
+            match existing {
+                Some(ix) => groups[ix].2.push(&broken_link.link),
+                None => groups.push((broken_link, severity, Vec::new())),
+            }
+        }
+
+        for (broken_link, severity, also_linked_from) in groups {
+            let link = &broken_link.link;
+            let title = if also_linked_from.is_empty() {
+                broken_link.to_string()
+            } else {
+                format!(
+                    "{} (linked from {} places)",
+                    broken_link,
+                    also_linked_from.len() + 1
+                )
+            };
+
+            let mut diag = Diagnostic::new(
+                severity,
+                title,
+                Label::new(
+                    link.file,
+                    link.span,
+                    broken_link.reason.to_string(),
+                ),
+            )
+            .with_code(broken_link.reason.category().code());
+            if !also_linked_from.is_empty() {
+                diag = diag.with_secondary_labels(
+                    also_linked_from.into_iter().map(|other| {
+                        Label::new(other.file, other.span, "Also linked here")
+                    }),
+                );
+            }
+            match broken_link.reason {
+                // only local links point at headings we generated anchors for
+                Reason::AnchorNotFound(_)
+                    if link.uri.authority_part().is_none() =>
+                {
+                    let note = "hint: a heading's anchor is its text in lowercase, with spaces replaced by `-` and punctuation removed (e.g. `## Getting Started!` becomes `#getting-started`)";
+                    diag = diag.with_notes(vec![note.to_string()]);
+                },
+                _ => {},
+            }
+            diags.push(diag);
+        }
+    }
+}
+
+/// The URL a broken link is grouped under (see [`Config::group_by_url`]).
+/// Relative links depend on the file they're written in, so only absolute
+/// URLs are grouped.
+pub(crate) fn group_key(link: &Link) -> Option<String> {
+    link.uri.scheme_str()?;
+    let url = canonical_url(&link.uri);
+
+    match link.fragment {
+        Some(ref fragment) if !fragment.is_empty() => {
+            Some(format!("{}#{}", url, fragment))
+        },
+        _ => Some(url),
+    }
+}
+
+/// The [`Severity`] to report problems with, or `None` if they should be
+/// ignored.
+pub(crate) fn policy_severity(policy: WarningPolicy) -> Option<Severity> {
+    match policy {
+        WarningPolicy::Error => Some(Severity::Error),
+        WarningPolicy::Warn => Some(Severity::Warning),
+        WarningPolicy::Ignore => None,
+    }
+}
+
+/// HACK: this is a workaround for
+/// [pulldown-cmark#165](https://github.com/raphlinus/pulldown-cmark/issues/165)
+/// which uses good ol' string searching to find where an incomplete link may
+/// have been defined.
+pub(crate) fn resolve_incomplete_link_span(
+    incomplete: &IncompleteLink,
+    files: &Files,
+) -> Span {
+    let needle = format!("[{}]", incomplete.text);
+    let src = files.source(incomplete.file);
+
+    match src.find(&needle).map(|ix| ix as u32) {
+        Some(start_ix) => Span::new(start_ix, start_ix + needle.len() as u32),
+        None => files.source_span(incomplete.file),
+    }
+}
+
+/// An invalid [`Link`] and the [`Reason`] for why it isn't valid.
+#[derive(Debug)]
+pub struct InvalidLink {
+    /// The dodgy link.
+    pub link: Link,
+    /// Why the link isn't valid.
+    pub reason: Reason,
+}
+
+impl Display for InvalidLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            Reason::FileNotFound => write!(f, "File not found: {}", self.link.uri),
+            Reason::AnchorNotFound(ref fragment) => {
+                write!(f, "\"{}\" doesn't contain a \"#{}\" anchor", self.link.uri, fragment)
+            },
+            Reason::TraversesParentDirectories(ref path) => {
+                write!(f, "\"{}\" escapes the book's source directory (it resolves to \"{}\"), but this is forbidden", self.link.uri, path.display())
+            },
+            Reason::UnsuccessfulServerResponse(code) => {
+                write!(f, "The server responded with {} for \"{}\"", code, self.link.uri)
+            },
+            Reason::TimedOut(timeout) => {
+                write!(f, "Timed out after {}s while retrieving \"{}\"", timeout.as_secs(), self.link.uri)
+            },
+            Reason::TooManyRedirects { limit, ref last_url } => {
+                write!(f, "\"{}\" redirected more than {} times (last URL was \"{}\")", self.link.uri, limit, last_url)
+            },
+            Reason::Client(ref err) => write!(f, "Unable to retrieve \"{}\": {}", self.link.uri, err),
+            Reason::RetriesExhausted { attempts, ref last } => {
+                write!(f, "Unable to retrieve \"{}\" after {} attempts: {}", self.link.uri, attempts, last)
+            },
+            Reason::MalformedEmailAddress(_)
+            | Reason::MalformedPhoneNumber(_) => {
+                write!(f, "\"{}\" is malformed", self.link.uri)
+            },
+            Reason::UnresolvableEmailDomain(ref domain) => {
+                write!(f, "\"{}\" sends email to \"{}\", which doesn't resolve", self.link.uri, domain)
+            },
+            Reason::CachedFailure(ref failure) => {
+                write!(f, "\"{}\" was broken when it was last checked: {}", self.link.uri, failure)
+            },
+            Reason::UnexpectedBody { .. } => {
+                write!(f, "\"{}\" looks broken: {}", self.link.uri, self.reason)
+            },
+            Reason::MalformedUrl(ref problem) => {
+                write!(f, "\"{}\" isn't a valid URL: {}", self.link.uri, problem)
+            },
+            Reason::Rejected(ref explanation) => {
+                write!(f, "\"{}\" was rejected: {}", self.link.uri, explanation)
+            },
+        }
+    }
+}
+
+impl InvalidLink {
+    /// How severe is this broken link, or `None` if it should be ignored?
+    ///
+    /// An explicit [`Config::web_warning_policy`] or
+    /// [`Config::local_warning_policy`] applies to every broken link of that
+    /// type. Otherwise broken links are always errors, except missing anchors
+    /// (the page itself still exists) and problems with `mailto:` or `tel:`
+    /// links which follow the [`Config::warning_policy`].
+    ///
+    /// Setting [`Config::broken_link_policy`] takes links which are actually
+    /// broken (see [`Category::is_broken_link()`]) out of the above, and a
+    /// policy for the problem's [`Category`] in [`Config::warning_policies`]
+    /// trumps everything.
+    pub fn severity(&self, cfg: &Config) -> Option<Severity> {
+        let category = self.reason.category();
+
+        if let Some(policy) = cfg.warning_policy_for(category) {
+            return policy_severity(policy);
+        }
+        if let Some(policy) = cfg.broken_link_policy {
+            if category.is_broken_link() {
+                return policy_severity(policy);
+            }
+        }
+
+        match (cfg.warning_policy_override(&self.link), &self.reason) {
+            (Some(policy), _) => policy_severity(policy),
+            (None, reason) if reason.is_warning() => {
+                policy_severity(cfg.warning_policy)
+            },
+            (None, _) => Some(Severity::Error),
+        }
+    }
+}
+
+/// Why is this [`Link`] invalid?
+#[derive(Debug, Clone)]
+pub enum Reason {
+    /// The link points to a file that doesn't exist.
+    FileNotFound,
+    /// The linked document exists, but it has no heading (or other anchor)
+    /// matching the link's fragment (see [`Config::check_anchors`]).
+    AnchorNotFound(String),
+    /// The link points to a file outside of the book directory, and traversing
+    /// outside the book directory is forbidden (see
+    /// [`Config::traverse_parent_directories`]). This contains the path the
+    /// link resolved to.
+    TraversesParentDirectories(PathBuf),
+    /// The server replied with an unsuccessful status code (according to
+    /// [`StatusCode::is_success()`]).
+    UnsuccessfulServerResponse(StatusCode),
+    /// The server didn't respond within [`Config::request_timeout`] seconds.
+    TimedOut(Duration),
+    /// The redirect chain was longer than [`Config::max_redirects`] (or
+    /// contained a loop).
+    TooManyRedirects {
+        /// The maximum number of redirects we were allowed to follow.
+        limit: usize,
+        /// The last URL we were redirected to before giving up.
+        last_url: String,
+    },
+    /// An error was encountered while checking a web link.
+    Client(Arc<reqwest::Error>),
+    /// We kept getting transient errors, even after retrying (see
+    /// [`Config::max_retries`]).
+    RetriesExhausted {
+        /// The total number of requests made.
+        attempts: u32,
+        /// The error from the final attempt.
+        last: Box<Reason>,
+    },
+    /// A `mailto:` link doesn't contain a plausible email address (see
+    /// [`Config::check_mailto`]).
+    MalformedEmailAddress(String),
+    /// A `tel:` link isn't a valid RFC 3966 phone number (see
+    /// [`Config::check_tel`]).
+    MalformedPhoneNumber(String),
+    /// The domain of an address in a `mailto:` link doesn't resolve (see
+    /// [`Config::check_mailto_dns`]).
+    UnresolvableEmailDomain(String),
+    /// The link was broken the last time it was checked, and that result is
+    /// still in the cache (see [`Config::negative_cache_timeout`]).
+    CachedFailure(String),
+    /// The web page's body didn't pass a [`Config::body_must_contain`] or
+    /// [`Config::body_must_not_contain`] check.
+    UnexpectedBody {
+        /// The regex the body was checked against.
+        pattern: String,
+        /// Was the body meant to match the pattern?
+        must_contain: bool,
+    },
+    /// The link isn't a well-formed web URL (see
+    /// [`Config::check_url_syntax`]). This contains what's wrong with it.
+    MalformedUrl(String),
+    /// A custom [`LinkValidator`] rejected the link. This contains its
+    /// explanation.
+    Rejected(String),
+}
+
+impl Reason {
+    /// The [`Category`] this problem is reported under.
+    pub fn category(&self) -> Category {
+        match self {
+            Reason::FileNotFound => Category::MissingFile,
+            Reason::AnchorNotFound(_) => Category::BadAnchor,
+            Reason::TraversesParentDirectories(_) => {
+                Category::ForbiddenParentDirectory
+            },
+            Reason::MalformedEmailAddress(_)
+            | Reason::MalformedPhoneNumber(_) => Category::MalformedContactLink,
+            Reason::UnresolvableEmailDomain(_) => {
+                Category::UnresolvableEmailDomain
+            },
+            Reason::MalformedUrl(_) => Category::MalformedUrl,
+            Reason::Rejected(_) => Category::RejectedLink,
+            _ => Category::BrokenWebLink,
+        }
+    }
+
+    /// Is this a problem which follows the [`Config::warning_policy`] (e.g.
+    /// a missing anchor) instead of always being an error?
+    pub fn is_warning(&self) -> bool {
+        matches!(
+            self,
+            Reason::AnchorNotFound(_)
+                | Reason::MalformedEmailAddress(_)
+                | Reason::MalformedPhoneNumber(_)
+                | Reason::UnresolvableEmailDomain(_)
+        )
+    }
+
+    /// A convenience function for determining if the underlying request timed
+    /// out.
+    pub fn timed_out(&self) -> bool {
+        match self {
+            Reason::TimedOut(_) => true,
+            Reason::Client(ref inner) => inner.is_timeout(),
+            Reason::RetriesExhausted { ref last, .. } => last.timed_out(),
+            _ => false,
+        }
+    }
+
+    /// Is this the sort of error which might go away if we try again (e.g. a
+    /// dropped connection or a `503 Service Unavailable`)?
+    ///
+    /// Other than `429 Too Many Requests`, `4xx` responses are deterministic
+    /// so they aren't considered transient.
+    fn is_transient(&self) -> bool {
+        match self {
+            Reason::UnsuccessfulServerResponse(status) => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            },
+            Reason::TimedOut(_) | Reason::Client(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::FileNotFound => "File not found".fmt(f),
+            Reason::AnchorNotFound(ref fragment) => {
+                write!(f, "There is no \"#{}\" anchor", fragment)
+            },
+            Reason::TraversesParentDirectories(ref path) => write!(
+                f,
+                "Link escapes the book's source directory (resolves to \"{}\")",
+                path.display()
+            ),
+            Reason::UnsuccessfulServerResponse(code) => {
+                write!(f, "Server responded with {}", code)
+            },
+            Reason::TimedOut(timeout) => {
+                write!(f, "Timed out after {}s", timeout.as_secs())
+            },
+            Reason::TooManyRedirects { limit, ref last_url } => write!(
+                f,
+                "Too many redirects (limit is {}), last URL was \"{}\"",
+                limit, last_url
+            ),
+            Reason::Client(ref err) => err.fmt(f),
+            Reason::RetriesExhausted { attempts, ref last } => {
+                write!(f, "{} (after {} attempts)", last, attempts)
+            },
+            Reason::MalformedEmailAddress(ref address) => {
+                write!(f, "\"{}\" isn't a valid email address", address)
+            },
+            Reason::MalformedPhoneNumber(ref number) => {
+                write!(f, "\"{}\" isn't a valid phone number", number)
+            },
+            Reason::UnresolvableEmailDomain(ref domain) => {
+                write!(f, "The \"{}\" domain doesn't resolve", domain)
+            },
+            Reason::CachedFailure(ref failure) => {
+                write!(f, "{} (cached)", failure)
+            },
+            Reason::UnexpectedBody {
+                ref pattern,
+                must_contain: true,
+            } => write!(f, "The page doesn't contain `{}`", pattern),
+            Reason::UnexpectedBody {
+                ref pattern,
+                must_contain: false,
+            } => write!(f, "The page contains `{}`", pattern),
+            Reason::MalformedUrl(ref problem) => problem.fmt(f),
+            Reason::Rejected(ref explanation) => explanation.fmt(f),
+        }
+    }
+}
+
+/// An unknown [`Uri::scheme_str()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownScheme(pub Link);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{policy, MockServer, Response},
+        DnsResolution, HashedRegex, WebScheme,
+    };
+    use codespan::Files;
+    use std::{convert::TryInto, iter::FromIterator};
+
+    fn check_url(url: &str, cfg: &Config) -> Result<(), Reason> {
+        let mut files = Files::new();
+        let id = files.add("chapter.md", url);
+        let link = Link::parse(url, 0..url.len(), id).unwrap();
+        let clients = Clients::new(cfg).unwrap();
+        let throttle = Throttle::default();
+
+        check_link(&link, &clients, cfg, &Cache::default(), &throttle)
+            .map(|_| ())
+    }
+
+    #[test]
+    fn sort_links_into_buckets() {
+        let mut files = Files::new();
+        let id = files.add("asd", "");
+        let links = vec![Link::parse("path/to/file.md", 0..1, id).unwrap()];
+
+        let got = sort_into_buckets(&links, |unknown| {
+            panic!("Unknown schema: {:?}", unknown)
+        });
+
+        assert_eq!(got.file.len(), 1);
+        assert_eq!(got.file[0], links[0]);
+    }
+
+    #[test]
+    fn accepted_status_codes_are_successful() {
+        let cfg = Config {
+            accepted_status_codes: vec![403],
+            ..Default::default()
+        };
+
+        let url = "https://example.com/";
+
+        assert!(is_successful(url, StatusCode::OK, &cfg));
+        assert!(is_successful(url, StatusCode::FORBIDDEN, &cfg));
+        assert!(!is_successful(url, StatusCode::NOT_FOUND, &cfg));
+        assert!(!is_successful(
+            url,
+            StatusCode::FORBIDDEN,
+            &Config::default()
+        ));
+    }
+
+    #[test]
+    fn transient_errors_are_retried() {
+        let server = MockServer::start(|req| {
+            if req.path == "/flaky" {
+                Response::new(503)
+            } else {
+                Response::new(200)
+            }
+        });
+        let cfg = Config {
+            max_retries: 2,
+            retry_backoff_ms: 0,
+            ..Default::default()
+        };
+
+        match check_url(&server.url("/flaky"), &cfg) {
+            Err(Reason::RetriesExhausted { attempts: 3, ref last })
+                if last.to_string().contains("503") => {},
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn retries_are_logged_without_credentials() {
+        let server = MockServer::start(|_| Response::new(503));
+        let url = server.url("/flaky").replace("://", "://user:secret@");
+        let cfg = Config {
+            max_retries: 1,
+            retry_backoff_ms: 0,
+            ..Default::default()
+        };
+
+        let logs = crate::test_utils::capture_logs(|| {
+            check_url(&url, &cfg).unwrap_err();
+        });
+
+        let retries: Vec<_> = logs
+            .iter()
+            .filter(|(level, _)| *level == log::Level::Info)
+            .map(|(_, msg)| msg)
+            .collect();
+        assert_eq!(retries.len(), 1);
+        let host = server.url("").replace("http://", "");
+        assert!(
+            retries[0].starts_with(&format!("Retrying a request to {} ", host)),
+            "{}",
+            retries[0]
+        );
+        assert!(!retries[0].contains("secret"));
+    }
+
+    #[test]
+    fn a_flaky_link_passes_if_a_retry_succeeds() {
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Response::new(503),
+                _ => Response::new(200),
+            }
+        });
+        let cfg = Config {
+            max_retries: 1,
+            retry_backoff_ms: 0,
+            ..Default::default()
+        };
+
+        check_url(&server.url("/"), &cfg).unwrap();
+
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn nothing_is_retried_by_default() {
+        let server = MockServer::start(|_| Response::new(503));
+
+        match check_url(&server.url("/"), &Config::default()) {
+            Err(Reason::UnsuccessfulServerResponse(
+                StatusCode::SERVICE_UNAVAILABLE,
+            )) => {},
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let server = MockServer::start(|_| Response::new(404));
+        let cfg = Config {
+            max_retries: 3,
+            retry_backoff_ms: 0,
+            ..Default::default()
+        };
+
+        match check_url(&server.url("/missing"), &cfg) {
+            Err(Reason::UnsuccessfulServerResponse(StatusCode::NOT_FOUND)) => {
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn retry_delays_back_off_exponentially() {
+        let cfg = Config {
+            retry_backoff_ms: 300,
+            ..Default::default()
+        };
+
+        assert_eq!(retry_delay(&cfg, 1), Duration::from_millis(300));
+        assert_eq!(retry_delay(&cfg, 2), Duration::from_millis(600));
+        assert_eq!(retry_delay(&cfg, 3), Duration::from_millis(1200));
+    }
+
+    #[test]
+    fn retry_delays_are_capped() {
+        let cfg = Config {
+            retry_backoff_ms: 300,
+            max_retry_after: 1,
+            ..Default::default()
+        };
+        assert_eq!(retry_delay(&cfg, 3), Duration::from_secs(1));
+
+        // multiplying this by the backoff factor would overflow
+        let cfg = Config {
+            retry_backoff_ms: u64::MAX,
+            ..Default::default()
+        };
+        assert_eq!(
+            retry_delay(&cfg, u32::MAX),
+            Duration::from_secs(cfg.max_retry_after)
+        );
+    }
+
+    #[test]
+    fn parse_retry_after_in_seconds() {
+        let got = parse_retry_after("120", SystemTime::now());
+
+        assert_eq!(got, Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn parse_retry_after_as_a_http_date() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_717);
+
+        let got = parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now);
+
+        assert_eq!(got, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn retry_after_dates_in_the_past_mean_retry_immediately() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_717);
+
+        let got = parse_retry_after("Sun, 06 Nov 1994 08:47:37 GMT", now);
+
+        assert_eq!(got, Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn invalid_retry_after_headers_are_ignored() {
+        assert_eq!(parse_retry_after("tomorrow", SystemTime::now()), None);
+    }
+
+    #[test]
+    fn retry_after_is_honoured_for_429_responses() {
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Response::new(429).with_header("Retry-After", "0"),
+                _ => Response::new(200),
+            }
+        });
+        let cfg = Config {
+            max_retries: 1,
+            // make sure we'd notice if the backoff was used instead
+            retry_backoff_ms: 60_000,
+            ..Default::default()
+        };
+
+        check_url(&server.url("/"), &cfg).unwrap();
+
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn wait_for_the_retry_after_duration() {
+        let attempts = std::sync::atomic::AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            match attempts.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Response::new(429).with_header("Retry-After", "1"),
+                _ => Response::new(200),
+            }
+        });
+        let cfg = Config {
+            max_retries: 1,
+            retry_backoff_ms: 0,
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+
+        check_url(&server.url("/"), &cfg).unwrap();
+
+        assert_eq!(server.requests().len(), 2);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn retry_after_is_capped_by_the_config() {
+        let server = MockServer::start(|_| {
+            Response::new(503).with_header("Retry-After", "3600")
+        });
+        let cfg = Config {
+            max_retries: 1,
+            max_retry_after: 0,
+            ..Default::default()
+        };
+        let started = std::time::Instant::now();
+
+        let got = check_url(&server.url("/"), &cfg);
+
+        assert!(matches!(got, Err(Reason::RetriesExhausted { .. })));
+        assert_eq!(server.requests().len(), 2);
+        assert!(started.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn limit_the_number_of_concurrent_requests_to_a_host() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let active = Arc::new(AtomicUsize::new(0));
+        let most_active = Arc::new(AtomicUsize::new(0));
+        let (a, m) = (Arc::clone(&active), Arc::clone(&most_active));
+        let server = MockServer::start(move |_| {
+            let now_active = a.fetch_add(1, Ordering::SeqCst) + 1;
+            m.fetch_max(now_active, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            a.fetch_sub(1, Ordering::SeqCst);
+            Response::new(200)
+        });
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let links: Vec<_> = (0..8)
+            .map(|i| {
+                Link::parse(&server.url(&format!("/{}", i)), 0..1, id).unwrap()
+            })
+            .collect();
+        let cfg = Config {
+            max_concurrency: 0,
+            max_concurrency_per_host: 2,
+            ..Default::default()
+        };
+        let mut outcome = ValidationOutcome::default();
+        // a dedicated pool, so there are always more threads than the limit
+        // regardless of how many CPUs the machine has
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(8)
+            .build()
+            .unwrap();
+
+        pool.install(|| {
+            validate_web_links(&links, &cfg, &mut outcome, &Cache::default())
+        })
+        .unwrap();
+
+        assert_eq!(outcome.valid_links.len(), 8);
+        assert_eq!(server.requests().len(), 8);
+        assert_eq!(most_active.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn a_401_is_only_accepted_from_matching_hosts() {
+        let server = MockServer::start(|_| Response::new(401));
+        let cfg = Config {
+            accepted_status_codes_by_url: HashMap::from_iter(vec![(
+                HashedRegex::new("127\\.0\\.0\\.1").unwrap(),
+                HashSet::from_iter(vec![401]),
+            )]),
+            ..Default::default()
+        };
+
+        check_url(&server.url("/login"), &cfg).unwrap();
+
+        let other_host = server.url("/login").replace("127.0.0.1", "localhost");
+        match check_url(&other_host, &cfg) {
+            Err(Reason::UnsuccessfulServerResponse(
+                StatusCode::UNAUTHORIZED,
+            )) => {},
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_403_passes_when_it_is_an_accepted_status_code() {
+        let server = MockServer::start(|_| Response::new(403));
+        let url = server.url("/secret");
+        let cfg: Config =
+            toml::from_str("accepted-status-codes = [403, 429]").unwrap();
+
+        check_url(&url, &cfg).unwrap();
+        assert_eq!(server.requests()[0].path, "/secret");
+
+        match check_url(&url, &Config::default()) {
+            Err(Reason::UnsuccessfulServerResponse(StatusCode::FORBIDDEN)) => {
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    fn check_local_links(src: &str, cfg: &Config) -> ValidationOutcome {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let root = dunce::canonicalize(root).unwrap();
+        let mut files = Files::new();
+        let id = files.add("nested/index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let mut outcome = ValidationOutcome::default();
+
+        validate_local_links(&links, cfg, &root, &mut outcome, &files);
+
+        outcome
+    }
+
+    #[test]
+    fn a_link_to_an_existing_heading_is_valid() {
+        let src = "[link](../chapter_1.md#subheading)";
+
+        let got = check_local_links(src, &Config::default());
+
+        assert_eq!(got.valid_links.len(), 1);
+        assert!(got.invalid_links.is_empty());
+    }
+
+    #[test]
+    fn a_link_to_a_missing_heading_is_invalid() {
+        let src = "[link](../chapter_1.md#nonexistent)";
+
+        let got = check_local_links(src, &Config::default());
+
+        assert_eq!(got.invalid_links.len(), 1);
+        match got.invalid_links[0].reason {
+            Reason::AnchorNotFound(ref fragment) => {
+                assert_eq!(fragment, "nonexistent")
+            },
+            ref other => panic!("Unexpected reason: {:?}", other),
+        }
+
+        let cfg = Config {
+            check_anchors: false,
+            ..Default::default()
+        };
+        let got = check_local_links(src, &cfg);
+        assert!(got.invalid_links.is_empty());
+    }
+
+    #[test]
+    fn only_check_local_links_with_the_listed_extensions() {
+        let src = "[chapter](../chapter_1.md)\n[missing](./missing.md)\n[data](../assets/data.csv)\n[folder](./missing-folder)";
+        let cfg = Config {
+            local_link_extensions: vec![String::from("md")],
+            ..Default::default()
+        };
+
+        let got = check_local_links(src, &cfg);
+
+        let uris = |links: Vec<&Link>| -> Vec<String> {
+            links.into_iter().map(|link| link.uri.to_string()).collect()
+        };
+        assert_eq!(uris(got.valid_links.iter().collect()), ["../chapter_1.md"]);
+        assert_eq!(
+            uris(got.invalid_links.iter().map(|i| &i.link).collect()),
+            ["./missing.md", "./missing-folder"]
+        );
+        assert_eq!(uris(got.ignored.iter().collect()), ["../assets/data.csv"]);
+
+        // by default, everything is checked
+        let got = check_local_links(src, &Config::default());
+        assert_eq!(got.invalid_links.len(), 3);
+        assert!(got.ignored.is_empty());
+    }
+
+    #[test]
+    fn fragments_can_ignore_case() {
+        let src = "[link](../chapter_1.md#SubHeading)";
+
+        let got = check_local_links(src, &Config::default());
+        assert_eq!(got.invalid_links.len(), 1);
+
+        let cfg = Config {
+            case_insensitive_fragments: true,
+            ..Default::default()
+        };
+        let got = check_local_links(src, &cfg);
+        assert_eq!(got.valid_links.len(), 1);
+        assert!(got.invalid_links.is_empty());
+
+        // the file name still has to match exactly
+        let src = "[link](../Chapter_1.md#SubHeading)";
+        let got = check_local_links(src, &cfg);
+        assert_eq!(got.invalid_links.len(), 1);
+        if let Reason::AnchorNotFound(_) = got.invalid_links[0].reason {
+            panic!("The anchor shouldn't have been checked");
+        }
+    }
+
+    #[test]
+    fn links_escaping_the_book_are_reported_with_the_resolved_path() {
+        let crate_root =
+            dunce::canonicalize(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let src = "[exists](../../../../Cargo.toml)\n\n[missing](../../../../../../../etc/nonexistent)";
+
+        let got = check_local_links(src, &Config::default());
+
+        assert_eq!(got.invalid_links.len(), 2);
+        match got.invalid_links[0].reason {
+            Reason::TraversesParentDirectories(ref path) => {
+                assert_eq!(*path, crate_root.join("Cargo.toml"))
+            },
+            ref other => panic!("Unexpected reason: {:?}", other),
+        }
+        match got.invalid_links[1].reason {
+            Reason::TraversesParentDirectories(ref path) => {
+                assert!(path.ends_with("etc/nonexistent"), "{:?}", path);
+                assert!(!path.to_string_lossy().contains(".."));
+            },
+            ref other => panic!("Unexpected reason: {:?}", other),
+        }
+
+        let cfg = Config {
+            traverse_parent_directories: true,
+            ..Default::default()
+        };
+        let got = check_local_links(src, &cfg);
+        assert_eq!(got.valid_links.len(), 1);
+        match got.invalid_links[0].reason {
+            Reason::FileNotFound => {},
+            ref other => panic!("Unexpected reason: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_headings_get_numbered_anchors() {
+        let src = "# Overview\n\n[first](#overview)\n\n# Overview\n\n[second](#overview-1)\n\n[third](#overview-2)";
+
+        let got = check_local_links(src, &Config::default());
+
+        assert_eq!(got.valid_links.len(), 2);
+        assert_eq!(got.invalid_links.len(), 1);
+        assert_eq!(
+            got.invalid_links[0].link.fragment,
+            Some(String::from("overview-2"))
+        );
+    }
+
+    #[test]
+    fn missing_anchors_are_reported_according_to_the_warning_policy() {
+        let src = "[link](../chapter_1.md#nonexistent)";
+        let got = check_local_links(src, &Config::default());
+        let files = Files::new();
+
+        let cfg = policy(WarningPolicy::Warn);
+
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(diags[0].notes.len(), 1);
+
+        let cfg = policy(WarningPolicy::Error);
+
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags[0].severity, Severity::Error);
+
+        let cfg = policy(WarningPolicy::Ignore);
+
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert!(diags.is_empty());
+    }
+
+    #[test]
+    fn validate_a_single_link() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let cfg = Config::default();
+
+        let got = validate_link("./chapter_1.md", &cfg, &root).unwrap();
+        assert!(matches!(got, LinkResult::Valid));
+
+        let got = validate_link("./missing.md", &cfg, &root).unwrap();
+        assert!(matches!(got, LinkResult::Invalid(Reason::FileNotFound)));
+
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/" => Response::new(200),
+            _ => Response::new(404),
+        });
+
+        // web links are only checked when the config says so
+        let got = validate_link(&server.url("/"), &cfg, &root).unwrap();
+        assert!(matches!(got, LinkResult::Ignored));
+        assert!(server.requests().is_empty());
+
+        let cfg = Config {
+            follow_web_links: true,
+            ..Default::default()
+        };
+        let got = validate_link(&server.url("/"), &cfg, &root).unwrap();
+        assert!(matches!(got, LinkResult::Valid));
+        let got = validate_link(&server.url("/missing"), &cfg, &root).unwrap();
+        match got {
+            LinkResult::Invalid(Reason::UnsuccessfulServerResponse(code)) => {
+                assert_eq!(code, StatusCode::NOT_FOUND)
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn scheme_relative_links_are_web_links() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let server = MockServer::start(|_| Response::new(200));
+        let link = server.url("/").replacen("http:", "", 1);
+
+        // skipped, rather than reported as a missing file
+        let got = validate_link(&link, &Config::default(), &root).unwrap();
+        assert!(matches!(got, LinkResult::Ignored));
+
+        let cfg = Config {
+            follow_web_links: true,
+            scheme_relative_default: WebScheme::Http,
+            ..Default::default()
+        };
+        let got = validate_link(&link, &cfg, &root).unwrap();
+        assert!(matches!(got, LinkResult::Valid));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn per_category_policies_take_precedence() {
+        let mut files = Files::new();
+        let id = files.add("index.md", "");
+        let broken = |target: &str, reason: Reason| InvalidLink {
+            link: Link::parse(target, 0..1, id).unwrap(),
+            reason,
+        };
+        let web = broken(
+            "https://example.com/",
+            Reason::UnsuccessfulServerResponse(StatusCode::NOT_FOUND),
+        );
+        let anchor = broken(
+            "./chapter_1.md#nope",
+            Reason::AnchorNotFound(String::from("nope")),
+        );
+        let mut cfg = Config {
+            web_warning_policy: Some(WarningPolicy::Error),
+            ..Default::default()
+        };
+        cfg.warning_policies
+            .insert(Category::BrokenWebLink, WarningPolicy::Warn);
+        cfg.warning_policies
+            .insert(Category::BadAnchor, WarningPolicy::Error);
+
+        assert_eq!(web.severity(&cfg), Some(Severity::Warning));
+        assert_eq!(anchor.severity(&cfg), Some(Severity::Error));
+
+        // unspecified categories fall back to the normal rules
+        let missing = broken("./missing.md", Reason::FileNotFound);
+        assert_eq!(missing.severity(&cfg), Some(Severity::Error));
+    }
+
+    #[test]
+    fn offline_mode_never_touches_the_network() {
+        let server = MockServer::start(|_| Response::new(200));
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let root = dunce::canonicalize(root).unwrap();
+        let mut files = Files::new();
+        let src = format!(
+            "[web]({})\n\n[local](../chapter_1.md#nonexistent)",
+            server.url("/")
+        );
+        let id = files.add("nested/index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let cfg = Config {
+            follow_web_links: true,
+            offline: true,
+            include: vec![HashedRegex::new(".*").unwrap()],
+            ..Default::default()
+        };
+
+        let got =
+            validate(&links, &cfg, &root, &Cache::default(), &files, Vec::new())
+                .unwrap();
+
+        assert!(server.requests().is_empty());
+        assert_eq!(got.skipped_offline.len(), 1);
+        assert_eq!(got.invalid_links.len(), 1);
+        match got.invalid_links[0].reason {
+            Reason::AnchorNotFound(_) => {},
+            ref other => panic!("Unexpected reason: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn warn_about_links_which_redirect() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/old" => Response::new(301).with_header("Location", "/new"),
+            "/moved" => Response::new(302).with_header("Location", "/new"),
+            _ => Response::new(200),
+        });
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let src = format!(
+            "[old]({})\n\n[moved]({})\n\n[new]({})",
+            server.url("/old#intro"),
+            server.url("/moved"),
+            server.url("/new"),
+        );
+        let id = files.add("index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let cfg = Config {
+            follow_web_links: true,
+            warn_on_redirect: true,
+            include: vec![HashedRegex::new(".*").unwrap()],
+            ..Default::default()
+        };
+
+        let got =
+            validate(&links, &cfg, &root, &Cache::default(), &files, Vec::new())
+                .unwrap();
+
+        // only the permanent redirect is reported by default
+        assert!(got.invalid_links.is_empty());
+        assert_eq!(got.valid_links.len(), 3);
+        assert_eq!(got.redirected_links.len(), 1);
+        let redirected = &got.redirected_links[0];
+        assert_eq!(redirected.link, links[0]);
+        assert_eq!(redirected.url, server.url("/new#intro"));
+        assert!(redirected.permanent);
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(diags[0].code.as_deref(), Some("LC0013"));
+        assert_eq!(
+            diags[0].primary_label.message,
+            format!("Consider linking to \"{}\" instead", redirected.url)
+        );
+
+        let cfg = Config {
+            warn_on_temporary_redirect: true,
+            ..cfg
+        };
+        let got =
+            validate(&links, &cfg, &root, &Cache::default(), &files, Vec::new())
+                .unwrap();
+
+        assert_eq!(got.redirected_links.len(), 2);
+        let temporary = got
+            .redirected_links
+            .iter()
+            .find(|r| r.link == links[1])
+            .unwrap();
+        assert_eq!(temporary.url, server.url("/new"));
+        assert!(!temporary.permanent);
+        assert!(temporary.to_string().contains("temporarily redirects"));
+    }
+
+    #[test]
+    fn custom_validators_are_consulted_first() {
+        struct Jira;
+
+        impl LinkValidator for Jira {
+            fn validate(&self, link: &str) -> Option<LinkResult> {
+                let ticket =
+                    link.strip_prefix("jira://")?.trim_end_matches('/');
+                let result = if ticket.starts_with("PROJ-") {
+                    LinkResult::Valid
+                } else {
+                    let msg = format!("\"{}\" isn't a ticket", ticket);
+                    LinkResult::Invalid(Reason::Rejected(msg))
+                };
+                Some(result)
+            }
+        }
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let src = "[a](jira://PROJ-123) [b](jira://NOPE-1) [c](doc://intro)
+[d](./chapter_1.md) [e](./missing.md) [f](ftp://example.com)";
+        let id = files.add("index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let cfg = Config::default();
+        // validators are tried in order, and closures work too
+        let everything_is_a_doc = |link: &str| {
+            if link.starts_with("doc://") || link.starts_with("jira://") {
+                Some(LinkResult::Ignored)
+            } else {
+                None
+            }
+        };
+        let validators: [&dyn LinkValidator; 2] = [&Jira, &everything_is_a_doc];
+
+        let got = validate_with(
+            &links,
+            &cfg,
+            &root,
+            &Cache::default(),
+            &files,
+            Vec::new(),
+            &validators,
+        )
+        .unwrap();
+
+        let uris = |links: &[Link]| -> Vec<String> {
+            links.iter().map(|link| link.uri.to_string()).collect()
+        };
+        assert_eq!(
+            uris(&got.valid_links),
+            ["jira://PROJ-123/", "./chapter_1.md"]
+        );
+        assert_eq!(uris(&got.ignored), ["doc://intro/"]);
+        assert_eq!(uris(&got.unknown_schema), ["ftp://example.com/"]);
+        let broken: Vec<_> = got
+            .invalid_links
+            .iter()
+            .map(|invalid| invalid.to_string())
+            .collect();
+        assert_eq!(
+            broken,
+            [
+                r#""jira://NOPE-1/" was rejected: "NOPE-1" isn't a ticket"#,
+                "File not found: ./missing.md",
+            ]
+        );
+        let diags = got.generate_diagnostics(&files, &cfg);
+        let codes: Vec<_> =
+            diags.iter().map(|diag| diag.code.as_deref()).collect();
+        assert!(codes.contains(&Some("LC0015")));
+
+        // without any validators, the custom schemes are unknown
+        let got =
+            validate(&links, &cfg, &root, &Cache::default(), &files, Vec::new())
+                .unwrap();
+        assert_eq!(
+            uris(&got.unknown_schema),
+            [
+                "jira://PROJ-123/",
+                "jira://NOPE-1/",
+                "doc://intro/",
+                "ftp://example.com/"
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_links_to_loopback_addresses_without_a_request() {
+        let server = MockServer::start(|_| Response::new(200));
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let src = format!(
+            "[ip]({})\n\n[name]({})\n\n[ipv6](http://[::1]:1/)",
+            server.url("/page"),
+            server.url("/page").replace("127.0.0.1", "localhost"),
+        );
+        let id = files.add("index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let cfg = Config {
+            follow_web_links: true,
+            skip_loopback: true,
+            ..Default::default()
+        };
+
+        let got =
+            validate(&links, &cfg, &root, &Cache::default(), &files, Vec::new())
+                .unwrap();
+
+        assert_eq!(got.ignored, links);
+        assert!(got.valid_links.is_empty());
+        assert!(got.invalid_links.is_empty());
+        assert!(server.requests().is_empty());
+
+        // without the option, they're checked like any other link
+        let cfg = Config {
+            skip_loopback: false,
+            ..cfg
+        };
+        let got = validate(
+            &links[..1],
+            &cfg,
+            &root,
+            &Cache::default(),
+            &files,
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(got.valid_links.len(), 1);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn skip_links_disallowed_by_robots_txt() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/robots.txt" => Response::new(200)
+                .with_body("User-agent: *\nDisallow: /private/\n"),
+            _ => Response::new(200),
+        });
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let mut files = Files::new();
+        let src = format!(
+            "[private]({})\n\n[public]({})\n\n[again]({})",
+            server.url("/private/page"),
+            server.url("/public"),
+            server.url("/public"),
+        );
+        let id = files.add("index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let cfg = Config {
+            follow_web_links: true,
+            respect_robots_txt: true,
+            include: vec![HashedRegex::new(".*").unwrap()],
+            ..Default::default()
+        };
+
+        let got =
+            validate(&links, &cfg, &root, &Cache::default(), &files, Vec::new())
+                .unwrap();
+
+        assert_eq!(got.disallowed_by_robots, vec![links[0].clone()]);
+        assert_eq!(got.valid_links.len(), 2);
+        let paths: Vec<_> =
+            server.requests().into_iter().map(|req| req.path).collect();
+        assert_eq!(paths, vec!["/robots.txt", "/public"]);
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(diags[0].code.as_deref(), Some("LC0014"));
+        assert!(diags[0].message.contains("skipped per robots.txt"));
+
+        // the warning can be silenced like any other
+        let mut cfg = cfg;
+        cfg.warning_policies
+            .insert(Category::DisallowedByRobots, WarningPolicy::Ignore);
+        assert!(got.generate_diagnostics(&files, &cfg).is_empty());
+    }
+
+    #[test]
+    fn each_unique_url_is_only_requested_once() {
+        let server = MockServer::start(|_| Response::new(200));
+        let url = server.url("/page");
+        let shouty = url.replacen("http://", "HTTP://", 1);
+        let mut files = Files::new();
+        let ids = vec![
+            files.add("first.md", format!("[link]({})", url)),
+            files.add("second.md", format!("[link]({}#section)", url)),
+            files.add("third.md", format!("[link]({})", shouty)),
+        ];
+        let (links, _) = crate::links::extract(ids, &files);
+        let cfg = Config {
+            follow_web_links: true,
+            ..Default::default()
+        };
+
+        let got = validate(
+            &links,
+            &cfg,
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+            &Cache::default(),
+            &files,
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(got.valid_links.len(), 3);
+    }
+
+    #[test]
+    fn remote_fragments_are_still_checked_individually() {
+        let server = MockServer::start(|_| {
+            Response::new(200).with_body("<h1 id=\"first\">First</h1>")
+        });
+        let url = server.url("/page");
+        let mut files = Files::new();
+        let ids = vec![
+            files.add("first.md", format!("[link]({}#first)", url)),
+            files.add("second.md", format!("[link]({}#second)", url)),
+        ];
+        let (links, _) = crate::links::extract(ids, &files);
+        let cfg = Config {
+            follow_web_links: true,
+            check_remote_fragments: true,
+            max_retries: 0,
+            ..Default::default()
+        };
+
+        let got = validate(
+            &links,
+            &cfg,
+            Path::new(env!("CARGO_MANIFEST_DIR")),
+            &Cache::default(),
+            &files,
+            Vec::new(),
+        )
+        .unwrap();
+
+        assert_eq!(got.valid_links.len(), 1);
+        assert_eq!(got.invalid_links.len(), 1);
+        let fragment = got.invalid_links[0].link.fragment.as_deref();
+        assert_eq!(fragment, Some("second"));
+    }
+
+    #[test]
+    fn canonicalise_equivalent_urls() {
+        let inputs = vec![
+            ("http://example.com/", "http://example.com/"),
+            ("HTTPS://Example.COM/Page", "https://example.com/Page"),
+            ("http://example.com:80/a?b=c", "http://example.com/a?b=c"),
+            ("https://example.com:443/", "https://example.com/"),
+            ("https://example.com:8443/", "https://example.com:8443/"),
+            ("http://example.com:443/", "http://example.com:443/"),
+        ];
+
+        for (src, should_be) in inputs {
+            let uri: Uri = src.parse().unwrap();
+            assert_eq!(canonical_url(&uri), should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn malformed_contact_links_are_reported_according_to_the_warning_policy() {
+        let mut files = Files::new();
+        let src = "[me](mailto:alice) and [call](tel:+1-201-555-0123)";
+        let id = files.add("index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+
+        // they're ignored unless explicitly enabled
+        let mut got = ValidationOutcome::default();
+        let buckets = sort_into_buckets(&links, |_| unreachable!());
+        let cfg = Config::default();
+        validate_contact_links(buckets.contact, &cfg, &mut got, |_| false);
+        assert_eq!(got.ignored.len(), 2);
+
+        let cfg = Config {
+            check_mailto: true,
+            check_tel: true,
+            ..policy(WarningPolicy::Warn)
+        };
+        let mut got = ValidationOutcome::default();
+        let buckets = sort_into_buckets(&links, |_| unreachable!());
+        validate_contact_links(buckets.contact, &cfg, &mut got, |_| false);
+        assert_eq!(got.valid_links.len(), 1);
+        assert_eq!(got.invalid_links.len(), 1);
+
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+
+        let cfg = Config {
+            warning_policy: WarningPolicy::Ignore,
+            ..cfg
+        };
+        assert!(got.generate_diagnostics(&files, &cfg).is_empty());
+    }
+
+    #[test]
+    fn unresolvable_email_domains_are_a_separate_category() {
+        let mut files = Files::new();
+        let src = "[a](mailto:alice@127.0.0.1) [b](mailto:bob@nowhere.invalid)";
+        let id = files.add("index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let cfg = Config {
+            check_mailto: true,
+            check_mailto_dns: true,
+            ..Default::default()
+        };
+        let resolves = |domain: &str| domain != "nowhere.invalid";
+
+        let mut got = ValidationOutcome::default();
+        let buckets = sort_into_buckets(&links, |_| unreachable!());
+        validate_contact_links(buckets.contact, &cfg, &mut got, resolves);
+
+        assert_eq!(got.valid_links.len(), 1);
+        assert_eq!(got.invalid_links.len(), 1);
+        let reason = &got.invalid_links[0].reason;
+        assert_eq!(reason.category(), Category::UnresolvableEmailDomain);
+        assert!(reason.is_warning());
+
+        // no lookups happen unless they're enabled, or when we're offline
+        let disabled = Config {
+            check_mailto_dns: false,
+            ..cfg.clone()
+        };
+        let offline = Config {
+            offline: true,
+            ..cfg
+        };
+        for cfg in &[disabled, offline] {
+            let mut got = ValidationOutcome::default();
+            let buckets = sort_into_buckets(&links, |_| unreachable!());
+            validate_contact_links(buckets.contact, cfg, &mut got, |_| {
+                unreachable!()
+            });
+            assert_eq!(got.valid_links.len(), 2);
+        }
+    }
+
+    #[test]
+    fn each_email_domain_is_only_looked_up_once() {
+        let mut files = Files::new();
+        let src = concat!(
+            "[a](mailto:a@example.com) ",
+            "[b](mailto:b@example.com,c@EXAMPLE.com)"
+        );
+        let id = files.add("index.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let cfg = Config {
+            check_mailto: true,
+            check_mailto_dns: true,
+            ..Default::default()
+        };
+        let lookups = std::sync::atomic::AtomicUsize::new(0);
+
+        let mut got = ValidationOutcome::default();
+        let buckets = sort_into_buckets(&links, |_| unreachable!());
+        validate_contact_links(buckets.contact, &cfg, &mut got, |_| {
+            lookups.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            true
+        });
+
+        assert_eq!(got.valid_links.len(), 2);
+        assert_eq!(lookups.into_inner(), 1);
+    }
+
+    #[test]
+    fn check_fragments_in_remote_pages() {
+        let server = MockServer::start(|_| {
+            Response::new(200)
+                .with_body(r#"<html><h2 id="install">Install</h2></html>"#)
+        });
+        let cfg = Config {
+            check_remote_fragments: true,
+            ..Default::default()
+        };
+
+        check_url(&server.url("/page#install"), &cfg).unwrap();
+
+        match check_url(&server.url("/page#missing"), &cfg) {
+            Err(Reason::AnchorNotFound(ref fragment)) => {
+                assert_eq!(fragment, "missing")
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // fragments are ignored unless the user opts in
+        check_url(&server.url("/page#missing"), &Config::default()).unwrap();
+    }
+
+    #[test]
+    fn only_read_remote_pages_up_to_the_body_size_limit() {
+        let server = MockServer::start(|_| {
+            Response::new(200).with_body(&format!(
+                r#"<html>{}<h2 id="install">Install</h2></html>"#,
+                "x".repeat(1024)
+            ))
+        });
+        let cfg = Config {
+            check_remote_fragments: true,
+            max_body_size: 128,
+            ..Default::default()
+        };
+
+        // we can't tell whether the anchor exists, so give it the benefit of
+        // the doubt
+        check_url(&server.url("/page#missing"), &cfg).unwrap();
+    }
+
+    #[test]
+    fn pages_must_contain_the_expected_text() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/docs" => Response::new(200).with_body("<main>Welcome</main>"),
+            _ => Response::new(200).with_body("<p>Nothing here</p>"),
+        });
+        let cfg = Config {
+            body_must_contain: HashMap::from_iter(vec![(
+                HashedRegex::new("127.0.0.1").unwrap(),
+                HashedRegex::new("<main>").unwrap(),
+            )]),
+            ..Default::default()
+        };
+
+        check_url(&server.url("/docs"), &cfg).unwrap();
+        match check_url(&server.url("/soft-404"), &cfg) {
+            Err(Reason::UnexpectedBody {
+                ref pattern,
+                must_contain: true,
+            }) => assert_eq!(pattern, "<main>"),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // we need the body, so HEAD is never used
+        let methods: Vec<_> =
+            server.requests().into_iter().map(|req| req.method).collect();
+        assert_eq!(methods, ["GET", "GET"]);
+    }
+
+    #[test]
+    fn pages_must_not_contain_an_error_message() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/missing" => {
+                Response::new(200).with_body("<h1>Page Not Found</h1>")
+            },
+            _ => Response::new(200).with_body("<h1>Hello, World!</h1>"),
+        });
+        let cfg = Config {
+            body_must_not_contain: HashMap::from_iter(vec![(
+                HashedRegex::new("127.0.0.1").unwrap(),
+                HashedRegex::new("(?i)page not found").unwrap(),
+            )]),
+            ..Default::default()
+        };
+
+        check_url(&server.url("/hello"), &cfg).unwrap();
+        let reason = check_url(&server.url("/missing"), &cfg).unwrap_err();
+        assert_eq!(
+            reason.to_string(),
+            "The page contains `(?i)page not found`"
+        );
+        assert_eq!(reason.category(), Category::BrokenWebLink);
+
+        // other sites aren't affected
+        let cfg = Config {
+            body_must_not_contain: HashMap::from_iter(vec![(
+                HashedRegex::new("example\\.com").unwrap(),
+                HashedRegex::new("(?i)page not found").unwrap(),
+            )]),
+            ..Default::default()
+        };
+        check_url(&server.url("/missing"), &cfg).unwrap();
+    }
+
+    #[test]
+    fn orphan_pages_are_warnings_by_default() {
+        let mut files = Files::new();
+        let id = files.add("orphan.md", "# Nobody links here");
+        let outcome = ValidationOutcome {
+            orphan_pages: vec![id],
+            ..Default::default()
+        };
+
+        let diags = outcome.generate_diagnostics(&files, &Config::default());
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(diags[0].primary_label.file_id, id);
+
+        let cfg = Config {
+            warning_policies: HashMap::from_iter(vec![(
+                Category::OrphanPage,
+                WarningPolicy::Error,
+            )]),
+            ..Default::default()
+        };
+        let diags = outcome.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags[0].severity, Severity::Error);
+
+        let cfg = Config {
+            warning_policies: HashMap::from_iter(vec![(
+                Category::OrphanPage,
+                WarningPolicy::Ignore,
+            )]),
+            ..Default::default()
+        };
+        assert!(outcome.generate_diagnostics(&files, &cfg).is_empty());
+    }
+
+    #[test]
+    fn unused_references_are_reported_according_to_the_warning_policy() {
+        let mut files = Files::new();
+        let src = "[used]\n\n[used]: ./a.md\n[unused]: ./b.md\n";
+        let id = files.add("chapter.md", src);
+        let got = ValidationOutcome {
+            unused_references: crate::find_unused_references(vec![id], &files),
+            ..Default::default()
+        };
+
+        let diags = got.generate_diagnostics(&files, &Config::default());
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(
+            diags[0].primary_label.message,
+            "Nothing links to `[unused]`"
+        );
+
+        let cfg = policy(WarningPolicy::Ignore);
+
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert!(diags.is_empty());
+    }
+
+    #[test]
+    fn duplicate_anchors_are_reported_according_to_the_warning_policy() {
+        let mut files = Files::new();
+        let src = "# Overview\n\n# Overview\n\n# Overview 1\n";
+        let id = files.add("chapter.md", src);
+        let got = ValidationOutcome {
+            duplicate_anchors: crate::find_duplicate_anchors(vec![id], &files),
+            ..Default::default()
+        };
+
+        let cfg = policy(WarningPolicy::Warn);
+
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(diags[0].secondary_labels.len(), 1);
+
+        let cfg = policy(WarningPolicy::Ignore);
+
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert!(diags.is_empty());
+    }
+
+    #[test]
+    fn per_link_type_policies_apply_to_broken_links() {
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let broken = |url: &str, reason: Reason| InvalidLink {
+            link: Link::parse(url, 0..1, id).unwrap(),
+            reason,
+        };
+        let got = ValidationOutcome {
+            invalid_links: vec![
+                broken("./missing.md", Reason::FileNotFound),
+                broken(
+                    "https://example.com/",
+                    Reason::UnsuccessfulServerResponse(StatusCode::NOT_FOUND),
+                ),
+            ],
+            ..Default::default()
+        };
+
+        // broken links are errors by default
+        let diags = got.generate_diagnostics(&files, &Config::default());
+        assert!(diags.iter().all(|d| d.severity == Severity::Error));
+
+        let cfg = Config {
+            web_warning_policy: Some(WarningPolicy::Warn),
+            local_warning_policy: Some(WarningPolicy::Error),
+            ..Default::default()
+        };
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0].severity, Severity::Error);
+        assert_eq!(diags[1].severity, Severity::Warning);
+
+        let cfg = Config {
+            web_warning_policy: Some(WarningPolicy::Ignore),
+            ..Default::default()
+        };
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn stale_entries_are_revalidated_with_conditional_requests() {
+        let server = MockServer::start(|req| {
+            let etag = req
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("If-None-Match"));
+
+            match etag {
+                Some((_, value)) if value == r#""v1""# => Response::new(304),
+                _ => Response::new(200).with_header("ETag", r#""v1""#),
+            }
+        });
+        let url = server.url("/page");
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let link = Link::parse(&url, 0..1, id).unwrap();
+        // every cached entry is immediately stale
+        let cfg = Config {
+            cache_timeout: 0,
+            ..Default::default()
+        };
+        let clients = Clients::new(&cfg).unwrap();
+        let throttle = Throttle::default();
+        let cache = Cache::default();
+
+        check_link(&link, &clients, &cfg, &cache, &throttle).unwrap();
+        let first = cache.get(&url).unwrap();
+        assert_eq!(first.etag.as_ref().unwrap(), r#""v1""#);
+
+        check_link(&link, &clients, &cfg, &cache, &throttle).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1]
+            .headers
+            .iter()
+            .any(|(name, value)| name.eq_ignore_ascii_case("If-None-Match")
+                && value == r#""v1""#));
+        let second = cache.get(&url).unwrap();
+        assert!(second.successful);
+        assert!(second.unix_timestamp >= first.unix_timestamp);
+        assert_eq!(second.etag, first.etag);
+    }
+
+    #[test]
+    fn failures_are_reused_until_the_negative_cache_timeout() {
+        let server = MockServer::start(|_| Response::new(404));
+        let url = server.url("/broken");
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let link = Link::parse(&url, 0..1, id).unwrap();
+        let cfg = Config {
+            max_retries: 0,
+            ..Default::default()
+        };
+        let clients = Clients::new(&cfg).unwrap();
+        let throttle = Throttle::default();
+        let cache = Cache::default();
+
+        let first = check_link(&link, &clients, &cfg, &cache, &throttle);
+        let second = check_link(&link, &clients, &cfg, &cache, &throttle);
+
+        let requests = server.requests().len();
+        assert!(first.is_err());
+        match second {
+            Err(Reason::CachedFailure(ref msg)) => {
+                assert!(msg.contains("404"), "{}", msg)
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // failures are always checked again when negative caching is off
+        let cfg = Config {
+            negative_cache_timeout: 0,
+            ..cfg
+        };
+        let third = check_link(&link, &clients, &cfg, &cache, &throttle);
+        assert!(server.requests().len() > requests);
+        match third {
+            Err(Reason::UnsuccessfulServerResponse(_)) => {},
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_connect_to_the_allowed_address_family() {
+        // the mock server only listens on 127.0.0.1
+        let server = MockServer::start(|_| Response::new(200));
+        let url = server.url("/page");
+
+        let cfg = Config {
+            dns_resolution: DnsResolution::Ipv4Only,
+            ..Default::default()
+        };
+        check_url(&url, &cfg).unwrap();
+
+        let cfg = Config {
+            dns_resolution: DnsResolution::Ipv6Only,
+            max_retries: 0,
+            ..Default::default()
+        };
+        assert!(check_url(&url, &cfg).is_err());
+    }
+
+    #[test]
+    fn use_the_configured_http_method() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "HEAD" => Response::new(405),
+            _ => Response::new(200),
+        });
+        let cfg = Config {
+            http_method: HashMap::from_iter(vec![(
+                HashedRegex::new("/head-only").unwrap(),
+                HttpMethod::Head,
+            )]),
+            fallback_to_get: false,
+            prefer_head: false,
+            ..Default::default()
+        };
+
+        check_url(&server.url("/page"), &cfg).unwrap();
+        let got = check_url(&server.url("/head-only"), &cfg);
+
+        assert!(got.is_err());
+        let methods: Vec<_> =
+            server.requests().into_iter().map(|req| req.method).collect();
+        assert_eq!(methods, vec!["GET", "HEAD"]);
+    }
+
+    #[test]
+    fn self_signed_certificates_are_only_accepted_when_allowed() {
+        let server = MockServer::start_https(|_| Response::new(200));
+        let url = server.url("/page");
+
+        match check_url(&url, &Config::default()) {
+            Err(Reason::Client(_)) | Err(Reason::RetriesExhausted { .. }) => {},
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        let cfg = Config {
+            insecure_hosts: vec![HashedRegex::new("^example\\.com$").unwrap()],
+            ..Default::default()
+        };
+        assert!(check_url(&url, &cfg).is_err());
+
+        let cfg = Config {
+            insecure_hosts: vec![HashedRegex::new(r"^127\.0\.0\.1$").unwrap()],
+            ..Default::default()
+        };
+        check_url(&url, &cfg).unwrap();
+
+        let cfg = Config {
+            allow_insecure_certificates: true,
+            ..Default::default()
+        };
+        check_url(&url, &cfg).unwrap();
+    }
+
+    #[test]
+    fn trust_an_extra_root_certificate() {
+        let server = MockServer::start_https(|_| Response::new(200));
+        let url = server.url("/page");
+        let cfg = Config {
+            tls_ca_cert: Some(
+                Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join("tests")
+                    .join("self-signed.pem"),
+            ),
+            ..Default::default()
+        };
+
+        check_url(&url, &cfg).unwrap();
+    }
+
+    #[test]
+    fn a_per_pattern_method_overrides_prefer_head() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "HEAD" => Response::new(405),
+            _ => Response::new(200),
+        });
+        let cfg = Config {
+            http_method: HashMap::from_iter(vec![
+                (HashedRegex::new("/page").unwrap(), HttpMethod::Get),
+                (HashedRegex::new("/health").unwrap(), HttpMethod::Post),
+            ]),
+            prefer_head: true,
+            ..Default::default()
+        };
+
+        check_url(&server.url("/page"), &cfg).unwrap();
+        check_url(&server.url("/health"), &cfg).unwrap();
+
+        let methods: Vec<_> =
+            server.requests().into_iter().map(|req| req.method).collect();
+        assert_eq!(methods, vec!["GET", "POST"]);
+    }
+
+    #[test]
+    fn fall_back_to_get_when_head_is_rejected() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "HEAD" => Response::new(405),
+            _ => Response::new(200),
+        });
+        let cfg = Config {
+            http_method: HashMap::from_iter(vec![(
+                HashedRegex::new(".*").unwrap(),
+                HttpMethod::Head,
+            )]),
+            http_headers: HashMap::from_iter(vec![(
+                HashedRegex::new(".*").unwrap(),
+                vec!["X-Token: secret".try_into().unwrap()],
+            )]),
+            ..Default::default()
+        };
+
+        check_url(&server.url("/page"), &cfg).unwrap();
+
+        let requests = server.requests();
+        let methods: Vec<_> =
+            requests.iter().map(|req| req.method.as_str()).collect();
+        assert_eq!(methods, vec!["HEAD", "GET"]);
+        // the GET request is sent with the same headers
+        assert!(requests[1].headers.iter().any(|(name, value)| {
+            name.eq_ignore_ascii_case("X-Token") && value == "secret"
+        }));
+    }
+
+    #[test]
+    fn repeated_headers_are_all_sent() {
+        let server = MockServer::start(|_| Response::new(200));
+        let cfg = Config {
+            http_headers: HashMap::from_iter(vec![(
+                HashedRegex::new(".*").unwrap(),
+                vec![
+                    "X-Custom: first".try_into().unwrap(),
+                    "Cookie: a=1".try_into().unwrap(),
+                    "X-Custom: second".try_into().unwrap(),
+                    "Cookie: b=2".try_into().unwrap(),
+                ],
+            )]),
+            ..Default::default()
+        };
+
+        check_url(&server.url("/page"), &cfg).unwrap();
+
+        let headers = &server.requests()[0].headers;
+        let values = |name: &str| -> Vec<String> {
+            headers
+                .iter()
+                .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+                .collect()
+        };
+        assert_eq!(values("X-Custom"), vec!["first", "second"]);
+        assert_eq!(values("Cookie"), vec!["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn credentials_are_sent_to_matching_sites() {
+        let server = MockServer::start(|_| Response::new(200));
+        let cfg = Config {
+            basic_auth: HashMap::from_iter(vec![(
+                HashedRegex::new("/private").unwrap(),
+                String::from("aladdin:opensesame").try_into().unwrap(),
+            )]),
+            bearer_token: HashMap::from_iter(vec![(
+                HashedRegex::new("/api").unwrap(),
+                String::from("s3cr3t").try_into().unwrap(),
+            )]),
+            ..Default::default()
+        };
+
+        check_url(&server.url("/private"), &cfg).unwrap();
+        check_url(&server.url("/api"), &cfg).unwrap();
+        check_url(&server.url("/public"), &cfg).unwrap();
+
+        let authorization: Vec<_> = server
+            .requests()
+            .iter()
+            .map(|request| {
+                request
+                    .headers
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case("Authorization"))
+                    .map(|(_, value)| value.clone())
+            })
+            .collect();
+        assert_eq!(
+            authorization,
+            vec![
+                Some(String::from("Basic YWxhZGRpbjpvcGVuc2VzYW1l")),
+                Some(String::from("Bearer s3cr3t")),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn prefer_head_requests_and_fall_back_to_get() {
+        let server = MockServer::start(|req| match req.path.as_str() {
+            "/no-head" if req.method == "HEAD" => Response::new(405),
+            _ => Response::new(200),
+        });
+        let cfg = Config::default();
+
+        check_url(&server.url("/page"), &cfg).unwrap();
+        check_url(&server.url("/no-head"), &cfg).unwrap();
+
+        let methods: Vec<_> =
+            server.requests().into_iter().map(|req| req.method).collect();
+        assert_eq!(methods, vec!["HEAD", "HEAD", "GET"]);
+    }
+
+    fn redirect_chain() -> MockServer {
+        // "/hop/N" redirects to "/hop/N+1", and "/hop/3" is the destination
+        MockServer::start(|req| {
+            let hop: usize =
+                req.path.trim_start_matches("/hop/").parse().unwrap();
+
+            if hop < 3 {
+                Response::new(301)
+                    .with_header("Location", &format!("/hop/{}", hop + 1))
+            } else {
+                Response::new(200)
+            }
+        })
+    }
+
+    #[test]
+    fn follow_redirects_up_to_the_limit() {
+        let server = redirect_chain();
+        let cfg = Config {
+            max_redirects: 3,
+            ..Default::default()
+        };
+
+        check_url(&server.url("/hop/0"), &cfg).unwrap();
+    }
+
+    #[test]
+    fn too_many_redirects_is_an_error() {
+        let server = redirect_chain();
+        let cfg = Config {
+            max_redirects: 2,
+            ..Default::default()
+        };
+
+        match check_url(&server.url("/hop/0"), &cfg) {
+            Err(Reason::TooManyRedirects { limit, last_url }) => {
+                assert_eq!(limit, 2);
+                assert!(last_url.ends_with("/hop/2"), "{}", last_url);
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_redirect_is_the_final_status_when_redirects_are_disabled() {
+        let server = redirect_chain();
+        let cfg = Config {
+            max_redirects: 0,
+            ..Default::default()
+        };
+
+        match check_url(&server.url("/hop/0"), &cfg) {
+            Err(Reason::UnsuccessfulServerResponse(status)) => {
+                assert_eq!(status, StatusCode::MOVED_PERMANENTLY)
+            },
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn upgrade_http_links_to_https() {
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let link = |url: &str| Link::parse(url, 0..1, id).unwrap();
+
+        let got = https_equivalent(&link("http://example.com/path?q=1"));
+        assert_eq!(got.unwrap().uri.to_string(), "https://example.com/path?q=1");
+
+        assert!(https_equivalent(&link("https://example.com/")).is_none());
+        assert!(https_equivalent(&link("http://localhost:8080/")).is_none());
+    }
+
+    #[test]
+    fn suggest_upgrading_links_which_also_work_over_https() {
+        // the mock server can't speak HTTPS, so pretend only one host has
+        // an HTTPS version
+        let mut files = Files::new();
+        let id = files.add("chapter.md", "");
+        let links = vec![
+            Link::parse("http://secure.example.com/", 0..1, id).unwrap(),
+            Link::parse("http://insecure.example.com/", 0..1, id).unwrap(),
+            Link::parse("https://already-secure.example.com/", 0..1, id)
+                .unwrap(),
+        ];
+
+        let got = find_insecure_links(&links, |https| {
+            https.uri.host() == Some("secure.example.com")
+        });
+
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].link, links[0]);
+        assert_eq!(
+            got[0].https_url.as_deref(),
+            Some("https://secure.example.com/")
+        );
+
+        let outcome = ValidationOutcome {
+            valid_links: links.clone(),
+            insecure_links: got,
+            ..Default::default()
+        };
+        let diags = outcome.generate_diagnostics(&files, &Config::default());
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+
+        let cfg = policy(WarningPolicy::Error);
+        let diags = outcome.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn diagnostics_carry_a_stable_code() {
+        let src = "[link](./missing.md)";
+        let mut files = Files::new();
+        let id = files.add("chapter.md", src);
+        let outcome = ValidationOutcome {
+            invalid_links: vec![InvalidLink {
+                link: Link::parse("./missing.md", 0..src.len(), id).unwrap(),
+                reason: Reason::FileNotFound,
+            }],
+            ..Default::default()
+        };
+
+        let diags = outcome.generate_diagnostics(&files, &Config::default());
+
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].code.as_deref(), Some("LC0001"));
+        let mut buffer = codespan_reporting::term::termcolor::NoColor::new(
+            Vec::new(),
+        );
+        codespan_reporting::term::emit(
+            &mut buffer,
+            &codespan_reporting::term::Config::default(),
+            &files,
+            &diags[0],
+        )
+        .unwrap();
+        let rendered = String::from_utf8(buffer.into_inner()).unwrap();
+        assert!(
+            rendered.starts_with("error[LC0001]: File not found: ./missing.md"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn group_broken_links_by_url() {
+        let mut files = Files::new();
+        let src = "See [the docs](https://example.com/missing).\n";
+        let chapters: Vec<_> = (1..=3)
+            .map(|i| files.add(format!("chapter_{}.md", i), src))
+            .collect();
+        let start = src.find('[').unwrap();
+        let invalid_links = chapters
+            .iter()
+            .map(|&id| InvalidLink {
+                link: Link::parse(
+                    "https://example.com/missing",
+                    start..src.len() - 2,
+                    id,
+                )
+                .unwrap(),
+                reason: Reason::UnsuccessfulServerResponse(
+                    StatusCode::NOT_FOUND,
+                ),
+            })
+            .collect();
+        let outcome = ValidationOutcome {
+            invalid_links,
+            ..Default::default()
+        };
+
+        // by default, every occurrence gets its own error
+        let diags = outcome.generate_diagnostics(&files, &Config::default());
+        assert_eq!(diags.len(), 3);
+
+        let cfg = Config {
+            group_by_url: true,
+            ..Default::default()
+        };
+        let diags = outcome.generate_diagnostics(&files, &cfg);
+
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Error);
+        assert_eq!(
+            diags[0].message,
+            "The server responded with 404 Not Found for \"https://example.com/missing\" (linked from 3 places)"
+        );
+        let locations: Vec<_> = std::iter::once(&diags[0].primary_label)
+            .chain(&diags[0].secondary_labels)
+            .map(|label| files.name(label.file_id))
+            .collect();
+        assert_eq!(
+            locations,
+            vec!["chapter_1.md", "chapter_2.md", "chapter_3.md"]
+        );
+
+        let mut buffer = codespan_reporting::term::termcolor::NoColor::new(
+            Vec::new(),
+        );
+        codespan_reporting::term::emit(
+            &mut buffer,
+            &codespan_reporting::term::Config::default(),
+            &files,
+            &diags[0],
+        )
+        .unwrap();
+        let rendered = String::from_utf8(buffer.into_inner()).unwrap();
+        assert_eq!(rendered.matches("(linked from 3 places)").count(), 1);
+        for chapter in &["chapter_1.md", "chapter_2.md", "chapter_3.md"] {
+            assert!(rendered.contains(chapter), "{}", rendered);
+        }
+    }
+
+    #[test]
+    fn malformed_urls_are_caught_offline() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let src = "[a](https://example.com/)\n[b](htps://example.com/)\n[c](https:///path)\n[d](htps://internal.example.com/)";
+        let mut files = Files::new();
+        let id = files.add("chapter.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let cfg = Config {
+            offline: true,
+            check_url_syntax: true,
+            exclude: vec![HashedRegex::new("internal").unwrap()],
+            ..Default::default()
+        };
+
+        let got =
+            validate(&links, &cfg, &root, &Cache::default(), &files, Vec::new())
+                .unwrap();
+
+        // the well-formed link is skipped like any other web link
+        assert_eq!(got.skipped_offline, vec![links[0].clone()]);
+        let broken: Vec<_> =
+            got.invalid_links.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            broken,
+            vec![
+                "\"htps://example.com/\" isn't a valid URL: Unknown scheme \"htps\", did you mean \"https\"?",
+                "\"https:///path\" isn't a valid URL: The URL has no host",
+            ]
+        );
+        assert!(got.invalid_links.iter().all(|invalid| {
+            invalid.severity(&cfg) == Some(Severity::Error)
+                && invalid.reason.category() == Category::MalformedUrl
+        }));
+        // excluded links are left alone
+        assert_eq!(got.unknown_schema, vec![links[3].clone()]);
+    }
+
+    #[test]
+    fn flag_every_plain_http_link() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("all-green")
+            .join("src");
+        let src = "[a](http://example.com/)\n[b](https://example.com/)\n[c](http://internal.example.com/)\n[d](http://legacy.example.com/)\n[e](http://localhost:8000/)";
+        let mut files = Files::new();
+        let id = files.add("chapter.md", src);
+        let (links, _) = crate::links::extract(vec![id], &files);
+        let mut exclude_in_files = HashMap::new();
+        exclude_in_files.insert(
+            HashedRegex::new(r"chapter\.md").unwrap(),
+            vec![HashedRegex::new("legacy").unwrap()],
+        );
+        let cfg = Config {
+            warn_on_insecure_http: true,
+            exclude: vec![HashedRegex::new("internal").unwrap()],
+            exclude_in_files,
+            skip_loopback: true,
+            ..Default::default()
+        };
+
+        let got =
+            validate(&links, &cfg, &root, &Cache::default(), &files, Vec::new())
+                .unwrap();
+
+        // web links aren't followed, so nothing was probed
+        assert_eq!(got.insecure_links.len(), 1);
+        assert_eq!(got.insecure_links[0].link, links[0]);
+        assert!(got.insecure_links[0].https_url.is_none());
+        let diags = got.generate_diagnostics(&files, &cfg);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Warning);
+        assert_eq!(diags[0].message, "\"http://example.com/\" uses plain HTTP");
+
+        // it's opt-in
+        let got = validate(
+            &links,
+            &Config::default(),
+            &root,
+            &Cache::default(),
+            &files,
+            Vec::new(),
+        )
+        .unwrap();
+        assert!(got.insecure_links.is_empty());
     }
 }
@@ -2,14 +2,20 @@
 extern crate pretty_assertions;
 
 use codespan::Files;
+use codespan_reporting::term::termcolor::ColorChoice;
 use failure::Error;
-use mdbook::{renderer::RenderContext, MDBook};
-use mdbook_linkcheck::{HashedRegex, Cache, Config, ValidationOutcome};
+use mdbook::{
+    renderer::{RenderContext, Renderer},
+    MDBook,
+};
+use mdbook_linkcheck::{
+    Cache, Config, ExitCode, IncludeMap, Link, ValidationOutcome, WarningPolicy,
+};
 use std::{
+    cell::RefCell,
     path::{Path, PathBuf},
-    convert::TryInto,
-    collections::HashMap,
-    iter::FromIterator,
+    process::Command,
+    rc::Rc,
 };
 
 fn test_dir() -> PathBuf { Path::new(env!("CARGO_MANIFEST_DIR")).join("tests") }
@@ -69,6 +75,301 @@ fn correctly_find_broken_links() {
     assert_eq!(output.incomplete_links[0].text, "incomplete link");
 }
 
+#[test]
+fn reference_links_and_images_are_checked() {
+    let root = test_dir().join("references");
+    let expected_valid = &[
+        "./chapter_1.md",
+        "./chapter_1.md",
+        "./chapter_1.md",
+        "./images/pixel.svg",
+    ];
+    let expected_broken =
+        &["./missing.md", "./images/missing.png", "./images/nope.png"];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> = output
+        .valid_links
+        .iter()
+        .map(|link| link.uri.to_string())
+        .collect();
+    assert_same_links(valid, expected_valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| invalid.link.uri.to_string())
+        .collect();
+    assert_same_links(broken, expected_broken);
+    assert!(output.incomplete_links.is_empty());
+
+    let md = MDBook::load(&root).unwrap();
+    let mut files = Files::new();
+    let chapters =
+        mdbook_linkcheck::load_files_into_memory(&md.book, &mut files);
+    let unused: Vec<_> =
+        mdbook_linkcheck::find_unused_references(chapters, &files)
+            .into_iter()
+            .map(|unused| unused.label)
+            .collect();
+    assert_eq!(unused, vec!["nobody uses this"]);
+}
+
+#[test]
+fn fragments_are_checked_against_the_headings() {
+    let root = test_dir().join("anchors");
+    let expected_valid =
+        &["#examples", "#examples-1", "./chapter_1.md#examples-1"];
+    let expected_broken = &["#examples-2", "./chapter_1.md#installation"];
+    let with_fragment = |link: &Link| {
+        format!("{}#{}", link.uri, link.fragment.as_deref().unwrap_or(""))
+    };
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> = output.valid_links.iter().map(with_fragment).collect();
+    assert_same_links(valid, expected_valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| with_fragment(&invalid.link))
+        .collect();
+    assert_same_links(broken, expected_broken);
+}
+
+#[test]
+fn links_in_inline_html_are_checked() {
+    let root = test_dir().join("inline-html");
+    let expected_valid = &["./chapter_2.html", "images/pixel.svg"];
+    let expected_broken = &["./nowhere.html"];
+
+    let output = run_link_checker(&root).unwrap();
+
+    let valid: Vec<_> = output
+        .valid_links
+        .iter()
+        .map(|link| link.uri.to_string())
+        .collect();
+    assert_same_links(valid, expected_valid);
+    let broken: Vec<_> = output
+        .invalid_links
+        .iter()
+        .map(|invalid| invalid.link.uri.to_string())
+        .collect();
+    assert_same_links(broken, expected_broken);
+    assert!(output.unknown_schema.is_empty());
+}
+
+#[test]
+fn the_cache_can_be_disabled() {
+    let root = test_dir().join("all-green");
+    let cache_file = std::env::temp_dir()
+        .join(concat!(env!("CARGO_PKG_NAME"), "-disabled-cache"))
+        .join("cache.json");
+    let _ = std::fs::remove_file(&cache_file);
+
+    let cfg = Config::builder().use_cache(false).build().unwrap();
+    run_the_whole_pipeline(&root, &cfg, &cache_file).unwrap();
+    assert!(!cache_file.exists());
+
+    // sanity check, the same run normally creates the cache file
+    run_the_whole_pipeline(&root, &Config::default(), &cache_file).unwrap();
+    assert!(cache_file.exists());
+    std::fs::remove_file(&cache_file).unwrap();
+}
+
+#[test]
+fn disabling_the_cache_leaves_an_existing_cache_alone() {
+    let root = test_dir().join("all-green");
+    let dir = std::env::temp_dir()
+        .join(concat!(env!("CARGO_PKG_NAME"), "-untouched-cache"));
+    std::fs::create_dir_all(&dir).unwrap();
+    let cache_file = dir.join("cache.json");
+    // a stale entry which would normally be re-checked and overwritten
+    let original = r#"{"version":2,"links":{"https://example.com/":{"unix_timestamp":0,"successful":false}}}"#;
+    std::fs::write(&cache_file, original).unwrap();
+
+    let cfg = Config::builder()
+        .use_cache(false)
+        .cache_timeout(0)
+        .build()
+        .unwrap();
+    run_the_whole_pipeline(&root, &cfg, &cache_file).unwrap();
+
+    let got = std::fs::read_to_string(&cache_file).unwrap();
+    assert_eq!(got, original);
+    std::fs::remove_file(&cache_file).unwrap();
+}
+
+#[test]
+fn check_a_single_chapter() {
+    let src_dir = test_dir().join("all-green").join("src");
+    let markdown = "# Notes\n\n[fine](./chapter_1.md)\n[broken](./missing.md)\n[web](https://example.com/)\n";
+    let mut files = Files::new();
+
+    let diags = mdbook_linkcheck::check_markdown(
+        markdown,
+        Path::new("notes.md"),
+        &src_dir,
+        &Config::default(),
+        &mut files,
+    )
+    .unwrap();
+
+    // the web link isn't checked because follow-web-links is off
+    assert_eq!(diags.len(), 1);
+    let label = &diags[0].primary_label;
+    assert_eq!(files.name(label.file_id), "notes.md");
+    assert_eq!(
+        files.source_slice(label.file_id, label.span).unwrap(),
+        "[broken](./missing.md)"
+    );
+}
+
+#[test]
+fn links_in_included_files_are_reported_where_they_were_written() {
+    let root = test_dir().join("includes");
+    let ctx = preprocessed(&root);
+    let cfg = Config::default();
+    let src = dunce::canonicalize(ctx.source_dir()).unwrap();
+    let mut files = Files::new();
+    let chapters =
+        mdbook_linkcheck::load_files_into_memory(&ctx.book, &mut files);
+    let includes = IncludeMap::new(&chapters, &mut files, &ctx.source_dir());
+    let (links, incomplete) =
+        mdbook_linkcheck::extract_links(chapters.clone(), &files);
+
+    let mut outcome = mdbook_linkcheck::validate(
+        &links, &cfg, &src, &Cache::default(), &files, incomplete,
+    )
+    .unwrap();
+    includes.remap(&mut outcome);
+
+    // chapter 1 includes the whole snippet, and chapter 2 only the broken link
+    assert_eq!(outcome.invalid_links.len(), 2);
+    for invalid in &outcome.invalid_links {
+        let link = &invalid.link;
+        let location = files.location(link.file, link.span.start()).unwrap();
+        assert_eq!(
+            Path::new(files.name(link.file)),
+            Path::new("snippets").join("links.md")
+        );
+        assert_eq!(location.line.to_usize(), 2);
+        assert_eq!(
+            files.source_slice(link.file, link.span).unwrap(),
+            "[broken](./missing.md)"
+        );
+    }
+    // links written in the chapter itself are left alone
+    assert_eq!(files.name(outcome.valid_links[0].file), "chapter_1.md");
+}
+
+#[test]
+fn exit_codes_for_a_book_with_only_warnings() {
+    let root = test_dir().join("warnings-only");
+    let inputs = vec![
+        (WarningPolicy::Ignore, ExitCode::Success),
+        (WarningPolicy::Warn, ExitCode::Success),
+        (WarningPolicy::Error, ExitCode::WarningsAsErrors),
+    ];
+
+    for (policy, should_be) in inputs {
+        let cfg = Config::builder()
+            .warning_policy(policy)
+            .use_cache(false)
+            .build()
+            .unwrap();
+        let got = run_the_whole_pipeline(&root, &cfg, Path::new("")).unwrap();
+        assert_eq!(got, should_be, "{:?}", policy);
+
+        let policy = serde_json::to_string(&policy).unwrap();
+        let status = linkcheck_binary(&root)
+            .env("MDBOOK_OUTPUT__LINKCHECK__WARNING_POLICY", &policy)
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(should_be.code()), "{}", policy);
+    }
+}
+
+#[test]
+fn exit_codes_for_broken_links_and_bad_config() {
+    let status = linkcheck_binary(&test_dir().join("broken-links"))
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(ExitCode::Errors.code()));
+
+    let status = linkcheck_binary(&test_dir().join("warnings-only"))
+        .env("MDBOOK_OUTPUT__LINKCHECK__WARNING_POLICY", "sometimes")
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(ExitCode::InvalidConfig.code()));
+}
+
+#[test]
+fn listing_links_shows_how_they_would_be_checked() {
+    let output = linkcheck_binary(&test_dir().join("references"))
+        .arg("--list-links")
+        .output()
+        .unwrap();
+
+    // the book has broken links, but they aren't checked
+    assert_eq!(output.status.code(), Some(ExitCode::Success.code()));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains("  file     local ./images/missing.png\n"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.ends_with(
+            "Would fetch 0 web links and check 6 local links, skipping 0\n"
+        ),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn dry_run_is_an_alias_for_list_links() {
+    let root = test_dir().join("references");
+    let listed = linkcheck_binary(&root).arg("--list-links").output().unwrap();
+
+    let output = linkcheck_binary(&root).arg("--dry-run").output().unwrap();
+
+    assert_eq!(output.status.code(), Some(ExitCode::Success.code()));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(listed.stdout).unwrap()
+    );
+}
+
+#[test]
+fn quiet_hides_the_summary() {
+    let root = test_dir().join("warnings-only");
+    // the missing heading and the incomplete link are both warnings
+    let summary = "Checked 1 links across 1 files: 0 ok, 2 warnings, 0 errors";
+
+    let output = linkcheck_binary(&root).output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains(summary), "{}", stderr);
+
+    let output = linkcheck_binary(&root)
+        .env("MDBOOK_OUTPUT__LINKCHECK__PRINT_SUMMARY", "true")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains(summary), "{}", stderr);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mdbook-linkcheck"))
+        .args(["--standalone", "--no-cache", "--offline", "--colour=never"])
+        .arg(&root)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(summary), "{}", stderr);
+}
+
 fn assert_same_links<L, R, P, Q>(left: L, right: R)
 where
     L: IntoIterator<Item = P>,
@@ -94,20 +395,16 @@ fn run_link_checker(root: &Path) -> Result<ValidationOutcome, Error> {
     env_logger::try_init().ok();
 
     let mut md = MDBook::load(root).unwrap();
-    let cfg = Config {
-        follow_web_links: true,
-        traverse_parent_directories: false,
-        exclude: vec![r"forbidden\.com".parse().unwrap()],
-        http_headers: HashMap::from_iter(vec![
-            (
-                HashedRegex::new(r"crates\.io").unwrap(),
-                vec![
-                    "Accept: text/html".try_into().unwrap()
-                ]
-            )
-        ]),
-        ..Default::default()
-    };
+    let cfg = Config::builder()
+        .follow_web_links(true)
+        .traverse_parent_directories(false)
+        // the older books link to headings like "#Subheading", which mdbook
+        // renders with a lowercase id
+        .case_insensitive_fragments(true)
+        .exclude(r"forbidden\.com")
+        .add_http_header(r"crates\.io", "Accept: text/html")
+        .build()
+        .unwrap();
     md.config.set("output.linkcheck", &cfg).unwrap();
 
     let ctx = RenderContext::new(root, md.book, md.config, root.to_path_buf());
@@ -122,3 +419,60 @@ fn run_link_checker(root: &Path) -> Result<ValidationOutcome, Error> {
     let cache = Cache::default();
     mdbook_linkcheck::validate(&links, &cfg, &src, &cache, &files, incomplete)
 }
+
+/// Run the book's preprocessors (e.g. to expand `{{#include}}`s) and get the
+/// [`RenderContext`] a backend would be given.
+fn preprocessed(root: &Path) -> RenderContext {
+    struct Capture(Rc<RefCell<Option<RenderContext>>>);
+
+    impl Renderer for Capture {
+        fn name(&self) -> &str { "capture" }
+
+        fn render(&self, ctx: &RenderContext) -> mdbook::errors::Result<()> {
+            let ctx = RenderContext::new(
+                ctx.root.clone(),
+                ctx.book.clone(),
+                ctx.config.clone(),
+                ctx.destination.clone(),
+            );
+            *self.0.borrow_mut() = Some(ctx);
+            Ok(())
+        }
+    }
+
+    let captured = Rc::new(RefCell::new(None));
+    let mut md = MDBook::load(root).unwrap();
+    // don't leave a rendered copy of the book lying around
+    md.config.build.build_dir = std::env::temp_dir()
+        .join(concat!(env!("CARGO_PKG_NAME"), "-preprocessed"));
+    md.with_renderer(Capture(Rc::clone(&captured)));
+    md.build().unwrap();
+
+    let ctx = captured.borrow_mut().take();
+    ctx.expect("The renderer was never run")
+}
+
+/// Run `mdbook-linkcheck` in standalone mode, without touching the network or
+/// leaving a cache behind.
+fn linkcheck_binary(root: &Path) -> Command {
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_mdbook-linkcheck"));
+    cmd.arg("--standalone")
+        .arg("--no-cache")
+        .arg("--offline")
+        .arg("--quiet")
+        .arg(root)
+        .env_remove("RUST_LOG");
+    cmd
+}
+
+fn run_the_whole_pipeline(
+    root: &Path,
+    cfg: &Config,
+    cache_file: &Path,
+) -> Result<ExitCode, Error> {
+    let mut md = MDBook::load(root).unwrap();
+    md.config.set("output.linkcheck", cfg).unwrap();
+    let ctx = RenderContext::new(root, md.book, md.config, root.to_path_buf());
+
+    mdbook_linkcheck::run(cache_file, ColorChoice::Never, &ctx)
+}